@@ -1,16 +1,200 @@
-use std::{
-    ops::Deref,
-    sync::{Arc, RwLock},
-};
+use std::{path::PathBuf, str::FromStr, time::Duration};
 
 use serde::{Deserialize, Serialize};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime, UtcOffset};
+
+use crate::types::{
+    decode::{Decoder, LogFormat},
+    level::{LevelDefinition, LevelTable},
+};
 
 #[derive(Default, Serialize, Deserialize, Debug, Clone)]
 pub struct Settings {
     pub rabbit_mq: RabbitMQSettings,
+    /// Extra levels, aliases and colors on top of the built-in ones
+    #[serde(default)]
+    pub levels: Vec<LevelDefinition>,
+    #[serde(default)]
+    pub storage: StorageSettings,
+    #[serde(default)]
+    pub tail: TailSettings,
+    #[serde(default)]
+    pub http: HttpSettings,
+    #[serde(default)]
+    pub syslog: SyslogSettings,
+    /// How messages of all sources are decoded
+    #[serde(default)]
+    pub decode: DecodeSettings,
+    /// Read lines piped into the process, set from the command line
+    #[serde(skip)]
+    pub stdin: bool,
+    /// Offset of local time, only readable before threads are started
+    #[serde(skip)]
+    pub local_offset: Option<UtcOffset>,
+}
+
+/// How messages are turned into entries, whichever source they come from
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DecodeSettings {
+    /// Layout of the log messages written by producers
+    pub format: LogFormat,
+    /// Extra timestamp formats, like `[year]-[month]-[day] [hour]:[minute]:[second]`
+    pub time_formats: Vec<String>,
+    /// What happens to messages that fail to decode. Only a broker can
+    /// dead-letter them, other sources keep them as raw entries.
+    pub on_decode_error: DecodeErrorAction,
+}
+
+/// Local files followed like `tail -F`
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TailSettings {
+    /// Paths or glob patterns
+    pub paths: Vec<String>,
+    /// Read files found on startup from the beginning instead of only
+    /// following what's appended. Files followed before continue where they
+    /// were left either way.
+    pub from_start: bool,
+}
+
+/// Local endpoint taking `POST /logs` from services that can't speak AMQP
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpSettings {
+    pub enabled: bool,
+    /// Address and port to listen on, like `127.0.0.1:9880`
+    pub bind: String,
+    /// Requests must carry `Authorization: Bearer <token>` if set
+    pub token: Option<String>,
+    /// Bigger request bodies are refused
+    pub max_body_mb: u64,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        HttpSettings {
+            enabled: false,
+            bind: "127.0.0.1:9880".to_string(),
+            token: None,
+            max_body_mb: 10,
+        }
+    }
+}
+
+/// Syslog receiver, RFC 5424 and RFC 3164 messages
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyslogSettings {
+    pub enabled: bool,
+    /// Address and port both protocols listen on, like `0.0.0.0:514`
+    pub bind: String,
+    pub udp: bool,
+    pub tcp: bool,
+}
+
+impl Default for SyslogSettings {
+    fn default() -> Self {
+        SyslogSettings {
+            enabled: false,
+            // 514 takes privileges
+            bind: "127.0.0.1:5514".to_string(),
+            udp: true,
+            tcp: true,
+        }
+    }
+}
+
+/// Where and how entries are stored. The backend applies to new sessions,
+/// the others keep the one they were recorded with.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StorageSettings {
+    pub backend: StorageBackend,
+    /// How often written entries are flushed to the disk
+    pub sync_interval_ms: u64,
+    /// A new segment file is started once the current one grows that big
+    pub segment_size_mb: u64,
+    pub retention: RetentionSettings,
+    /// Start a new session on every launch instead of continuing the last one
+    pub new_session_on_launch: bool,
+}
+
+/// Limits on what is kept. The oldest entries are evicted first, on startup
+/// and periodically while running. Unset limits don't apply. They cover
+/// the recorded session and past ones together, which are removed whole.
+/// Imported sessions are left alone.
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetentionSettings {
+    pub max_entries: Option<usize>,
+    /// Written like `7d` or `12h 30m`
+    #[serde(with = "duration_option")]
+    pub max_age: Option<Duration>,
+    /// Size of the files on disk. Segments are evicted whole, so the
+    /// NDJSON backend may stay over the limit by up to a segment. SQLite
+    /// may take a few maintenance passes to get under it.
+    pub max_size_mb: Option<u64>,
+    /// Append evicted entries to gzipped NDJSON files in the archive
+    /// directory instead of deleting them
+    pub archive: bool,
+}
+
+impl RetentionSettings {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_entries.is_none() && self.max_age.is_none() && self.max_size_mb.is_none()
+    }
+}
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// Append-only NDJSON files, all entries are kept in memory
+    #[default]
+    Segments,
+    /// SQLite database queried as needed, for millions of entries.
+    /// Entries stored the other way are imported into a new database.
+    Sqlite,
+}
+
+impl StorageBackend {
+    pub const ALL: [StorageBackend; 2] = [StorageBackend::Segments, StorageBackend::Sqlite];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StorageBackend::Segments => "NDJSON segments",
+            StorageBackend::Sqlite => "SQLite",
+        }
+    }
+}
+
+impl Default for StorageSettings {
+    fn default() -> Self {
+        StorageSettings {
+            backend: StorageBackend::Segments,
+            sync_interval_ms: 1000,
+            segment_size_mb: 16,
+            retention: RetentionSettings::default(),
+            new_session_on_launch: false,
+        }
+    }
 }
 
 impl Settings {
+    pub fn level_table(&self) -> Result<LevelTable, String> {
+        LevelTable::new(&self.levels)
+    }
+
+    pub fn decoder(&self) -> Result<Decoder, String> {
+        Decoder::new(
+            self.decode.format,
+            &self.decode.time_formats,
+            self.level_table()?,
+            self.local_offset,
+        )
+        .map_err(|e| e.to_string())
+    }
+
     pub fn load_configuration() -> Settings {
         let config_file = config_file();
 
@@ -18,11 +202,11 @@ impl Settings {
             .add_source(config::File::new(&config_file, config::FileFormat::Yaml))
             .build()
             .and_then(|b| b.try_deserialize());
-        if config.is_ok() {
-            config.unwrap()
-        } else {
-            Settings::default()
-        }
+        let mut settings: Settings = config.unwrap_or_default();
+        settings
+            .rabbit_mq
+            .move_decode_settings(&mut settings.decode);
+        settings
     }
 
     pub fn write_configuration(&self) -> std::io::Result<()> {
@@ -43,42 +227,341 @@ fn config_file() -> String {
 
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
 pub struct RabbitMQSettings {
+    /// Off without a config file. Configs written before it could be turned
+    /// off keep consuming.
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
     pub host: String,
     pub vhost: String,
     pub port: u16,
     pub username: String,
     pub password: String,
+    #[serde(default)]
+    pub topology: TopologySettings,
+    #[serde(default)]
+    pub stream: StreamSettings,
+    /// Unacknowledged deliveries the broker may push to us at once
+    #[serde(default = "default_prefetch")]
+    pub prefetch: u16,
+    #[serde(default)]
+    pub reconnect: ReconnectSettings,
+    #[serde(default)]
+    pub tls: TlsSettings,
+    /// Decode settings of configs from before they applied to all sources,
+    /// moved to [`Settings::decode`] on load
+    #[serde(default, skip_serializing)]
+    pub(crate) format: Option<LogFormat>,
+    #[serde(default, skip_serializing)]
+    pub(crate) time_formats: Option<Vec<String>>,
+    #[serde(default, skip_serializing)]
+    pub(crate) on_decode_error: Option<DecodeErrorAction>,
+}
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DecodeErrorAction {
+    /// Ack and keep as a raw entry
+    #[default]
+    Keep,
+    /// Reject without requeue, so the broker routes it to the queue's
+    /// dead-letter exchange (or drops it if there is none). Bad lines of
+    /// an otherwise fine batch are still kept, as the message gets acked.
+    DeadLetter,
+}
+
+impl DecodeErrorAction {
+    pub const ALL: [DecodeErrorAction; 2] =
+        [DecodeErrorAction::Keep, DecodeErrorAction::DeadLetter];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DecodeErrorAction::Keep => "keep as raw entry",
+            DecodeErrorAction::DeadLetter => "dead-letter",
+        }
+    }
+}
+
+fn default_prefetch() -> u16 {
+    100
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// Connect over amqps. System root certificates are trusted in addition
+/// to `ca_file`.
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TlsSettings {
+    pub enabled: bool,
+    /// PEM bundle with extra certificate authorities to trust
+    pub ca_file: Option<PathBuf>,
+    /// PEM client certificate chain, requires `client_key`
+    pub client_cert: Option<PathBuf>,
+    /// PEM private key for `client_cert`
+    pub client_key: Option<PathBuf>,
+    /// Accept any broker certificate. Only meant for development!
+    pub skip_verify: bool,
+}
+
+/// How the worker retries after losing (or failing to get) a connection.
+/// Delays grow exponentially from `initial_delay_ms` up to `max_delay_ms`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReconnectSettings {
+    pub initial_delay_ms: u64,
+    pub max_delay_ms: u64,
+    pub multiplier: f64,
+    /// Random spread applied to every delay, `0.2` means ±20%
+    pub jitter: f64,
+    /// Give up after that many failed attempts in a row. Retry forever if unset.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectSettings {
+    fn default() -> Self {
+        ReconnectSettings {
+            initial_delay_ms: 500,
+            max_delay_ms: 30_000,
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_attempts: None,
+        }
+    }
+}
+
+/// Describes where logs are consumed from and how the broker side should look.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TopologySettings {
+    pub mode: ConsumeMode,
+    /// Queue to consume in [`ConsumeMode::Shared`]. Ignored when tapping.
+    pub queue: String,
+    /// Declare the queue (durable) on connect instead of expecting it to exist
+    pub declare_queue: bool,
+    /// Exchange the queue gets bound to. `None` means consume the queue as is.
+    pub exchange: Option<ExchangeSettings>,
+    /// Routing keys to bind the queue with. For headers exchange every key is
+    /// a comma separated list of `header=value` pairs which all have to match.
+    pub binding_keys: Vec<String>,
+}
+
+impl Default for TopologySettings {
+    fn default() -> Self {
+        TopologySettings {
+            mode: ConsumeMode::Shared,
+            queue: "log".to_string(),
+            declare_queue: false,
+            exchange: None,
+            binding_keys: Vec::new(),
+        }
+    }
 }
 
 impl RabbitMQSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        self.topology.validate()?;
+        if self.stream.enabled {
+            if self.topology.mode != ConsumeMode::Shared {
+                return Err("Streams can only be consumed in shared queue mode".into());
+            }
+            if self.prefetch == 0 {
+                return Err("Streams require a non-zero prefetch".into());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TopologySettings {
+    pub fn validate(&self) -> Result<(), String> {
+        match self.mode {
+            ConsumeMode::Shared if self.queue.is_empty() => Err("Queue name is empty".into()),
+            ConsumeMode::Tap if self.exchange.is_none() => {
+                Err("Tap mode requires an exchange to bind to".into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConsumeMode {
+    /// Consume the configured queue, competing with other consumers of it
+    #[default]
+    Shared,
+    /// Consume a private exclusive auto-delete queue bound to the exchange,
+    /// so every running instance gets a full copy of the stream
+    Tap,
+}
+
+/// The queue is a RabbitMQ stream, which is read non-destructively
+/// starting from `offset`.
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StreamSettings {
+    pub enabled: bool,
+    pub offset: StreamOffset,
+}
+
+/// Where to start reading a stream from, passed as `x-stream-offset`.
+///
+/// Written in the config as `first`, `last`, `next`, a numeric offset,
+/// an RFC3339 timestamp or a duration like `1h 30m` meaning "that long ago".
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum StreamOffset {
+    First,
+    Last,
+    #[default]
+    Next,
+    Offset(u64),
+    Timestamp(OffsetDateTime),
+    Ago(Duration),
+}
+
+impl StreamOffset {
+    /// Relative offsets are resolved against current time
+    pub fn resolve(&self) -> StreamOffset {
+        match self {
+            StreamOffset::Ago(d) => StreamOffset::Timestamp(OffsetDateTime::now_utc() - *d),
+            other => other.clone(),
+        }
+    }
+}
+
+impl FromStr for StreamOffset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match s {
+            "first" => return Ok(StreamOffset::First),
+            "last" => return Ok(StreamOffset::Last),
+            "next" => return Ok(StreamOffset::Next),
+            _ => {}
+        }
+        if let Ok(offset) = s.parse() {
+            return Ok(StreamOffset::Offset(offset));
+        }
+        if let Ok(ts) = OffsetDateTime::parse(s, &Rfc3339) {
+            return Ok(StreamOffset::Timestamp(ts));
+        }
+        if let Ok(d) = humantime::parse_duration(s) {
+            return Ok(StreamOffset::Ago(d));
+        }
+        Err(format!(
+            "Invalid stream offset \"{s}\": expected first, last, next, \
+             a number, an RFC3339 timestamp or a duration"
+        ))
+    }
+}
+
+impl TryFrom<String> for StreamOffset {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<StreamOffset> for String {
+    fn from(o: StreamOffset) -> Self {
+        o.to_string()
+    }
+}
+
+impl std::fmt::Display for StreamOffset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StreamOffset::First => f.write_str("first"),
+            StreamOffset::Last => f.write_str("last"),
+            StreamOffset::Next => f.write_str("next"),
+            StreamOffset::Offset(o) => write!(f, "{o}"),
+            StreamOffset::Timestamp(ts) => {
+                f.write_str(&ts.format(&Rfc3339).map_err(|_| std::fmt::Error)?)
+            }
+            StreamOffset::Ago(d) => write!(f, "{}", humantime::format_duration(*d)),
+        }
+    }
+}
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct ExchangeSettings {
+    pub name: String,
+    pub kind: ExchangeKind,
+    #[serde(default)]
+    pub declare: bool,
+}
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExchangeKind {
+    #[default]
+    Topic,
+    Fanout,
+    Headers,
+    Direct,
+}
+
+impl ExchangeKind {
+    pub const ALL: [ExchangeKind; 4] = [
+        ExchangeKind::Topic,
+        ExchangeKind::Fanout,
+        ExchangeKind::Headers,
+        ExchangeKind::Direct,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExchangeKind::Topic => "topic",
+            ExchangeKind::Fanout => "fanout",
+            ExchangeKind::Headers => "headers",
+            ExchangeKind::Direct => "direct",
+        }
+    }
+}
+
+impl RabbitMQSettings {
+    fn move_decode_settings(&mut self, decode: &mut DecodeSettings) {
+        if let Some(format) = self.format.take() {
+            decode.format = format;
+        }
+        if let Some(time_formats) = self.time_formats.take() {
+            decode.time_formats = time_formats;
+        }
+        if let Some(action) = self.on_decode_error.take() {
+            decode.on_decode_error = action;
+        }
+    }
+
     pub fn connection_string(&self) -> String {
+        let scheme = if self.tls.enabled { "amqps" } else { "amqp" };
         format!(
-            "amqp://{}:{}@{}:{}/{}",
-            self.username, self.password, self.host, self.port, self.vhost
+            "{}://{}:{}@{}:{}/{}",
+            scheme, self.username, self.password, self.host, self.port, self.vhost
         )
-        .into()
     }
 }
 
-mod arc_rwlock_serde {
-    use serde::de::Deserializer;
-    use serde::ser::Serializer;
-    use serde::{Deserialize, Serialize};
-    use std::sync::{Arc, RwLock};
+/// Optional duration written the [`humantime`] way
+mod duration_option {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
 
-    pub fn serialize<S, T>(val: &Arc<RwLock<T>>, s: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-        T: Serialize,
-    {
-        T::serialize(&*val.read().unwrap(), s)
+    pub fn serialize<S: Serializer>(val: &Option<Duration>, s: S) -> Result<S::Ok, S::Error> {
+        match val {
+            Some(d) => s.serialize_some(&humantime::format_duration(*d).to_string()),
+            None => s.serialize_none(),
+        }
     }
 
-    pub fn deserialize<'de, D, T>(d: D) -> Result<Arc<RwLock<T>>, D::Error>
-    where
-        D: Deserializer<'de>,
-        T: Deserialize<'de>,
-    {
-        Ok(Arc::new(RwLock::new(T::deserialize(d)?)))
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Duration>, D::Error> {
+        Option::<String>::deserialize(d)?
+            .map(|s| humantime::parse_duration(&s).map_err(serde::de::Error::custom))
+            .transpose()
     }
 }
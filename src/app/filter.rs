@@ -0,0 +1,89 @@
+//! Filter typed above the table. Terms are separated by whitespace and all
+//! of them have to match:
+//!
+//! - `key=value` the value under `key` equals `value`
+//! - `key~value` the value under `key` contains `value`
+//! - anything else is looked up in the message
+//!
+//! Keys are `level`, `message`, `target`, `file`, `source_id`,
+//! `origin.<property>` (e.g. `origin.routing_key`, `origin.headers.service`)
+//! and `fields.<name>` or just `<name>` for fields. Comparison ignores case.
+
+use std::borrow::Cow;
+
+use crate::types::json_log::LogEntry;
+
+#[derive(Default)]
+pub struct Filter {
+    terms: Vec<Term>,
+}
+
+pub enum Term {
+    Equals { key: String, value: String },
+    Contains { key: String, value: String },
+    Text(String),
+}
+
+impl Filter {
+    pub fn parse(s: &str) -> Filter {
+        let terms = s
+            .split_whitespace()
+            .map(|term| {
+                let split = term.find(['=', '~']).filter(|&idx| idx > 0);
+                match split {
+                    Some(idx) => {
+                        let key = term[..idx].to_string();
+                        let value = term[idx + 1..].to_lowercase();
+                        if term[idx..].starts_with('=') {
+                            Term::Equals { key, value }
+                        } else {
+                            Term::Contains { key, value }
+                        }
+                    }
+                    None => Term::Text(term.to_lowercase()),
+                }
+            })
+            .collect();
+        Filter { terms }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    pub fn terms(&self) -> &[Term] {
+        &self.terms
+    }
+
+    pub fn matches(&self, entry: &LogEntry) -> bool {
+        self.terms.iter().all(|term| match term {
+            Term::Equals { key, value } => {
+                lookup(entry, key).is_some_and(|v| v.to_lowercase() == *value)
+            }
+            Term::Contains { key, value } => {
+                lookup(entry, key).is_some_and(|v| v.to_lowercase().contains(value.as_str()))
+            }
+            Term::Text(text) => entry.message.to_lowercase().contains(text.as_str()),
+        })
+    }
+}
+
+fn lookup<'a>(entry: &'a LogEntry, key: &str) -> Option<Cow<'a, str>> {
+    if let Some(key) = key.strip_prefix("origin.") {
+        return entry.origin.as_ref()?.get(key).map(Cow::Owned);
+    }
+    match key {
+        "level" => Some(Cow::Borrowed(&entry.level.name)),
+        "message" => Some(Cow::Borrowed(&entry.message)),
+        "target" => entry.source.target.as_deref().map(Cow::Borrowed),
+        "file" => entry.source.file.as_deref().map(Cow::Borrowed),
+        "source_id" => entry.source_id.as_deref().map(Cow::Borrowed),
+        _ => {
+            let name = key.strip_prefix("fields.").unwrap_or(key);
+            entry.fields.get(name).map(|v| match v {
+                serde_json::Value::String(s) => Cow::Borrowed(s.as_str()),
+                other => Cow::Owned(other.to_string()),
+            })
+        }
+    }
+}
@@ -0,0 +1,328 @@
+//! Log dumps are imported from files dropped on the window, picked in the
+//! import window or passed on the command line. A file is read and decoded
+//! on a thread, the app stores the entries as they come.
+//!
+//! JSON arrays are read item by item, anything else line by line, so plain
+//! text lines of `.log` files end up as raw entries. Gzipped files are
+//! recognized by their header.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, SyncSender, TryRecvError},
+        Arc,
+    },
+};
+
+use eframe::egui;
+use serde::de::{DeserializeSeed, Error as _, SeqAccess, Visitor};
+use serde_json::{value::RawValue, Value};
+
+use crate::types::{
+    decode::Decoder,
+    json_log::{LogEntry, RawPayload},
+};
+
+/// Entries are handed over in batches of that size
+const BATCH_SIZE: usize = 500;
+
+/// Batches decoded ahead of what's stored
+const QUEUED_BATCHES: usize = 8;
+
+/// Pretty printed objects spanning many lines are joined up to that size
+const MAX_MULTILINE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportTarget {
+    /// A session of its own, shown read-only
+    NewSession,
+    /// Merged into the recorded session
+    Current,
+}
+
+pub enum Progress {
+    Entries(Vec<LogEntry>),
+    Finished,
+    /// Entries handed over before stay imported
+    Failed(String),
+}
+
+/// File being imported. Dropping it stops the import.
+pub struct Import {
+    pub path: PathBuf,
+    pub target: ImportTarget,
+    /// Stored so far
+    pub entries: usize,
+    rx: Receiver<Progress>,
+    /// Bytes of the file read so far
+    read: Arc<AtomicU64>,
+    size: u64,
+}
+
+impl Import {
+    pub fn start(
+        path: PathBuf,
+        target: ImportTarget,
+        decoder: Decoder,
+        ctx: egui::Context,
+    ) -> io::Result<Import> {
+        let file = File::open(&path)?;
+        let size = file.metadata()?.len();
+        let read = Arc::new(AtomicU64::new(0));
+        let (tx, rx) = mpsc::sync_channel(QUEUED_BATCHES);
+        let counted = Counted {
+            inner: file,
+            read: read.clone(),
+        };
+        std::thread::spawn(move || {
+            let mut sink = Sink {
+                tx: &tx,
+                ctx: &ctx,
+                decoder: &decoder,
+                batch: Vec::new(),
+            };
+            // What was read before an error is kept
+            let read = read_file(counted, &mut sink);
+            let done = match read.and(sink.flush()) {
+                Ok(()) => Progress::Finished,
+                Err(e) => Progress::Failed(e.to_string()),
+            };
+            // Nobody listens if it was cancelled
+            let _ = tx.send(done);
+            ctx.request_repaint();
+        });
+        Ok(Import {
+            path,
+            target,
+            entries: 0,
+            rx,
+            read,
+            size,
+        })
+    }
+
+    /// Share of the file read so far
+    pub fn progress(&self) -> f32 {
+        if self.size == 0 {
+            return 1.0;
+        }
+        (self.read.load(Ordering::Relaxed) as f32 / self.size as f32).min(1.0)
+    }
+
+    pub fn file_name(&self) -> String {
+        file_name(&self.path)
+    }
+
+    /// `None` if nothing new was decoded yet
+    pub fn try_next(&self) -> Option<Progress> {
+        match self.rx.try_recv() {
+            Ok(progress) => Some(progress),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                Some(Progress::Failed("Import stopped unexpectedly".to_string()))
+            }
+        }
+    }
+}
+
+pub fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+fn read_file(file: Counted<File>, sink: &mut Sink) -> io::Result<()> {
+    let mut reader = BufReader::new(file);
+    let mut reader: Box<dyn BufRead> = if reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        Box::new(BufReader::new(flate2::bufread::MultiGzDecoder::new(reader)))
+    } else {
+        Box::new(reader)
+    };
+    if first_byte(&mut reader)? == Some(b'[') {
+        let mut de = serde_json::Deserializer::from_reader(reader);
+        Items { sink }
+            .deserialize(&mut de)
+            .and_then(|()| de.end())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    } else {
+        read_lines(reader, sink)
+    }
+}
+
+/// Skips leading whitespace
+fn first_byte(reader: &mut dyn BufRead) -> io::Result<Option<u8>> {
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(None);
+        }
+        match buf.iter().position(|b| !b.is_ascii_whitespace()) {
+            Some(idx) => {
+                let first = buf[idx];
+                reader.consume(idx);
+                return Ok(Some(first));
+            }
+            None => {
+                let len = buf.len();
+                reader.consume(len);
+            }
+        }
+    }
+}
+
+fn read_lines(mut reader: Box<dyn BufRead>, sink: &mut Sink) -> io::Result<()> {
+    let mut line = Vec::new();
+    let mut number = 0;
+    // Lines of an object not complete yet and where it started
+    let mut pending = Vec::new();
+    let mut first = 0;
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        number += 1;
+        if pending.is_empty() {
+            if line.trim_ascii().is_empty() {
+                continue;
+            }
+            first = number;
+        }
+        pending.extend_from_slice(&line);
+        match serde_json::from_slice::<Value>(&pending) {
+            Ok(value) => sink.push_value(value, pending.trim_ascii(), first, None)?,
+            // Only a line opening an object alone starts a pretty printed one,
+            // anything else incomplete is just a broken line
+            Err(e)
+                if e.is_eof() && pending.len() < MAX_MULTILINE && first_line_is_brace(&pending) =>
+            {
+                continue
+            }
+            Err(e) => sink.push_raw(pending.trim_ascii(), format!("Line {first}: {e}"), None)?,
+        }
+        pending.clear();
+    }
+    if !pending.is_empty() {
+        sink.push_raw(
+            pending.trim_ascii(),
+            format!("Line {first}: Unexpected end of file"),
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+fn first_line_is_brace(data: &[u8]) -> bool {
+    data.split(|&b| b == b'\n')
+        .next()
+        .is_some_and(|line| line.trim_ascii() == b"{")
+}
+
+/// Decodes entries and hands them over in batches
+struct Sink<'a> {
+    tx: &'a SyncSender<Progress>,
+    ctx: &'a egui::Context,
+    decoder: &'a Decoder,
+    batch: Vec<LogEntry>,
+}
+
+impl Sink<'_> {
+    /// `data` is kept for raw entries, `index` is the position in an array
+    fn push_value(
+        &mut self,
+        value: Value,
+        data: &[u8],
+        line: usize,
+        index: Option<usize>,
+    ) -> io::Result<()> {
+        match self.decoder.decode(value) {
+            Ok(entry) => self.push(entry),
+            Err(e) => {
+                let error = match index {
+                    Some(index) => format!("Item {}: {e}", index + 1),
+                    None => format!("Line {line}: {e}"),
+                };
+                self.push_raw(data, error, index)
+            }
+        }
+    }
+
+    fn push_raw(&mut self, data: &[u8], error: String, index: Option<usize>) -> io::Result<()> {
+        self.push(LogEntry::raw(RawPayload {
+            error,
+            batch_index: index,
+            data: data.to_vec(),
+        }))
+    }
+
+    fn push(&mut self, entry: LogEntry) -> io::Result<()> {
+        self.batch.push(entry);
+        if self.batch.len() >= BATCH_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        let batch = std::mem::take(&mut self.batch);
+        self.tx
+            .send(Progress::Entries(batch))
+            .map_err(|_| io::Error::other("Import was cancelled"))?;
+        self.ctx.request_repaint();
+        Ok(())
+    }
+}
+
+/// Top level JSON array, decoded item by item as it's read
+struct Items<'a, 'b> {
+    sink: &'a mut Sink<'b>,
+}
+
+impl<'de> DeserializeSeed<'de> for Items<'_, '_> {
+    type Value = ();
+
+    fn deserialize<D: serde::Deserializer<'de>>(self, d: D) -> Result<(), D::Error> {
+        d.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Items<'_, '_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("an array of log entries")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        let mut index = 0;
+        // Taken as written, raw entries keep the item the way it was
+        while let Some(item) = seq.next_element::<Box<RawValue>>()? {
+            let value = serde_json::from_str(item.get()).map_err(A::Error::custom)?;
+            self.sink
+                .push_value(value, item.get().as_bytes(), 0, Some(index))
+                .map_err(A::Error::custom)?;
+            index += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Counts bytes read from the file for the progress
+struct Counted<R> {
+    inner: R,
+    read: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
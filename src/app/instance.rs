@@ -0,0 +1,215 @@
+//! Only one instance records into the data directory. It holds an advisory
+//! lock on `bucklog.lock` and listens on a localhost port written to
+//! `bucklog.port`, so later launches can hand their request over to it and
+//! quit. If it doesn't answer, they show the stored logs read-only.
+//!
+//! Any local process can connect to the port, so requests have to start
+//! with a random token written next to the port. Only the user can read
+//! the file.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+    net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver},
+    time::Duration,
+};
+
+use eframe::egui;
+
+use crate::trace_err;
+
+use super::logs::logs_dir;
+
+/// How long a later launch waits for the running instance to answer
+const HAND_OFF_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What a later launch asks the running instance to do
+#[derive(Debug, Clone, PartialEq)]
+pub enum Request {
+    /// Bring the window to the front
+    Show,
+    /// Import the file into a session of its own, path is absolute
+    Import(PathBuf),
+}
+
+impl Request {
+    fn to_line(&self) -> String {
+        match self {
+            Request::Show => "show\n".to_string(),
+            Request::Import(path) => format!("import {}\n", path.display()),
+        }
+    }
+
+    fn parse(line: &str) -> Option<Request> {
+        match line.trim_end().split_once(' ') {
+            None if line.trim_end() == "show" => Some(Request::Show),
+            Some(("import", path)) => Some(Request::Import(PathBuf::from(path))),
+            _ => None,
+        }
+    }
+}
+
+pub enum Launch {
+    /// Nobody else uses the data directory
+    Primary(InstanceLock),
+    /// The running instance took the request over, nothing left to do
+    HandedOff,
+    /// Another instance holds the lock but didn't answer
+    ReadOnly,
+}
+
+/// Lock on the data directory, released when dropped or the process ends
+pub struct InstanceLock {
+    /// Unset if the lock file couldn't be opened
+    _file: Option<File>,
+    listener: Option<TcpListener>,
+    /// Later launches prove with it they may read the data directory
+    token: String,
+}
+
+/// Takes the lock, or hands `requests` to the instance holding it. Without
+/// requests the lock is only checked.
+pub fn launch(requests: &[Request]) -> Launch {
+    let file = match File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(lock_file())
+    {
+        Ok(file) => file,
+        Err(e) => {
+            // Better run unguarded than not at all
+            println!("Can't open the lock file: {e}");
+            return Launch::Primary(InstanceLock::listen(None));
+        }
+    };
+    match file.try_lock() {
+        Ok(()) => Launch::Primary(InstanceLock::listen(Some(file))),
+        Err(std::fs::TryLockError::WouldBlock) if requests.is_empty() => Launch::ReadOnly,
+        Err(std::fs::TryLockError::WouldBlock) => match requests.iter().try_for_each(hand_off) {
+            Ok(()) => Launch::HandedOff,
+            Err(e) => {
+                println!("Running instance didn't answer: {e}");
+                Launch::ReadOnly
+            }
+        },
+        Err(std::fs::TryLockError::Error(e)) => {
+            println!("Can't lock the data directory: {e}");
+            Launch::Primary(InstanceLock::listen(Some(file)))
+        }
+    }
+}
+
+impl InstanceLock {
+    fn listen(file: Option<File>) -> Self {
+        let token = format!("{:032x}", rand::random::<u128>());
+        let listener = trace_err!(TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).and_then(
+            |listener| {
+                let port = listener.local_addr()?.port();
+                write_private(&port_file(), &format!("{port} {token}"))?;
+                Ok(listener)
+            }
+        ))
+        .ok();
+        InstanceLock {
+            _file: file,
+            listener,
+            token,
+        }
+    }
+
+    /// Answers later launches, passing their requests on
+    pub fn serve(&mut self, ctx: egui::Context) -> Receiver<Request> {
+        let (tx, rx) = mpsc::channel();
+        let Some(listener) = self.listener.take() else {
+            return rx;
+        };
+        let token = self.token.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = trace_err!(stream) else {
+                    continue;
+                };
+                let Ok(Some(request)) = trace_err!(read_request(&stream, &token)) else {
+                    continue;
+                };
+                if tx.send(request).is_err() {
+                    return;
+                }
+                ctx.request_repaint();
+                let _ = (&stream).write_all(b"ok\n");
+            }
+        });
+        rx
+    }
+}
+
+/// `TOKEN REQUEST`, requests with another token are dropped
+fn read_request(stream: &TcpStream, token: &str) -> io::Result<Option<Request>> {
+    stream.set_read_timeout(Some(HAND_OFF_TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    match line.split_once(' ') {
+        Some((given, request)) if same_token(given, token) => Ok(Request::parse(request)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "Hand-off request with invalid token",
+        )),
+    }
+}
+
+/// Takes as long whichever byte differs
+fn same_token(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn hand_off(request: &Request) -> io::Result<()> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid port file");
+    let contents = std::fs::read_to_string(port_file())?;
+    let (port, token) = contents.trim().split_once(' ').ok_or_else(invalid)?;
+    let port: u16 = port.parse().map_err(|_| invalid())?;
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let mut stream = TcpStream::connect_timeout(&addr, HAND_OFF_TIMEOUT)?;
+    stream.set_read_timeout(Some(HAND_OFF_TIMEOUT))?;
+    stream.write_all(format!("{token} {}", request.to_line()).as_bytes())?;
+    let mut answer = String::new();
+    BufReader::new(&stream).read_line(&mut answer)?;
+    if answer.trim_end() != "ok" {
+        return Err(io::Error::other("Unexpected answer"));
+    }
+    Ok(())
+}
+
+fn lock_file() -> PathBuf {
+    logs_dir().join("bucklog.lock")
+}
+
+fn port_file() -> PathBuf {
+    logs_dir().join("bucklog.port")
+}
+
+/// Readable by the user only, where permissions allow that
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    let mut options = File::options();
+    options.create(true).truncate(true).write(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // Mode only applies to new files
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)).or_else(
+            |e| match e.kind() {
+                io::ErrorKind::NotFound => Ok(()),
+                _ => Err(e),
+            },
+        )?;
+    }
+    options.open(path)?.write_all(contents.as_bytes())
+}
@@ -0,0 +1,55 @@
+//! Evicted entries can be kept in gzipped NDJSON files, one per day of
+//! eviction. Every eviction appends a new gzip member, which `zcat` and
+//! friends read as one stream.
+
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use flate2::{write::GzEncoder, Compression};
+use time::{macros::format_description, OffsetDateTime};
+
+use crate::types::json_log::LogEntry;
+
+pub struct Archive {
+    dir: PathBuf,
+}
+
+impl Archive {
+    pub fn new(dir: PathBuf) -> Self {
+        Archive { dir }
+    }
+
+    /// Appends complete NDJSON lines
+    pub fn write(&self, lines: &[u8]) -> io::Result<()> {
+        if lines.is_empty() {
+            return Ok(());
+        }
+        std::fs::create_dir_all(&self.dir)?;
+        let date = OffsetDateTime::now_utc()
+            .format(format_description!("[year]-[month]-[day]"))
+            .map_err(io::Error::other)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(format!("evicted-{date}.ndjson.gz")))?;
+        let mut encoder = GzEncoder::new(file, Compression::default());
+        encoder.write_all(lines)?;
+        // Evicted entries are deleted right after, so they better be there
+        encoder.finish()?.sync_data()
+    }
+
+    pub fn write_entries<'a>(
+        &self,
+        entries: impl IntoIterator<Item = &'a LogEntry>,
+    ) -> io::Result<()> {
+        let mut lines = Vec::new();
+        for entry in entries {
+            serde_json::to_writer(&mut lines, entry).map_err(io::Error::other)?;
+            lines.push(b'\n');
+        }
+        self.write(&lines)
+    }
+}
@@ -0,0 +1,892 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use serde_json::Value;
+use time::{macros::format_description, OffsetDateTime};
+
+use archive::Archive;
+use segment::SegmentLogs;
+pub use session::SessionInfo;
+use sqlite::SqliteLogs;
+
+use crate::{
+    app::config::{RetentionSettings, StorageBackend, StorageSettings},
+    trace_err,
+    types::{decode::Decoder, json_log::LogEntry},
+};
+
+use super::{filter::Filter, import};
+
+mod archive;
+mod segment;
+mod session;
+mod sqlite;
+
+/// Stable identifier of a stored entry
+pub type EntryId = u64;
+
+/// Where entries live. Entries keep the order they were appended in.
+pub trait LogStore {
+    /// Once this returns the entry is written, see [`LogStore::sync`]
+    fn append(&mut self, entry: LogEntry) -> io::Result<()>;
+    fn count(&self, filter: &Filter) -> io::Result<usize>;
+    /// Entries matching the filter with ids above `after`, skipping first
+    /// `skip` of them
+    fn page(
+        &self,
+        filter: &Filter,
+        after: Option<EntryId>,
+        skip: usize,
+        limit: usize,
+    ) -> io::Result<Vec<(EntryId, LogEntry)>>;
+    fn get(&self, id: EntryId) -> io::Result<Option<LogEntry>>;
+    fn raw_count(&self) -> io::Result<usize>;
+    /// Entries per level name
+    fn levels(&self) -> io::Result<BTreeMap<String, usize>>;
+    /// Tries to decode raw entries again, e.g. after changing the log
+    /// format. Returns how many of them were decoded.
+    fn redecode(&mut self, decoder: &Decoder) -> io::Result<usize>;
+    /// Syncs written entries if it's time to. Returns when to call again.
+    fn sync_if_due(&mut self) -> io::Result<Option<Duration>>;
+    /// Makes sure everything written survives an OS crash
+    fn sync(&mut self) -> io::Result<()>;
+    /// Removes the oldest entries until the limits are met, writing them
+    /// to `archive` first if given. Returns how many were removed.
+    fn evict(
+        &mut self,
+        retention: &RetentionSettings,
+        archive: Option<&Archive>,
+    ) -> io::Result<usize>;
+}
+
+/// Rows of the table are fetched from the store in pages of that size
+const PAGE_SIZE: usize = 200;
+
+/// How often retention limits are enforced and the session description
+/// saved while running
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Store of a session with the current filter applied and the visible part
+/// cached
+pub struct Logs {
+    store: Box<dyn LogStore>,
+    filter_text: String,
+    filter: Filter,
+    count: Option<usize>,
+    raw_count: Option<usize>,
+    /// Offset of the first cached row and the rows
+    page: Option<(usize, Vec<(EntryId, LogEntry)>)>,
+    /// Id of the last row before an offset, so pages are looked up from
+    /// the closest one instead of skipping every row before them
+    anchors: BTreeMap<usize, EntryId>,
+    retention: RetentionSettings,
+    archive: Archive,
+    last_maintenance: Instant,
+    session: SessionInfo,
+    /// Changed since it was last saved
+    session_dirty: bool,
+    read_only: bool,
+    recoveries: Vec<Recovery>,
+}
+
+impl Logs {
+    /// Continues the latest session, or starts a new one if there is none
+    /// or it's configured to
+    pub fn open(settings: &StorageSettings) -> io::Result<Self> {
+        match SessionInfo::latest()? {
+            Some(session) if !settings.new_session_on_launch => {
+                Logs::record(session, settings, settings.retention.clone())
+            }
+            _ => Logs::start_session("", settings),
+        }
+    }
+
+    pub fn start_session(name: &str, settings: &StorageSettings) -> io::Result<Self> {
+        Logs::record(
+            SessionInfo::create(name)?,
+            settings,
+            settings.retention.clone(),
+        )
+    }
+
+    /// Starts a session for entries imported from `file`. Retention limits
+    /// don't apply to it, nor does it evict other sessions.
+    pub fn import_session(file: &Path, settings: &StorageSettings) -> io::Result<Self> {
+        let mut session = SessionInfo::create(&import::file_name(file))?;
+        session.imported = Some(file.to_path_buf());
+        Logs::record(session, settings, RetentionSettings::default())
+    }
+
+    /// Opens the latest session without changing it, for when another
+    /// instance records into it
+    pub fn open_read_only() -> io::Result<Self> {
+        let session =
+            SessionInfo::latest()?.ok_or_else(|| io::Error::other("There are no sessions yet"))?;
+        Logs::view(session)
+    }
+
+    /// Read-only view without entries, for when there's no session to show
+    pub fn empty() -> Self {
+        let session = SessionInfo {
+            id: String::new(),
+            name: String::new(),
+            started: OffsetDateTime::now_utc(),
+            ended: None,
+            entries: 0,
+            levels: BTreeMap::new(),
+            imported: None,
+            backend: None,
+        };
+        Logs::new(
+            Box::new(SegmentLogs::empty()),
+            session,
+            RetentionSettings::default(),
+            true,
+        )
+    }
+
+    /// Opens a past session without changing its entries. Damaged files are
+    /// read as far as possible but left alone.
+    pub fn view(session: SessionInfo) -> io::Result<Self> {
+        let mut recoveries = Vec::new();
+        let store = open_read_only(&session, &mut recoveries)?;
+        let mut logs = Logs::new(store, session, RetentionSettings::default(), true);
+        logs.recoveries = recoveries;
+        // Counts of a session that wasn't closed properly may be behind.
+        // One without an end may still be recorded, that's up to its recorder.
+        logs.refresh_session()?;
+        if std::mem::take(&mut logs.session_dirty) && logs.session.ended.is_some() {
+            logs.session.save()?;
+        }
+        Ok(logs)
+    }
+
+    /// Opens the session to append entries to it, stored the way they were
+    /// so far. Damaged files are moved aside and replaced with what could be
+    /// read from them.
+    fn record(
+        mut session: SessionInfo,
+        settings: &StorageSettings,
+        retention: RetentionSettings,
+    ) -> io::Result<Self> {
+        let dir = session.dir();
+        let mut recoveries = Vec::new();
+        let backend = recorded_backend(&session)?.unwrap_or(settings.backend);
+        let store: Box<dyn LogStore> = match backend {
+            StorageBackend::Segments => {
+                Box::new(SegmentLogs::open(&dir, settings, &mut recoveries)?)
+            }
+            StorageBackend::Sqlite => Box::new(SqliteLogs::open(&dir, settings, &mut recoveries)?),
+        };
+        session.ended = None;
+        session.backend = Some(backend);
+        let mut logs = Logs::new(store, session, retention, false);
+        logs.recoveries = recoveries;
+        logs.session_dirty = true;
+        logs.evict()?;
+        logs.refresh_session()?;
+        logs.save_session()?;
+        Ok(logs)
+    }
+
+    fn new(
+        store: Box<dyn LogStore>,
+        session: SessionInfo,
+        retention: RetentionSettings,
+        read_only: bool,
+    ) -> Self {
+        Logs {
+            store,
+            filter_text: String::new(),
+            filter: Filter::default(),
+            count: None,
+            raw_count: None,
+            page: None,
+            anchors: BTreeMap::new(),
+            retention,
+            archive: Archive::new(archive_dir()),
+            last_maintenance: Instant::now(),
+            session,
+            session_dirty: false,
+            read_only,
+            recoveries: Vec::new(),
+        }
+    }
+
+    /// Reports about damaged files found while opening
+    pub fn take_recoveries(&mut self) -> Vec<Recovery> {
+        std::mem::take(&mut self.recoveries)
+    }
+
+    pub fn session(&self) -> &SessionInfo {
+        &self.session
+    }
+
+    /// The entry is written to disk before it shows up
+    pub fn append(&mut self, entry: LogEntry) -> io::Result<()> {
+        let level = entry.level.name.to_string();
+        self.store.append(entry)?;
+        self.session.entries += 1;
+        *self.session.levels.entry(level).or_default() += 1;
+        self.session_dirty = true;
+        // Rows only get added after the others, anchors stay valid
+        self.count = None;
+        self.raw_count = None;
+        self.page = None;
+        Ok(())
+    }
+
+    pub fn set_filter(&mut self, text: &str) {
+        if self.filter_text != text {
+            self.filter_text = text.to_string();
+            self.filter = Filter::parse(text);
+            self.invalidate();
+        }
+    }
+
+    /// Entries matching the filter
+    pub fn count(&mut self) -> usize {
+        if self.count.is_none() {
+            self.count = Some(trace_err!(self.store.count(&self.filter)).unwrap_or_default());
+        }
+        self.count.unwrap_or_default()
+    }
+
+    /// `idx`-th entry matching the filter
+    pub fn row(&mut self, idx: usize) -> Option<&(EntryId, LogEntry)> {
+        let cached = self
+            .page
+            .as_ref()
+            .is_some_and(|(offset, rows)| (*offset..offset + rows.len()).contains(&idx));
+        if !cached {
+            let offset = idx - idx % PAGE_SIZE;
+            let (from, after) = self
+                .anchors
+                .range(..=offset)
+                .next_back()
+                .map_or((0, None), |(&from, &id)| (from, Some(id)));
+            let rows = trace_err!(self
+                .store
+                .page(&self.filter, after, offset - from, PAGE_SIZE))
+            .unwrap_or_default();
+            if let Some((id, _)) = rows.last() {
+                self.anchors.insert(offset + rows.len(), *id);
+            }
+            self.page = Some((offset, rows));
+        }
+        let (offset, rows) = self.page.as_ref()?;
+        rows.get(idx - offset)
+    }
+
+    pub fn get(&self, id: EntryId) -> Option<LogEntry> {
+        trace_err!(self.store.get(id)).ok().flatten()
+    }
+
+    pub fn raw_count(&mut self) -> usize {
+        if self.raw_count.is_none() {
+            self.raw_count = Some(trace_err!(self.store.raw_count()).unwrap_or_default());
+        }
+        self.raw_count.unwrap_or_default()
+    }
+
+    pub fn redecode(&mut self, decoder: &Decoder) -> io::Result<usize> {
+        let decoded = self.store.redecode(decoder);
+        self.invalidate();
+        self.refresh_session()?;
+        decoded
+    }
+
+    pub fn sync_if_due(&mut self) -> io::Result<Option<Duration>> {
+        self.store.sync_if_due()
+    }
+
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.store.sync()?;
+        self.save_session()
+    }
+
+    /// Marks the session as ended. Nothing should be appended after that.
+    pub fn close(&mut self) -> io::Result<()> {
+        if !self.read_only {
+            self.session.ended = Some(OffsetDateTime::now_utc());
+            self.session_dirty = true;
+        }
+        self.sync()
+    }
+
+    /// Takes [`Logs::close`] back, for when no other session replaces this one
+    pub fn reopen(&mut self) -> io::Result<()> {
+        if !self.read_only {
+            self.session.ended = None;
+            self.session_dirty = true;
+        }
+        self.save_session()
+    }
+
+    /// Applies new limits right away
+    pub fn set_retention(&mut self, retention: &RetentionSettings) -> io::Result<usize> {
+        if self.retention == *retention {
+            return Ok(0);
+        }
+        self.retention = retention.clone();
+        self.evict()
+    }
+
+    /// Enforces retention limits and saves the session description if
+    /// it's time to. Returns when to call again, entries age out without
+    /// anything else waking the app.
+    pub fn maintain_if_due(&mut self) -> io::Result<Option<Duration>> {
+        if self.last_maintenance.elapsed() >= MAINTENANCE_INTERVAL {
+            self.evict()?;
+            self.save_session()?;
+        }
+        let limited = !self.read_only && !self.retention.is_unlimited();
+        Ok(limited.then(|| MAINTENANCE_INTERVAL.saturating_sub(self.last_maintenance.elapsed())))
+    }
+
+    fn evict(&mut self) -> io::Result<usize> {
+        self.last_maintenance = Instant::now();
+        if self.read_only || self.retention.is_unlimited() {
+            return Ok(0);
+        }
+        let archive = self.retention.archive.then_some(&self.archive);
+        let evicted = self.store.evict(&self.retention, archive)?;
+        if evicted > 0 {
+            self.invalidate();
+            self.refresh_session()?;
+        }
+        Ok(evicted + self.evict_sessions()?)
+    }
+
+    /// Applies the limits to the recorded sessions together, newer ones
+    /// are kept first. This one is trimmed entry by entry, past ones are
+    /// removed whole. Imported sessions and ones that haven't ended, which
+    /// may still be recorded into, are left alone.
+    fn evict_sessions(&mut self) -> io::Result<usize> {
+        let cutoff = self.retention.max_age.and_then(cutoff);
+        let max_size = self.retention.max_size_mb.map(|mb| mb * 1024 * 1024);
+        let mut size = dir_size(&self.session.dir())?;
+        let mut entries = self.session.entries;
+        let archive = self.retention.archive.then_some(&self.archive);
+        let mut evicted = 0;
+        let past = SessionInfo::list()?
+            .into_iter()
+            .filter(|s| s.imported.is_none() && s.ended.is_some() && s.id != self.session.id);
+        for session in past {
+            let session_size = dir_size(&session.dir())?;
+            let too_old = cutoff.is_some_and(|c| session.ended.is_some_and(|ended| ended < c));
+            let too_big = max_size.is_some_and(|max| size + session_size > max);
+            let too_many =
+                (self.retention.max_entries).is_some_and(|max| entries + session.entries > max);
+            // One that can't be removed, e.g. open elsewhere, is tried
+            // again next time
+            if !(too_old || too_big || too_many)
+                || trace_err!(remove_session(&session, archive)).is_err()
+            {
+                size += session_size;
+                entries += session.entries;
+                continue;
+            }
+            evicted += session.entries;
+        }
+        Ok(evicted)
+    }
+
+    /// Counts entries of the session again
+    fn refresh_session(&mut self) -> io::Result<()> {
+        let levels = self.store.levels()?;
+        let entries = levels.values().sum();
+        if self.session.levels != levels || self.session.entries != entries {
+            self.session.levels = levels;
+            self.session.entries = entries;
+            self.session_dirty = true;
+        }
+        Ok(())
+    }
+
+    fn save_session(&mut self) -> io::Result<()> {
+        if std::mem::take(&mut self.session_dirty) && !self.read_only {
+            self.session.save()?;
+        }
+        Ok(())
+    }
+
+    fn invalidate(&mut self) {
+        self.count = None;
+        self.raw_count = None;
+        self.page = None;
+        self.anchors.clear();
+    }
+}
+
+/// Decodes raw entry again in place. A whole message may turn out to be a
+/// batch, entries of its other lines are returned. `None` if it still
+/// can't be decoded.
+fn redecode_entry(entry: &mut LogEntry, decoder: &Decoder) -> Option<Vec<LogEntry>> {
+    let raw = entry.raw.as_mut()?;
+    let result = match raw.batch_index {
+        // Lines of a batch are stored already decompressed
+        Some(_) => decoder
+            .decode_payload(&raw.data, None, None)
+            .map(|e| vec![e]),
+        None => {
+            let origin = entry.origin.as_ref();
+            decoder.decode_entries(
+                &raw.data,
+                origin.and_then(|o| o.content_type.as_deref()),
+                origin.and_then(|o| o.content_encoding.as_deref()),
+            )
+        }
+    };
+    let mut entries = match result {
+        Ok(entries) if !entries.is_empty() => entries,
+        Ok(_) => {
+            raw.error = "Empty batch".to_string();
+            return None;
+        }
+        Err(e) => {
+            raw.error = e.to_string();
+            return None;
+        }
+    };
+    for new in &mut entries {
+        new.origin = entry.origin.clone();
+        new.source_id = entry.source_id.clone();
+    }
+    let rest = entries.split_off(1);
+    *entry = entries.pop().expect("Checked above");
+    Some(rest)
+}
+
+/// Opens the store of the session without changing it
+fn open_read_only(
+    session: &SessionInfo,
+    recoveries: &mut Vec<Recovery>,
+) -> io::Result<Box<dyn LogStore>> {
+    let dir = session.dir();
+    Ok(match recorded_backend(session)? {
+        Some(StorageBackend::Sqlite) => Box::new(SqliteLogs::open_read_only(&dir)?),
+        Some(StorageBackend::Segments) | None => {
+            Box::new(SegmentLogs::open_read_only(&dir, recoveries)?)
+        }
+    })
+}
+
+/// Backend the entries of the session are stored with, if there are any.
+/// Sessions from before it was noted down only have a database if they were
+/// recorded into one.
+fn recorded_backend(session: &SessionInfo) -> io::Result<Option<StorageBackend>> {
+    Ok(match session.backend {
+        Some(backend) => Some(backend),
+        None if std::fs::exists(sqlite_file(&session.dir()))? => Some(StorageBackend::Sqlite),
+        None => None,
+    })
+}
+
+/// Deletes the session, writing its entries to `archive` first a page at
+/// a time if given
+fn remove_session(session: &SessionInfo, archive: Option<&Archive>) -> io::Result<()> {
+    if let Some(archive) = archive {
+        let store = open_read_only(session, &mut Vec::new())?;
+        let filter = Filter::default();
+        let mut after = None;
+        loop {
+            let rows = store.page(&filter, after, 0, PAGE_SIZE)?;
+            let Some((last, _)) = rows.last() else {
+                break;
+            };
+            after = Some(*last);
+            archive.write_entries(rows.iter().map(|(_, entry)| entry))?;
+        }
+    }
+    session.delete()
+}
+
+/// Bytes taken by the files in `dir` and below
+fn dir_size(dir: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    for item in std::fs::read_dir(dir)? {
+        let item = item?;
+        let meta = item.metadata()?;
+        size += if meta.is_dir() {
+            dir_size(&item.path())?
+        } else {
+            meta.len()
+        };
+    }
+    Ok(size)
+}
+
+/// Entries older than that are past `max_age`
+fn cutoff(max_age: Duration) -> Option<OffsetDateTime> {
+    let max_age = time::Duration::try_from(max_age).ok()?;
+    OffsetDateTime::now_utc().checked_sub(max_age)
+}
+
+/// What was done about a stored file that couldn't be read whole
+#[derive(Clone, Debug)]
+pub struct Recovery {
+    pub file: PathBuf,
+    /// Where the original was moved, unless it was left alone
+    pub moved_to: Option<PathBuf>,
+    pub error: String,
+    /// Entries read from it
+    pub salvaged: usize,
+    /// Entries that couldn't be read, if it's known
+    pub lost: Option<usize>,
+}
+
+/// Renames a damaged file out of the way, keeping it for inspection
+fn move_aside(path: &Path) -> io::Result<PathBuf> {
+    let now = OffsetDateTime::now_utc()
+        .format(format_description!(
+            "[year][month][day]-[hour][minute][second]"
+        ))
+        .map_err(io::Error::other)?;
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".corrupt-{now}"));
+    let moved_to = path.with_file_name(name);
+    std::fs::rename(path, &moved_to)?;
+    Ok(moved_to)
+}
+
+/// Error for writing into a session opened read-only
+fn read_only() -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, "The session is read-only")
+}
+
+/// Before segments all entries lived in one JSON document
+#[derive(Deserialize)]
+struct LegacyLogs {
+    entries: Vec<LogEntry>,
+}
+
+/// Salvages what it can from a damaged document, moving it aside if
+/// `repair` is set
+fn read_legacy(
+    dir: &Path,
+    recoveries: &mut Vec<Recovery>,
+    repair: bool,
+) -> io::Result<Option<Vec<LogEntry>>> {
+    let path = legacy_file(dir);
+    let data = match std::fs::read(&path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if data.trim_ascii().is_empty() {
+        return Ok(None);
+    }
+    let error = match serde_json::from_slice::<LegacyLogs>(&data) {
+        Ok(logs) => return Ok(Some(logs.entries)),
+        Err(e) => e,
+    };
+    let (entries, lost) = salvage_legacy(&data);
+    recoveries.push(Recovery {
+        moved_to: if repair {
+            Some(move_aside(&path)?)
+        } else {
+            None
+        },
+        file: path,
+        error: error.to_string(),
+        salvaged: entries.len(),
+        lost,
+    });
+    Ok(Some(entries))
+}
+
+/// Reads entries of a damaged document one by one until the first one
+/// that isn't complete JSON, as the rest can't be told apart from garbage
+fn salvage_legacy(data: &[u8]) -> (Vec<LogEntry>, Option<usize>) {
+    let mut entries = Vec::new();
+    let mut lost = 0;
+    let start = data
+        .windows(b"\"entries\"".len())
+        .position(|w| w == b"\"entries\"")
+        .and_then(|idx| {
+            data[idx..]
+                .iter()
+                .position(|&b| b == b'[')
+                .map(|at| idx + at + 1)
+        });
+    let Some(mut rest) = start.map(|start| &data[start..]) else {
+        return (entries, None);
+    };
+    loop {
+        let skip = rest
+            .iter()
+            .position(|b| !b.is_ascii_whitespace() && *b != b',')
+            .unwrap_or(rest.len());
+        rest = &rest[skip..];
+        if rest.first() == Some(&b']') {
+            return (entries, Some(lost));
+        }
+        let mut values = serde_json::Deserializer::from_slice(rest).into_iter::<Value>();
+        match values.next() {
+            Some(Ok(value)) => match serde_json::from_value(value) {
+                Ok(entry) => entries.push(entry),
+                Err(_) => lost += 1,
+            },
+            _ => return (entries, None),
+        }
+        rest = &rest[values.byte_offset()..];
+    }
+}
+
+/// Files below are in the directory of a session
+fn legacy_file(dir: &Path) -> PathBuf {
+    dir.join("logs.json")
+}
+
+fn segments_dir(dir: &Path) -> PathBuf {
+    dir.join("logs")
+}
+
+fn sqlite_file(dir: &Path) -> PathBuf {
+    dir.join("logs.sqlite")
+}
+
+fn archive_dir() -> PathBuf {
+    logs_dir().join("archive")
+}
+
+pub fn logs_dir() -> PathBuf {
+    #[cfg(test)]
+    if let Some(dir) = tests::DATA_DIR.with(|dir| dir.borrow().clone()) {
+        return dir;
+    }
+    let local_config_dir = dirs::config_local_dir().expect("Failed to get config dir!");
+    let dir = local_config_dir.join("bucklog");
+    if !std::fs::exists(&dir).expect("Can't check existence of config dir") {
+        std::fs::create_dir(&dir).expect("Failed to create config dir");
+    }
+    dir
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::types::json_log::{Origin, RawPayload};
+
+    thread_local! {
+        /// Data directory of the test running on this thread
+        pub static DATA_DIR: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+    }
+
+    /// Data directory of a test, removed when dropped
+    pub struct TempDataDir(pub PathBuf);
+
+    impl TempDataDir {
+        pub fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("bucklog-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            DATA_DIR.with(|data| *data.borrow_mut() = Some(dir.clone()));
+            TempDataDir(dir)
+        }
+    }
+
+    impl Drop for TempDataDir {
+        fn drop(&mut self) {
+            DATA_DIR.with(|data| *data.borrow_mut() = None);
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    pub fn entry(message: &str) -> LogEntry {
+        let mut entry = LogEntry::raw(RawPayload {
+            error: String::new(),
+            batch_index: None,
+            data: message.as_bytes().to_vec(),
+        });
+        entry.raw = None;
+        entry.level = crate::types::level::Level::INFO;
+        entry
+    }
+
+    fn limited(max_entries: usize) -> StorageSettings {
+        StorageSettings {
+            retention: RetentionSettings {
+                max_entries: Some(max_entries),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn stored_entries(id: &str) -> Option<usize> {
+        let sessions = SessionInfo::list().unwrap();
+        sessions.iter().find(|s| s.id == id).map(|s| s.entries)
+    }
+
+    #[test]
+    fn keeps_live_session_when_others_start() {
+        let _dir = TempDataDir::new("keeps-live-session");
+        let settings = limited(1);
+        let mut live = Logs::open(&settings).unwrap();
+        for _ in 0..3 {
+            live.append(entry("live")).unwrap();
+        }
+        live.sync().unwrap();
+        let id = live.session().id.clone();
+
+        let mut imported = Logs::import_session(Path::new("/var/log/app.log"), &settings).unwrap();
+        imported.append(entry("imported")).unwrap();
+        imported.close().unwrap();
+        assert_eq!(stored_entries(&id), Some(3));
+
+        // Another instance starting a session while this one records
+        let mut other = Logs::start_session("other", &settings).unwrap();
+        other.close().unwrap();
+        assert_eq!(stored_entries(&id), Some(3));
+        assert_eq!(live.count(), 3);
+
+        // Once ended it's a past session like any other
+        live.close().unwrap();
+        let next = Logs::start_session("next", &settings).unwrap();
+        assert_eq!(stored_entries(&id), None);
+        assert!(stored_entries(imported.session().id.as_str()).is_some());
+        assert!(stored_entries(&next.session().id).is_some());
+    }
+
+    fn varied_entries() -> Vec<LogEntry> {
+        let mut entries = Vec::new();
+        let fields = [
+            json!({"user": "Élodie", "ok": true, "took": 1.5}),
+            json!({"user": "bob", "ok": false, "took": 20, "note": null}),
+            json!({"user": "BOB", "tags": ["a", "B"], "a.b": "dotted"}),
+        ];
+        for (n, fields) in fields.into_iter().enumerate() {
+            let mut entry = entry(&format!("Grüße aus ÄRGERLICH {n} 50%_off"));
+            entry.fields = serde_json::from_value(fields).unwrap();
+            entry.source.target = Some(format!("Übung::{n}"));
+            entries.push(entry);
+        }
+        let mut origin = Origin {
+            exchange: "logs".into(),
+            routing_key: "app.Ärger".into(),
+            priority: Some(5),
+            redelivered: true,
+            ..Default::default()
+        };
+        origin.headers.insert("retries".into(), json!(3));
+        origin.headers.insert("service".into(), json!("Billing"));
+        entries[1].origin = Some(origin);
+        entries[2].level = crate::types::level::Level::WARN;
+        entries
+    }
+
+    #[test]
+    fn backends_filter_alike() {
+        let dir = TempDataDir::new("backends-filter-alike");
+        let settings = StorageSettings::default();
+        let entries = varied_entries();
+        let mut stores: Vec<Box<dyn LogStore>> = Vec::new();
+        for (name, backend) in [
+            ("segments", StorageBackend::Segments),
+            ("sqlite", StorageBackend::Sqlite),
+        ] {
+            let path = dir.0.join(name);
+            std::fs::create_dir_all(&path).unwrap();
+            let mut store: Box<dyn LogStore> = match backend {
+                StorageBackend::Segments => {
+                    Box::new(SegmentLogs::open(&path, &settings, &mut Vec::new()).unwrap())
+                }
+                StorageBackend::Sqlite => {
+                    Box::new(SqliteLogs::open(&path, &settings, &mut Vec::new()).unwrap())
+                }
+            };
+            for entry in &entries {
+                store.append(entry.clone()).unwrap();
+            }
+            stores.push(store);
+        }
+
+        let filters = [
+            "",
+            "grüße",
+            "ärgerlich",
+            "50%_",
+            "%",
+            "level=warn",
+            "level~ar",
+            "target=übung::0",
+            "target~ÜBUNG",
+            "user=élodie",
+            "user=bob",
+            "fields.user~O",
+            "ok=true",
+            "ok=false",
+            "took=1.5",
+            "took~2",
+            "note=null",
+            "tags=[\"a\",\"b\"]",
+            "a.b=dotted",
+            "missing=",
+            "origin.routing_key~ärger",
+            "origin.priority=5",
+            "origin.redelivered=true",
+            "origin.headers.retries=3",
+            "origin.headers.service=billing",
+            "origin.kind~",
+            "origin.exchange~ user~bob",
+        ];
+        for text in filters {
+            let filter = Filter::parse(text);
+            let expected: Vec<_> = entries
+                .iter()
+                .filter(|e| filter.matches(e))
+                .map(|e| e.message.clone())
+                .collect();
+            for store in &stores {
+                let found: Vec<_> = store
+                    .page(&filter, None, 0, PAGE_SIZE)
+                    .unwrap()
+                    .into_iter()
+                    .map(|(_, e)| e.message)
+                    .collect();
+                assert_eq!(found, expected, "filter {text:?}");
+                assert_eq!(
+                    store.count(&filter).unwrap(),
+                    expected.len(),
+                    "filter {text:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn sessions_keep_their_backend() {
+        let _dir = TempDataDir::new("sessions-keep-backend");
+        let mut settings = StorageSettings {
+            backend: StorageBackend::Sqlite,
+            ..Default::default()
+        };
+        let mut logs = Logs::open(&settings).unwrap();
+        logs.append(entry("stored in sqlite")).unwrap();
+        logs.close().unwrap();
+
+        settings.backend = StorageBackend::Segments;
+        let mut logs = Logs::open(&settings).unwrap();
+        assert_eq!(logs.session().backend, Some(StorageBackend::Sqlite));
+        assert_eq!(logs.count(), 1);
+        logs.append(entry("still sqlite")).unwrap();
+        logs.close().unwrap();
+
+        let session = SessionInfo::latest().unwrap().unwrap();
+        assert_eq!(Logs::view(session).unwrap().count(), 2);
+        let mut new = Logs::start_session("", &settings).unwrap();
+        assert_eq!(new.session().backend, Some(StorageBackend::Segments));
+        new.close().unwrap();
+    }
+}
@@ -0,0 +1,658 @@
+//! Append-only storage of entries as newline delimited JSON split into
+//! numbered segment files.
+//!
+//! Every entry is written as soon as it arrives and synced to disk
+//! periodically. A crash can only leave the last line of the last segment
+//! half written, which is cut off when the store is opened again.
+//! New segments are prepared under a temporary name and renamed into place,
+//! so a segment either exists whole or not at all. A `.full` segment holds
+//! everything stored before it, older segments are leftovers of a rewrite.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    app::{
+        config::{RetentionSettings, StorageSettings},
+        filter::Filter,
+    },
+    types::{decode::Decoder, json_log::LogEntry},
+};
+
+use super::{
+    archive::Archive, cutoff, legacy_file, move_aside, read_legacy, read_only, redecode_entry,
+    segments_dir, EntryId, LogStore, Recovery,
+};
+
+const EXT: &str = "ndjson";
+const FULL_EXT: &str = "full.ndjson";
+const TMP_EXT: &str = "tmp";
+
+/// Segments with all entries kept in memory. Entry ids are positions,
+/// counting the evicted entries too.
+pub struct SegmentLogs {
+    entries: Vec<LogEntry>,
+    /// Unset for read-only sessions
+    store: Option<SegmentStore>,
+    /// Id of the first entry kept
+    first_id: EntryId,
+}
+
+impl SegmentLogs {
+    /// Opens the store of the session in `dir`
+    pub fn open(
+        dir: &Path,
+        settings: &StorageSettings,
+        recoveries: &mut Vec<Recovery>,
+    ) -> io::Result<Self> {
+        let (mut store, mut entries) =
+            SegmentStore::open(&segments_dir(dir), settings, recoveries)?;
+        if entries.is_empty() {
+            if let Some(legacy) = read_legacy(dir, recoveries, true)? {
+                store.rewrite(&legacy)?;
+                entries = legacy;
+                // A damaged one has been moved aside already
+                let legacy_file = legacy_file(dir);
+                if std::fs::exists(&legacy_file)? {
+                    std::fs::rename(&legacy_file, legacy_file.with_extension("json.migrated"))?;
+                }
+            }
+        }
+        Ok(SegmentLogs {
+            entries,
+            store: Some(store),
+            first_id: 0,
+        })
+    }
+
+    pub fn open_read_only(dir: &Path, recoveries: &mut Vec<Recovery>) -> io::Result<Self> {
+        let mut entries = read_all(&segments_dir(dir), recoveries)?;
+        if entries.is_empty() {
+            entries = read_legacy(dir, recoveries, false)?.unwrap_or_default();
+        }
+        Ok(SegmentLogs {
+            entries,
+            store: None,
+            first_id: 0,
+        })
+    }
+
+    /// Read-only and without entries
+    pub fn empty() -> Self {
+        SegmentLogs {
+            entries: Vec::new(),
+            store: None,
+            first_id: 0,
+        }
+    }
+
+    fn store(&mut self) -> io::Result<&mut SegmentStore> {
+        self.store.as_mut().ok_or_else(read_only)
+    }
+
+    fn matching<'a>(
+        &'a self,
+        filter: &'a Filter,
+    ) -> impl Iterator<Item = (usize, &'a LogEntry)> + 'a {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| filter.matches(e))
+    }
+}
+
+impl LogStore for SegmentLogs {
+    fn append(&mut self, entry: LogEntry) -> io::Result<()> {
+        self.store()?.append(&entry)?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    fn count(&self, filter: &Filter) -> io::Result<usize> {
+        if filter.is_empty() {
+            return Ok(self.entries.len());
+        }
+        Ok(self.matching(filter).count())
+    }
+
+    fn page(
+        &self,
+        filter: &Filter,
+        after: Option<EntryId>,
+        skip: usize,
+        limit: usize,
+    ) -> io::Result<Vec<(EntryId, LogEntry)>> {
+        let start = after.map_or(0, |id| (id + 1).saturating_sub(self.first_id) as usize);
+        Ok(self
+            .entries
+            .iter()
+            .enumerate()
+            .skip(start)
+            .filter(|(_, e)| filter.matches(e))
+            .skip(skip)
+            .take(limit)
+            .map(|(idx, e)| (self.first_id + idx as EntryId, e.clone()))
+            .collect())
+    }
+
+    fn get(&self, id: EntryId) -> io::Result<Option<LogEntry>> {
+        let Some(idx) = id.checked_sub(self.first_id) else {
+            return Ok(None);
+        };
+        Ok(self.entries.get(idx as usize).cloned())
+    }
+
+    fn raw_count(&self) -> io::Result<usize> {
+        Ok(self.entries.iter().filter(|e| e.is_raw()).count())
+    }
+
+    fn levels(&self) -> io::Result<BTreeMap<String, usize>> {
+        let mut levels = BTreeMap::new();
+        for entry in &self.entries {
+            *levels.entry(entry.level.name.to_string()).or_default() += 1;
+        }
+        Ok(levels)
+    }
+
+    fn redecode(&mut self, decoder: &Decoder) -> io::Result<usize> {
+        self.store()?;
+        let mut decoded = 0;
+        let mut entries = Vec::with_capacity(self.entries.len());
+        for mut entry in std::mem::take(&mut self.entries) {
+            let rest = redecode_entry(&mut entry, decoder);
+            entries.push(entry);
+            if let Some(rest) = rest {
+                decoded += 1;
+                entries.extend(rest);
+            }
+        }
+        self.entries = entries;
+        let Some(store) = &mut self.store else {
+            return Err(read_only());
+        };
+        store.rewrite(&self.entries)?;
+        Ok(decoded)
+    }
+
+    fn sync_if_due(&mut self) -> io::Result<Option<Duration>> {
+        let Some(store) = &mut self.store else {
+            return Ok(None);
+        };
+        store.sync_if_due()?;
+        Ok(store.dirty.then_some(store.sync_interval))
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        match &mut self.store {
+            Some(store) => store.sync(),
+            None => Ok(()),
+        }
+    }
+
+    /// Entries are in the order they arrived, so only the leading ones past
+    /// `max_age` are evicted, not the ones stuck behind a newer entry
+    fn evict(
+        &mut self,
+        retention: &RetentionSettings,
+        archive: Option<&Archive>,
+    ) -> io::Result<usize> {
+        let mut count = 0;
+        if let Some(max) = retention.max_entries {
+            count = count.max(self.entries.len().saturating_sub(max));
+        }
+        if let Some(cutoff) = retention.max_age.and_then(cutoff) {
+            let old = self
+                .entries
+                .iter()
+                .take_while(|e| e.timestamp < cutoff)
+                .count();
+            count = count.max(old);
+        }
+        let Some(store) = &mut self.store else {
+            return Err(read_only());
+        };
+        if let Some(max) = retention.max_size_mb {
+            count = count.max(store.entries_over(max * 1024 * 1024));
+        }
+        let count = count.min(self.entries.len());
+        if count == 0 {
+            return Ok(0);
+        }
+
+        store.drop_front(count, &mut |lines| match archive {
+            Some(archive) => archive.write(lines),
+            None => Ok(()),
+        })?;
+        self.entries.drain(..count);
+        self.first_id += count as EntryId;
+        Ok(count)
+    }
+}
+
+pub struct SegmentStore {
+    dir: PathBuf,
+    max_segment_size: u64,
+    sync_interval: Duration,
+    /// Oldest first, the last one is active and appended to
+    segments: VecDeque<Segment>,
+    file: File,
+    /// Something was written since the last sync
+    dirty: bool,
+    last_sync: Instant,
+}
+
+struct Segment {
+    seq: u64,
+    path: PathBuf,
+    full: bool,
+    entries: usize,
+    size: u64,
+}
+
+impl SegmentStore {
+    /// Opens the store in `dir` returning everything stored so far.
+    /// Damaged segments are moved aside and replaced with their readable lines.
+    pub fn open(
+        dir: &Path,
+        settings: &StorageSettings,
+        recoveries: &mut Vec<Recovery>,
+    ) -> io::Result<(Self, Vec<LogEntry>)> {
+        std::fs::create_dir_all(dir)?;
+        let mut segments = list_segments(dir)?;
+        // Everything before the last full segment has been rewritten into it
+        if let Some(idx) = segments.iter().rposition(|s| s.full) {
+            for old in segments.drain(..idx) {
+                std::fs::remove_file(old.path)?;
+            }
+        }
+
+        // Only the last one was being written to
+        let torn = match segments.last() {
+            Some(last) => truncate_partial_line(&last.path)?,
+            None => false,
+        };
+        let last = segments.len().saturating_sub(1);
+        let mut entries = Vec::new();
+        for (idx, segment) in segments.iter_mut().enumerate() {
+            let start = entries.len();
+            let read = read_segment(&segment.path, &mut entries)?;
+            if let Some(error) = read.error {
+                let moved_to = move_aside(&segment.path)?;
+                write_atomic(dir, &segment.path, &to_lines(&entries[start..])?)?;
+                recoveries.push(Recovery {
+                    file: segment.path.clone(),
+                    moved_to: Some(moved_to),
+                    error,
+                    salvaged: read.count,
+                    lost: Some(read.bad),
+                });
+            } else if torn && idx == last {
+                recoveries.push(Recovery {
+                    file: segment.path.clone(),
+                    moved_to: None,
+                    error: "Partially written last line was cut off".to_string(),
+                    salvaged: read.count,
+                    lost: Some(1),
+                });
+            }
+            segment.entries = read.count;
+            segment.size = std::fs::metadata(&segment.path)?.len();
+        }
+        if segments.is_empty() {
+            segments.push(create_segment(dir, 0, &[], false)?);
+        }
+
+        let active = segments.last().expect("There is at least one segment");
+        let store = SegmentStore {
+            dir: dir.to_path_buf(),
+            max_segment_size: settings.segment_size_mb.max(1) * 1024 * 1024,
+            sync_interval: Duration::from_millis(settings.sync_interval_ms),
+            file: OpenOptions::new().append(true).open(&active.path)?,
+            segments: segments.into(),
+            dirty: false,
+            last_sync: Instant::now(),
+        };
+        Ok((store, entries))
+    }
+
+    /// Once this returns the entry survives the app crashing, and after
+    /// the next sync an OS crash too
+    pub fn append(&mut self, entry: &LogEntry) -> io::Result<()> {
+        let mut line = serde_json::to_vec(entry).map_err(io::Error::other)?;
+        line.push(b'\n');
+        let active = self.active();
+        if active.size > 0 && active.size + line.len() as u64 > self.max_segment_size {
+            self.rollover()?;
+        }
+        // One write per line, so the line is either there or cut off
+        self.file.write_all(&line)?;
+        let active = self.active_mut();
+        active.size += line.len() as u64;
+        active.entries += 1;
+        self.dirty = true;
+        self.sync_if_due()
+    }
+
+    /// Replaces everything stored with `entries`
+    pub fn rewrite(&mut self, entries: &[LogEntry]) -> io::Result<()> {
+        self.sync()?;
+        let data = to_lines(entries)?;
+        let mut segment = create_segment(&self.dir, self.active().seq + 1, &data, true)?;
+        segment.entries = entries.len();
+        // Leftovers would be removed on next open anyway
+        for old in self.segments.drain(..) {
+            std::fs::remove_file(old.path)?;
+        }
+        self.switch_to(segment)
+    }
+
+    /// Total size of all segments
+    pub fn size(&self) -> u64 {
+        self.segments.iter().map(|s| s.size).sum()
+    }
+
+    /// How many of the oldest entries have to go for the store to fit
+    /// into `max_size`. Only whole segments are counted, never the active one.
+    pub fn entries_over(&self, max_size: u64) -> usize {
+        let mut size = self.size();
+        let mut entries = 0;
+        for segment in self.segments.range(..self.segments.len() - 1) {
+            if size <= max_size {
+                break;
+            }
+            size -= segment.size;
+            entries += segment.entries;
+        }
+        entries
+    }
+
+    /// Removes `count` oldest entries, passing their lines to `evicted`
+    pub fn drop_front(
+        &mut self,
+        mut count: usize,
+        evicted: &mut dyn FnMut(&[u8]) -> io::Result<()>,
+    ) -> io::Result<()> {
+        self.sync()?;
+        while count > 0 {
+            let oldest = self
+                .segments
+                .front()
+                .expect("There is at least one segment");
+            if oldest.entries <= count && self.segments.len() > 1 {
+                let data = std::fs::read(&oldest.path)?;
+                evicted(&data[..complete_len(&data)])?;
+                std::fs::remove_file(&oldest.path)?;
+                count -= oldest.entries;
+                self.segments.pop_front();
+                continue;
+            }
+            // Keep the rest of the segment, replacing it atomically
+            let data = std::fs::read(&oldest.path)?;
+            let data = &data[..complete_len(&data)];
+            let split = data
+                .iter()
+                .enumerate()
+                .filter(|(_, &b)| b == b'\n')
+                .nth(count - 1)
+                .map_or(data.len(), |(idx, _)| idx + 1);
+            evicted(&data[..split])?;
+            write_atomic(&self.dir, &oldest.path, &data[split..])?;
+
+            let active = self.segments.len() == 1;
+            let oldest = self
+                .segments
+                .front_mut()
+                .expect("There is at least one segment");
+            oldest.entries = oldest.entries.saturating_sub(count);
+            oldest.size = (data.len() - split) as u64;
+            if active {
+                self.file = OpenOptions::new().append(true).open(&oldest.path)?;
+            }
+            count = 0;
+        }
+        Ok(())
+    }
+
+    pub fn sync_if_due(&mut self) -> io::Result<()> {
+        if self.dirty && self.last_sync.elapsed() >= self.sync_interval {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    pub fn sync(&mut self) -> io::Result<()> {
+        if self.dirty {
+            self.file.sync_data()?;
+            self.dirty = false;
+        }
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+
+    fn active(&self) -> &Segment {
+        self.segments.back().expect("There is at least one segment")
+    }
+
+    fn active_mut(&mut self) -> &mut Segment {
+        self.segments
+            .back_mut()
+            .expect("There is at least one segment")
+    }
+
+    fn rollover(&mut self) -> io::Result<()> {
+        self.sync()?;
+        let segment = create_segment(&self.dir, self.active().seq + 1, &[], false)?;
+        self.switch_to(segment)
+    }
+
+    fn switch_to(&mut self, segment: Segment) -> io::Result<()> {
+        self.file = OpenOptions::new().append(true).open(&segment.path)?;
+        self.segments.push_back(segment);
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+fn create_segment(dir: &Path, seq: u64, data: &[u8], full: bool) -> io::Result<Segment> {
+    let path = dir.join(format!("{seq:08}.{}", if full { FULL_EXT } else { EXT }));
+    write_atomic(dir, &path, data)?;
+    Ok(Segment {
+        seq,
+        path,
+        full,
+        entries: 0,
+        size: data.len() as u64,
+    })
+}
+
+/// Writes the file in `dir` under temporary name and renames it into place
+fn write_atomic(dir: &Path, path: &Path, data: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension(TMP_EXT);
+    let mut file = File::create(&tmp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path)?;
+    sync_dir(dir)
+}
+
+fn to_lines(entries: &[LogEntry]) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    for entry in entries {
+        serde_json::to_writer(&mut data, entry).map_err(io::Error::other)?;
+        data.push(b'\n');
+    }
+    Ok(data)
+}
+
+/// Makes the rename itself durable. Not possible (nor needed) on Windows.
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+/// Segments sorted by sequence number. Unfinished temporary files are removed.
+fn list_segments(dir: &Path) -> io::Result<Vec<Segment>> {
+    let mut segments = Vec::new();
+    for item in std::fs::read_dir(dir)? {
+        let path = item?.path();
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        if name.ends_with(TMP_EXT) {
+            std::fs::remove_file(&path)?;
+            continue;
+        }
+        let (seq, full) = if let Some(seq) = name.strip_suffix(&format!(".{FULL_EXT}")) {
+            (seq, true)
+        } else if let Some(seq) = name.strip_suffix(&format!(".{EXT}")) {
+            (seq, false)
+        } else {
+            continue;
+        };
+        let Ok(seq) = seq.parse() else {
+            continue;
+        };
+        segments.push(Segment {
+            seq,
+            path,
+            full,
+            entries: 0,
+            size: 0,
+        });
+    }
+    segments.sort_by_key(|s| s.seq);
+    Ok(segments)
+}
+
+/// Length of the data up to and including the last line end
+fn complete_len(data: &[u8]) -> usize {
+    data.iter()
+        .rposition(|&b| b == b'\n')
+        .map_or(0, |idx| idx + 1)
+}
+
+/// Cuts off whatever follows the last complete line, returns whether there
+/// was anything
+fn truncate_partial_line(path: &Path) -> io::Result<bool> {
+    let data = std::fs::read(path)?;
+    let complete = complete_len(&data);
+    if complete == data.len() {
+        return Ok(false);
+    }
+    let file = OpenOptions::new().write(true).open(path)?;
+    file.set_len(complete as u64)?;
+    file.sync_all()?;
+    Ok(true)
+}
+
+/// Everything stored in `dir`, for reading it without opening the store.
+/// Damaged segments are reported but left alone.
+pub fn read_all(dir: &Path, recoveries: &mut Vec<Recovery>) -> io::Result<Vec<LogEntry>> {
+    if !std::fs::exists(dir)? {
+        return Ok(Vec::new());
+    }
+    let mut segments = list_segments(dir)?;
+    if let Some(idx) = segments.iter().rposition(|s| s.full) {
+        segments.drain(..idx);
+    }
+    let mut entries = Vec::new();
+    for segment in segments {
+        let read = read_segment(&segment.path, &mut entries)?;
+        if let Some(error) = read.error {
+            recoveries.push(Recovery {
+                file: segment.path,
+                moved_to: None,
+                error,
+                salvaged: read.count,
+                lost: Some(read.bad),
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// Outcome of reading a segment
+struct SegmentRead {
+    count: usize,
+    /// Lines that aren't entries
+    bad: usize,
+    /// What was wrong with the first of them
+    error: Option<String>,
+}
+
+/// Reads entries, skipping lines that fail to parse
+fn read_segment(path: &Path, entries: &mut Vec<LogEntry>) -> io::Result<SegmentRead> {
+    let data = std::fs::read(path)?;
+    let mut read = SegmentRead {
+        count: 0,
+        bad: 0,
+        error: None,
+    };
+    // Partially written last line is not an entry (yet)
+    for (idx, line) in data[..complete_len(&data)]
+        .split(|&b| b == b'\n')
+        .enumerate()
+    {
+        if line.trim_ascii().is_empty() {
+            continue;
+        }
+        match serde_json::from_slice(line) {
+            Ok(entry) => {
+                entries.push(entry);
+                read.count += 1;
+            }
+            Err(e) => {
+                read.bad += 1;
+                read.error
+                    .get_or_insert_with(|| format!("Line {}: {e}", idx + 1));
+            }
+        }
+    }
+    Ok(read)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::logs::tests::{entry, TempDataDir};
+
+    #[test]
+    fn cuts_off_torn_last_line() {
+        let dir = TempDataDir::new("torn-segment");
+        let segments = segments_dir(&dir.0);
+        std::fs::create_dir_all(&segments).unwrap();
+        let mut data = to_lines(&[entry("first"), entry("second")]).unwrap();
+        let complete = data.len() as u64;
+        data.extend_from_slice(br#"{"timestamp":"2024-01-01T00:00:00Z","lev"#);
+        let path = segments.join(format!("{:08}.{EXT}", 0));
+        std::fs::write(&path, &data).unwrap();
+
+        let mut recoveries = Vec::new();
+        let settings = StorageSettings::default();
+        let (mut store, entries) =
+            SegmentStore::open(&segments, &settings, &mut recoveries).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), complete);
+        assert_eq!(recoveries.len(), 1);
+        assert_eq!(recoveries[0].file, path);
+        assert_eq!(recoveries[0].moved_to, None);
+        assert_eq!((recoveries[0].salvaged, recoveries[0].lost), (2, Some(1)));
+
+        store.append(&entry("third")).unwrap();
+        store.sync().unwrap();
+        drop(store);
+        let mut recoveries = Vec::new();
+        let (_, entries) = SegmentStore::open(&segments, &settings, &mut recoveries).unwrap();
+        let messages: Vec<_> = entries.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, ["first", "second", "third"]);
+        assert!(recoveries.is_empty());
+    }
+}
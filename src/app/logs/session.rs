@@ -0,0 +1,176 @@
+//! Entries are grouped into sessions. Each one is stored in its own
+//! directory under `sessions/`, named after its start time, next to a
+//! `session.json` describing it. The description is kept up to date while
+//! the session is recorded, so the browser doesn't have to open the stores.
+
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use time::{macros::format_description, OffsetDateTime};
+
+use crate::{app::config::StorageBackend, trace_err};
+
+use super::{legacy_file, logs_dir, segments_dir, sqlite_file};
+
+const INFO_FILE: &str = "session.json";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SessionInfo {
+    /// Name of the directory
+    #[serde(skip)]
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub started: OffsetDateTime,
+    /// Unset while recorded, or if the app didn't get to close it
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub ended: Option<OffsetDateTime>,
+    #[serde(default)]
+    pub entries: usize,
+    /// Entries per level name
+    #[serde(default)]
+    pub levels: BTreeMap<String, usize>,
+    /// File the entries were imported from, such sessions are never
+    /// recorded into
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub imported: Option<PathBuf>,
+    /// How the entries are stored. Unset for sessions from before that was
+    /// noted down, and for new ones until they're first opened.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<StorageBackend>,
+}
+
+impl SessionInfo {
+    /// Creates the directory of a new session
+    pub fn create(name: &str) -> io::Result<SessionInfo> {
+        let started = OffsetDateTime::now_utc();
+        let base = started
+            .format(format_description!(
+                "[year][month][day]-[hour][minute][second]"
+            ))
+            .map_err(io::Error::other)?;
+        let mut id = base.clone();
+        let mut n = 1;
+        while std::fs::exists(sessions_dir().join(&id))? {
+            n += 1;
+            id = format!("{base}-{n}");
+        }
+        std::fs::create_dir_all(sessions_dir().join(&id))?;
+        let info = SessionInfo {
+            id,
+            name: name.trim().to_string(),
+            started,
+            ended: None,
+            entries: 0,
+            levels: BTreeMap::new(),
+            imported: None,
+            backend: None,
+        };
+        info.save()?;
+        Ok(info)
+    }
+
+    /// All sessions, newest first. Broken ones are skipped.
+    pub fn list() -> io::Result<Vec<SessionInfo>> {
+        migrate()?;
+        let mut sessions = Vec::new();
+        for item in std::fs::read_dir(sessions_dir())? {
+            let path = item?.path();
+            if path.is_dir() {
+                if let Ok(info) = trace_err!(SessionInfo::load(&path)) {
+                    sessions.push(info);
+                }
+            }
+        }
+        sessions.sort_by(|a, b| b.started.cmp(&a.started).then(b.id.cmp(&a.id)));
+        Ok(sessions)
+    }
+
+    /// Latest session entries were recorded into
+    pub fn latest() -> io::Result<Option<SessionInfo>> {
+        let sessions = SessionInfo::list()?;
+        Ok(sessions.into_iter().find(|s| s.imported.is_none()))
+    }
+
+    pub fn load(dir: &Path) -> io::Result<SessionInfo> {
+        let data = std::fs::read(dir.join(INFO_FILE))?;
+        let mut info: SessionInfo = serde_json::from_slice(&data).map_err(io::Error::other)?;
+        info.id = dir
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok(info)
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = self.dir().join(INFO_FILE);
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(
+            &tmp,
+            serde_json::to_vec_pretty(self).map_err(io::Error::other)?,
+        )?;
+        std::fs::rename(tmp, path)
+    }
+
+    /// Removes the session with all its entries
+    pub fn delete(&self) -> io::Result<()> {
+        std::fs::remove_dir_all(self.dir())
+    }
+
+    pub fn dir(&self) -> PathBuf {
+        sessions_dir().join(&self.id)
+    }
+
+    /// Name, or when it started for unnamed sessions
+    pub fn title(&self) -> String {
+        if !self.name.is_empty() {
+            return self.name.clone();
+        }
+        self.started
+            .format(crate::display_time_format())
+            .unwrap_or_else(|_| self.id.clone())
+    }
+}
+
+/// Before sessions everything was stored right in the logs directory,
+/// which becomes the first session
+fn migrate() -> io::Result<()> {
+    if std::fs::exists(sessions_dir())? {
+        return Ok(());
+    }
+    let old = logs_dir();
+    let sqlite = sqlite_file(&old);
+    let mut files = vec![
+        legacy_file(&old),
+        legacy_file(&old).with_extension("json.migrated"),
+        segments_dir(&old),
+        sqlite.with_extension("sqlite-wal"),
+        sqlite.with_extension("sqlite-shm"),
+        sqlite,
+    ];
+    files.retain(|f| f.exists());
+    if files.is_empty() {
+        return std::fs::create_dir_all(sessions_dir());
+    }
+
+    let info = SessionInfo::create("Before sessions")?;
+    for file in files {
+        let name = file.file_name().expect("Storage files have names");
+        std::fs::rename(&file, info.dir().join(name))?;
+    }
+    // Counts are filled in once it's opened
+    SessionInfo {
+        ended: Some(info.started),
+        ..info
+    }
+    .save()
+}
+
+fn sessions_dir() -> PathBuf {
+    logs_dir().join("sessions")
+}
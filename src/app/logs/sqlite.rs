@@ -0,0 +1,560 @@
+//! Entries in an SQLite database, for sessions too big to keep in memory.
+//!
+//! Columns the UI filters on are indexed, fields are stored as JSON to be
+//! queried with JSON paths. The rest of the entry is kept as JSON too.
+
+use std::{
+    collections::BTreeMap,
+    io,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use rusqlite::{
+    functions::FunctionFlags,
+    params, params_from_iter,
+    types::{Value as SqlValue, ValueRef},
+    Connection, ErrorCode, OpenFlags, OptionalExtension, Row,
+};
+use serde_json::Value;
+use time::OffsetDateTime;
+
+use crate::{
+    app::{
+        config::{RetentionSettings, StorageSettings},
+        filter::{Filter, Term},
+    },
+    types::{
+        decode::Decoder,
+        json_log::{LogEntry, Origin},
+    },
+};
+
+use super::{
+    archive::Archive, cutoff, move_aside, read_legacy, redecode_entry, segment, segments_dir,
+    sqlite_file, EntryId, LogStore, Recovery,
+};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS entries (
+    id INTEGER PRIMARY KEY,
+    -- Unix time in nanoseconds
+    timestamp INTEGER NOT NULL,
+    level TEXT NOT NULL,
+    severity INTEGER NOT NULL,
+    target TEXT,
+    message TEXT NOT NULL,
+    fields TEXT NOT NULL,
+    raw INTEGER NOT NULL,
+    -- Everything else
+    entry TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS entries_timestamp ON entries (timestamp);
+-- Filters compare ignoring case, which case sensitive indexes don't help
+DROP INDEX IF EXISTS entries_level;
+DROP INDEX IF EXISTS entries_target;
+DROP INDEX IF EXISTS entries_level_nocase;
+DROP INDEX IF EXISTS entries_target_nocase;
+CREATE INDEX IF NOT EXISTS entries_level_fold ON entries (fold(level));
+CREATE INDEX IF NOT EXISTS entries_target_fold ON entries (fold(target));
+CREATE INDEX IF NOT EXISTS entries_raw ON entries (raw) WHERE raw;
+";
+
+const COLUMNS: &str = "id, message, fields, entry";
+
+pub struct SqliteLogs {
+    conn: Connection,
+    sync_interval: Duration,
+    dirty: bool,
+    last_sync: Instant,
+}
+
+impl SqliteLogs {
+    /// Opens the database of the session in `dir`, importing entries
+    /// stored the other ways when it's new. A damaged database is moved
+    /// aside and replaced with a new one holding the rows still readable.
+    pub fn open(
+        dir: &Path,
+        settings: &StorageSettings,
+        recoveries: &mut Vec<Recovery>,
+    ) -> io::Result<Self> {
+        let path = sqlite_file(dir);
+        let (conn, count) = match connect(&path) {
+            Ok(opened) => opened,
+            Err(e) if is_corrupt(&e) => {
+                let (entries, recovery) = salvage(&path, e)?;
+                recoveries.push(recovery);
+                let mut logs = SqliteLogs::new(connect(&path).map_err(sql)?.0, settings);
+                logs.import(&entries)?;
+                return Ok(logs);
+            }
+            Err(e) => return Err(sql(e)),
+        };
+        let mut logs = SqliteLogs::new(conn, settings);
+        if count == 0 {
+            let entries = match read_legacy(dir, recoveries, true)? {
+                Some(entries) => entries,
+                None => segment::read_all(&segments_dir(dir), recoveries)?,
+            };
+            logs.import(&entries)?;
+        }
+        Ok(logs)
+    }
+
+    fn new(conn: Connection, settings: &StorageSettings) -> Self {
+        SqliteLogs {
+            conn,
+            sync_interval: Duration::from_millis(settings.sync_interval_ms),
+            dirty: false,
+            last_sync: Instant::now(),
+        }
+    }
+
+    /// Writing fails on the database level
+    pub fn open_read_only(dir: &Path) -> io::Result<Self> {
+        let conn = Connection::open_with_flags(sqlite_file(dir), OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .map_err(sql)?;
+        add_functions(&conn).map_err(sql)?;
+        Ok(SqliteLogs {
+            conn,
+            sync_interval: Duration::ZERO,
+            dirty: false,
+            last_sync: Instant::now(),
+        })
+    }
+
+    pub fn import(&mut self, entries: &[LogEntry]) -> io::Result<()> {
+        let tx = self.conn.transaction().map_err(sql)?;
+        for entry in entries {
+            write(&tx, None, entry)?;
+        }
+        tx.commit().map_err(sql)?;
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Deletes entries matching `clause`, archiving them first if asked to
+    fn delete(
+        &mut self,
+        clause: &str,
+        args: &[SqlValue],
+        archive: Option<&Archive>,
+    ) -> io::Result<usize> {
+        let tx = self.conn.transaction().map_err(sql)?;
+        if let Some(archive) = archive {
+            let mut stmt = tx
+                .prepare(&format!(
+                    "SELECT {COLUMNS} FROM entries WHERE {clause} ORDER BY id"
+                ))
+                .map_err(sql)?;
+            let rows = stmt
+                .query_map(params_from_iter(args), read_row)
+                .map_err(sql)?;
+            let entries = rows
+                .map(|row| row.map_err(sql)?.map(|(_, entry)| entry))
+                .collect::<io::Result<Vec<_>>>()?;
+            archive.write_entries(&entries)?;
+        }
+        let deleted = tx
+            .execute(
+                &format!("DELETE FROM entries WHERE {clause}"),
+                params_from_iter(args),
+            )
+            .map_err(sql)?;
+        tx.commit().map_err(sql)?;
+        if deleted > 0 {
+            self.dirty = true;
+        }
+        Ok(deleted)
+    }
+
+    /// Bytes taken by the data, free pages are reused before the file grows
+    fn size(&self) -> io::Result<u64> {
+        self.conn
+            .query_row(
+                "SELECT (page_count - freelist_count) * page_size
+                 FROM pragma_page_count, pragma_freelist_count, pragma_page_size",
+                [],
+                |row| row.get(0),
+            )
+            .map_err(sql)
+    }
+}
+
+impl LogStore for SqliteLogs {
+    fn append(&mut self, entry: LogEntry) -> io::Result<()> {
+        write(&self.conn, None, &entry)?;
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn count(&self, filter: &Filter) -> io::Result<usize> {
+        let (clause, args) = where_clause(filter);
+        self.conn
+            .prepare_cached(&format!("SELECT count(*) FROM entries WHERE {clause}"))
+            .and_then(|mut stmt| stmt.query_row(params_from_iter(args), |row| row.get(0)))
+            .map_err(sql)
+    }
+
+    fn page(
+        &self,
+        filter: &Filter,
+        after: Option<EntryId>,
+        skip: usize,
+        limit: usize,
+    ) -> io::Result<Vec<(EntryId, LogEntry)>> {
+        let (clause, mut args) = where_clause(filter);
+        args.push(SqlValue::Integer(after.map_or(-1, |id| id as i64)));
+        args.push(SqlValue::Integer(limit as i64));
+        // Only skips when jumping past pages not looked up yet
+        args.push(SqlValue::Integer(skip as i64));
+        let mut stmt = self
+            .conn
+            .prepare_cached(&format!(
+                "SELECT {COLUMNS} FROM entries WHERE {clause} AND id > ?
+                 ORDER BY id LIMIT ? OFFSET ?"
+            ))
+            .map_err(sql)?;
+        let rows = stmt
+            .query_map(params_from_iter(args), read_row)
+            .map_err(sql)?;
+        rows.map(|row| row.map_err(sql)?).collect()
+    }
+
+    fn get(&self, id: EntryId) -> io::Result<Option<LogEntry>> {
+        let row = self
+            .conn
+            .prepare_cached(&format!("SELECT {COLUMNS} FROM entries WHERE id = ?"))
+            .and_then(|mut stmt| stmt.query_row([id], read_row).optional())
+            .map_err(sql)?;
+        row.transpose().map(|row| row.map(|(_, entry)| entry))
+    }
+
+    fn raw_count(&self) -> io::Result<usize> {
+        self.conn
+            .query_row("SELECT count(*) FROM entries WHERE raw", [], |row| {
+                row.get(0)
+            })
+            .map_err(sql)
+    }
+
+    fn levels(&self) -> io::Result<BTreeMap<String, usize>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT level, count(*) FROM entries GROUP BY level")
+            .map_err(sql)?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(sql)?;
+        rows.map(|row| row.map_err(sql)).collect()
+    }
+
+    fn redecode(&mut self, decoder: &Decoder) -> io::Result<usize> {
+        let tx = self.conn.transaction().map_err(sql)?;
+        let raw = {
+            let mut stmt = tx
+                .prepare(&format!("SELECT {COLUMNS} FROM entries WHERE raw"))
+                .map_err(sql)?;
+            let rows = stmt.query_map([], read_row).map_err(sql)?;
+            rows.map(|row| row.map_err(sql)?)
+                .collect::<io::Result<Vec<_>>>()?
+        };
+        let mut decoded = 0;
+        for (id, mut entry) in raw {
+            let rest = redecode_entry(&mut entry, decoder);
+            write(&tx, Some(id), &entry)?;
+            if let Some(rest) = rest {
+                decoded += 1;
+                // Other lines of a batch go after what's recorded by now
+                for entry in rest {
+                    write(&tx, None, &entry)?;
+                }
+            }
+        }
+        tx.commit().map_err(sql)?;
+        self.dirty = true;
+        Ok(decoded)
+    }
+
+    fn sync_if_due(&mut self) -> io::Result<Option<Duration>> {
+        if self.dirty && self.last_sync.elapsed() >= self.sync_interval {
+            self.sync()?;
+        }
+        Ok(self.dirty.then_some(self.sync_interval))
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        if self.dirty {
+            // Syncs the log before moving it into the database
+            self.conn
+                .query_row("PRAGMA wal_checkpoint(PASSIVE)", [], |_| Ok(()))
+                .map_err(sql)?;
+            self.dirty = false;
+        }
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+
+    fn evict(
+        &mut self,
+        retention: &RetentionSettings,
+        archive: Option<&Archive>,
+    ) -> io::Result<usize> {
+        let mut evicted = 0;
+        if let Some(max) = retention.max_entries {
+            // Ids only grow, so everything up to the newest one past the limit
+            evicted += self.delete(
+                "id <= (SELECT id FROM entries ORDER BY id DESC LIMIT 1 OFFSET ?)",
+                &[SqlValue::Integer(max as i64)],
+                archive,
+            )?;
+        }
+        if let Some(cutoff) = retention.max_age.and_then(cutoff) {
+            let timestamp = Columns::timestamp(cutoff);
+            evicted += self.delete("timestamp < ?", &[SqlValue::Integer(timestamp)], archive)?;
+        }
+        if let Some(max) = retention.max_size_mb.map(|mb| mb * 1024 * 1024) {
+            let size = self.size()?;
+            if size > max {
+                // Guessed from the average entry size. Pages only free up
+                // once all their rows are gone, so the size lags behind and
+                // deleting until it's met would take far too much. Anything
+                // still over goes next time.
+                let count = self.count(&Filter::default())? as u64;
+                let over = (size - max).saturating_mul(count).div_ceil(size).max(1);
+                evicted += self.delete(
+                    "id IN (SELECT id FROM entries ORDER BY id LIMIT ?)",
+                    &[SqlValue::Integer(over as i64)],
+                    archive,
+                )?;
+            }
+        }
+        if evicted > 0 {
+            // Gives the freed pages back to the file system
+            self.conn
+                .execute_batch("PRAGMA incremental_vacuum;")
+                .map_err(sql)?;
+        }
+        Ok(evicted)
+    }
+}
+
+/// Opens the database, creating the schema if needed. Returns it with the
+/// number of entries, counting which reads the whole table.
+fn connect(path: &Path) -> rusqlite::Result<(Connection, usize)> {
+    let conn = Connection::open(path)?;
+    add_functions(&conn)?;
+    // Committed entries survive the app crashing, syncing is up to us.
+    // Auto vacuum can only be turned on before the first table exists.
+    conn.execute_batch(
+        "PRAGMA auto_vacuum = INCREMENTAL;
+         PRAGMA journal_mode = WAL;
+         PRAGMA synchronous = NORMAL;",
+    )?;
+    conn.execute_batch(SCHEMA)?;
+    let count = conn.query_row("SELECT count(*) FROM entries", [], |row| row.get(0))?;
+    Ok((conn, count))
+}
+
+/// `fold(text)` lowercases the way [`Filter`] does, SQLite's own `lower`
+/// only knows ASCII. Indexes use it, so it's needed on every connection.
+fn add_functions(conn: &Connection) -> rusqlite::Result<()> {
+    conn.create_scalar_function(
+        "fold",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            Ok(match ctx.get_raw(0) {
+                ValueRef::Text(text) => Some(String::from_utf8_lossy(text).to_lowercase()),
+                _ => None,
+            })
+        },
+    )
+}
+
+fn is_corrupt(e: &rusqlite::Error) -> bool {
+    matches!(
+        e.sqlite_error_code(),
+        Some(ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase)
+    )
+}
+
+/// Moves the damaged database aside and reads rows from it in order until
+/// the first one that fails
+fn salvage(path: &Path, error: rusqlite::Error) -> io::Result<(Vec<LogEntry>, Recovery)> {
+    let moved_to = move_aside(path)?;
+    // The log holds committed rows not yet in the database itself
+    for suffix in ["-wal", "-shm"] {
+        let mut side = path.as_os_str().to_os_string();
+        side.push(suffix);
+        if std::fs::exists(&side)? {
+            let mut to = moved_to.as_os_str().to_os_string();
+            to.push(suffix);
+            std::fs::rename(side, to)?;
+        }
+    }
+
+    let mut entries = Vec::new();
+    let mut lost = Some(0);
+    let read = Connection::open(&moved_to).and_then(|conn| {
+        let mut stmt = conn.prepare(&format!("SELECT {COLUMNS} FROM entries ORDER BY id"))?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            match read_row(row)? {
+                Ok((_, entry)) => entries.push(entry),
+                Err(_) => lost = lost.map(|n| n + 1),
+            }
+        }
+        Ok(())
+    });
+    // Whatever follows the failed row is lost too, but there's no telling
+    // how much of it there was
+    if read.is_err() {
+        lost = None;
+    }
+    let recovery = Recovery {
+        file: path.to_path_buf(),
+        moved_to: Some(moved_to),
+        error: error.to_string(),
+        salvaged: entries.len(),
+        lost,
+    };
+    Ok((entries, recovery))
+}
+
+/// Entry split the way it is stored
+struct Columns {
+    timestamp: i64,
+    fields: String,
+    rest: String,
+}
+
+impl Columns {
+    fn new(entry: &LogEntry) -> io::Result<Self> {
+        let mut rest = serde_json::to_value(entry).map_err(io::Error::other)?;
+        if let Some(obj) = rest.as_object_mut() {
+            obj.remove("message");
+            obj.remove("fields");
+        }
+        Ok(Columns {
+            timestamp: Columns::timestamp(entry.timestamp),
+            fields: serde_json::to_string(&entry.fields).map_err(io::Error::other)?,
+            rest: rest.to_string(),
+        })
+    }
+
+    fn timestamp(timestamp: OffsetDateTime) -> i64 {
+        timestamp
+            .unix_timestamp_nanos()
+            .clamp(i64::MIN as i128, i64::MAX as i128) as i64
+    }
+}
+
+/// Inserts new entry, or replaces the one with given id
+fn write(conn: &Connection, id: Option<EntryId>, entry: &LogEntry) -> io::Result<()> {
+    let row = Columns::new(entry)?;
+    conn.prepare_cached(
+        "INSERT OR REPLACE INTO entries
+         (id, timestamp, level, severity, target, message, fields, raw, entry)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .and_then(|mut stmt| {
+        stmt.execute(params![
+            id,
+            row.timestamp,
+            entry.level.name,
+            entry.level.severity,
+            entry.source.target,
+            entry.message,
+            row.fields,
+            entry.is_raw(),
+            row.rest
+        ])
+    })
+    .map_err(sql)?;
+    Ok(())
+}
+
+/// Reads [`COLUMNS`] back into an entry
+fn read_row(row: &Row) -> rusqlite::Result<io::Result<(EntryId, LogEntry)>> {
+    let id: EntryId = row.get(0)?;
+    let message: String = row.get(1)?;
+    let fields: String = row.get(2)?;
+    let rest: String = row.get(3)?;
+    Ok(join_entry(message, &fields, &rest).map(|entry| (id, entry)))
+}
+
+fn join_entry(message: String, fields: &str, rest: &str) -> io::Result<LogEntry> {
+    let mut value: Value = serde_json::from_str(rest).map_err(io::Error::other)?;
+    let fields: Value = serde_json::from_str(fields).map_err(io::Error::other)?;
+    if let Some(obj) = value.as_object_mut() {
+        obj.insert("message".into(), Value::String(message));
+        obj.insert("fields".into(), fields);
+    }
+    serde_json::from_value(value).map_err(io::Error::other)
+}
+
+/// Translates the filter into SQL doing the same as [`Filter::matches`]
+fn where_clause(filter: &Filter) -> (String, Vec<SqlValue>) {
+    let mut args = Vec::new();
+    let mut conditions = Vec::new();
+    for term in filter.terms() {
+        match term {
+            Term::Equals { key, value } => {
+                let column = column(key, &mut args);
+                args.push(SqlValue::Text(value.clone()));
+                conditions.push(format!("fold({column}) = ?"));
+            }
+            Term::Contains { key, value } => {
+                let column = column(key, &mut args);
+                args.push(SqlValue::Text(value.clone()));
+                conditions.push(format!("instr(fold({column}), ?) > 0"));
+            }
+            Term::Text(text) => {
+                args.push(SqlValue::Text(text.clone()));
+                conditions.push("instr(fold(message), ?) > 0".to_string());
+            }
+        }
+    }
+    if conditions.is_empty() {
+        return ("1".to_string(), args);
+    }
+    (conditions.join(" AND "), args)
+}
+
+/// Column or JSON path for a filter key, as text. Paths are passed as
+/// arguments. Like in [`Filter`], strings are taken as they are and other
+/// JSON values as JSON.
+fn column(key: &str, args: &mut Vec<SqlValue>) -> String {
+    let (column, path) = if let Some(key) = key.strip_prefix("origin.") {
+        let path = match key.strip_prefix("headers.") {
+            Some(header) => format!("$.origin.headers.{}", quote(header)),
+            None if key == "type" => "$.origin.kind".to_string(),
+            None if Origin::KEYS.contains(&key) => format!("$.origin.{}", quote(key)),
+            None => return "NULL".to_string(),
+        };
+        ("entry", path)
+    } else {
+        match key {
+            "level" | "message" | "target" => return key.to_string(),
+            "file" => ("entry", "$.source.file".to_string()),
+            "source_id" => ("entry", "$.source_id".to_string()),
+            _ => {
+                let name = key.strip_prefix("fields.").unwrap_or(key);
+                ("fields", format!("$.{}", quote(name)))
+            }
+        }
+    };
+    args.extend([SqlValue::Text(path.clone()), SqlValue::Text(path.clone())]);
+    args.push(SqlValue::Text(path));
+    format!("CASE json_type({column}, ?) WHEN 'text' THEN {column} ->> ? ELSE {column} -> ? END")
+}
+
+/// Keys may contain dots, which mean nesting in JSON paths unless quoted
+fn quote(key: &str) -> String {
+    format!("\"{}\"", key.replace('"', ""))
+}
+
+fn sql(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e)
+}
@@ -1,38 +1,244 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    net::SocketAddr,
+    path::PathBuf,
+    str::FromStr,
+    sync::mpsc::Receiver,
+    time::{Duration, Instant},
+};
+
 use assets::Assets;
-use config::{RabbitMQSettings, Settings};
+use config::{
+    ConsumeMode, DecodeErrorAction, ExchangeKind, ExchangeSettings, HttpSettings,
+    RetentionSettings, Settings, StorageBackend, StreamOffset, SyslogSettings,
+};
 use eframe::egui;
+use egui::Color32;
 use egui_notify::Toasts;
-use logs::Logs;
+use import::{Import, ImportTarget, Progress};
+use instance::{InstanceLock, Request};
+use logs::{EntryId, Logs, Recovery, SessionInfo};
 
-use crate::worker::{Worker, WorkerHandle};
+use crate::{
+    trace_err,
+    types::{
+        decode::LogFormat,
+        json_log::LogEntry,
+        level::{LevelColor, LevelDefinition, LevelTable},
+        timestamp::TimestampParser,
+    },
+    worker::{source::SourceId, Command, ConnectionState, Worker, WorkerHandle},
+};
 
 mod assets;
 pub mod config;
-mod logs;
+mod filter;
+mod import;
+pub mod instance;
+pub(crate) mod logs;
 mod uis;
 
 struct SettingsState {
     show: bool,
+    rabbit_mq: bool,
     host: String,
     vhost: String,
     port: String,
     username: String,
     password: String,
+    mode: ConsumeMode,
+    queue: String,
+    declare_queue: bool,
+    exchange: String,
+    exchange_kind: ExchangeKind,
+    declare_exchange: bool,
+    /// One binding key per line
+    binding_keys: String,
+    stream: bool,
+    stream_offset: String,
+    prefetch: String,
+    tls: bool,
+    ca_file: String,
+    client_cert: String,
+    client_key: String,
+    skip_verify: bool,
+    format: LogFormat,
+    on_decode_error: DecodeErrorAction,
+    /// One format per line
+    time_formats: String,
+    storage_backend: StorageBackend,
+    max_entries: String,
+    max_age: String,
+    max_size_mb: String,
+    archive_evicted: bool,
+    new_session_on_launch: bool,
+    /// One path or glob per line
+    tail_paths: String,
+    tail_from_start: bool,
+    http_enabled: bool,
+    http_bind: String,
+    http_token: String,
+    http_max_body_mb: String,
+    syslog_enabled: bool,
+    syslog_bind: String,
+    syslog_udp: bool,
+    syslog_tcp: bool,
+    /// Level name with its light and dark theme colors
+    level_colors: Vec<(String, Color32, Color32)>,
 }
 
 impl SettingsState {
-    fn load(settings: &RabbitMQSettings) -> SettingsState {
+    fn load(all: &Settings, levels: &LevelTable) -> SettingsState {
+        let settings = &all.rabbit_mq;
+        let topology = &settings.topology;
+        let exchange = topology.exchange.clone().unwrap_or_default();
         SettingsState {
             show: false,
+            rabbit_mq: settings.enabled,
             host: settings.host.clone(),
             vhost: settings.vhost.clone(),
             port: settings.port.to_string(),
             username: settings.username.clone(),
             password: settings.password.clone(),
+            mode: topology.mode,
+            queue: topology.queue.clone(),
+            declare_queue: topology.declare_queue,
+            exchange: exchange.name,
+            exchange_kind: exchange.kind,
+            declare_exchange: exchange.declare,
+            binding_keys: topology.binding_keys.join("\n"),
+            stream: settings.stream.enabled,
+            stream_offset: settings.stream.offset.to_string(),
+            prefetch: settings.prefetch.to_string(),
+            tls: settings.tls.enabled,
+            ca_file: path_to_string(&settings.tls.ca_file),
+            client_cert: path_to_string(&settings.tls.client_cert),
+            client_key: path_to_string(&settings.tls.client_key),
+            skip_verify: settings.tls.skip_verify,
+            format: all.decode.format,
+            on_decode_error: all.decode.on_decode_error,
+            time_formats: all.decode.time_formats.join("\n"),
+            storage_backend: all.storage.backend,
+            max_entries: option_to_string(all.storage.retention.max_entries),
+            max_age: option_to_string(
+                all.storage
+                    .retention
+                    .max_age
+                    .map(humantime::format_duration),
+            ),
+            max_size_mb: option_to_string(all.storage.retention.max_size_mb),
+            archive_evicted: all.storage.retention.archive,
+            new_session_on_launch: all.storage.new_session_on_launch,
+            tail_paths: all.tail.paths.join("\n"),
+            tail_from_start: all.tail.from_start,
+            http_enabled: all.http.enabled,
+            http_bind: all.http.bind.clone(),
+            http_token: all.http.token.clone().unwrap_or_default(),
+            http_max_body_mb: all.http.max_body_mb.to_string(),
+            syslog_enabled: all.syslog.enabled,
+            syslog_bind: all.syslog.bind.clone(),
+            syslog_udp: all.syslog.udp,
+            syslog_tcp: all.syslog.tcp,
+            level_colors: levels
+                .levels()
+                .iter()
+                .map(|l| {
+                    (
+                        l.name.to_string(),
+                        levels.color(l, false),
+                        levels.color(l, true),
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+impl SettingsState {
+    fn retention(&self) -> Result<RetentionSettings, String> {
+        Ok(RetentionSettings {
+            max_entries: parse_optional(&self.max_entries)
+                .map_err(|_| "Max entries must be a number")?,
+            max_age: parse_optional(&self.max_age)
+                .map(|d: Option<humantime::Duration>| d.map(Into::into))
+                .map_err(|e| format!("Invalid max age: {e}"))?,
+            max_size_mb: parse_optional(&self.max_size_mb)
+                .map_err(|_| "Max size must be a number of megabytes")?,
+            archive: self.archive_evicted,
+        })
+    }
+
+    fn http(&self) -> Result<HttpSettings, String> {
+        let bind = self.http_bind.trim();
+        if self.http_enabled && bind.parse::<SocketAddr>().is_err() {
+            return Err(format!("Invalid HTTP address {bind}"));
+        }
+        let max_body_mb = match self.http_max_body_mb.trim().parse() {
+            Ok(mb) if mb > 0 => mb,
+            _ => return Err("Max body size must be a positive number of megabytes".to_string()),
+        };
+        let token = self.http_token.trim();
+        Ok(HttpSettings {
+            enabled: self.http_enabled,
+            bind: bind.to_string(),
+            token: (!token.is_empty()).then(|| token.to_string()),
+            max_body_mb,
+        })
+    }
+
+    fn syslog(&self) -> Result<SyslogSettings, String> {
+        let bind = self.syslog_bind.trim();
+        if self.syslog_enabled {
+            if bind.parse::<SocketAddr>().is_err() {
+                return Err(format!("Invalid syslog address {bind}"));
+            }
+            if !self.syslog_udp && !self.syslog_tcp {
+                return Err("Syslog needs UDP or TCP".to_string());
+            }
         }
+        Ok(SyslogSettings {
+            enabled: self.syslog_enabled,
+            bind: bind.to_string(),
+            udp: self.syslog_udp,
+            tcp: self.syslog_tcp,
+        })
     }
 }
 
+/// Empty means unset
+fn parse_optional<T: FromStr>(s: &str) -> Result<Option<T>, T::Err> {
+    let s = s.trim();
+    (!s.is_empty()).then(|| s.parse()).transpose()
+}
+
+fn option_to_string<T: ToString>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+fn path_to_string(path: &Option<PathBuf>) -> String {
+    path.as_ref()
+        .map(|p| p.display().to_string())
+        .unwrap_or_default()
+}
+
+fn string_to_path(s: &str) -> Option<PathBuf> {
+    let s = s.trim();
+    (!s.is_empty()).then(|| PathBuf::from(s))
+}
+
+/// How long a frame may spend storing imported entries
+const IMPORT_FRAME_TIME: Duration = Duration::from_millis(20);
+
+/// Undecodable messages are counted up between toasts about them
+const UNDECODABLE_TOAST_INTERVAL: Duration = Duration::from_secs(5);
+
+struct ImportState {
+    show: bool,
+    /// One file per line
+    paths: String,
+    target: ImportTarget,
+}
+
 #[derive(Default)]
 struct TableState {
     as_countdown: bool,
@@ -40,41 +246,328 @@ struct TableState {
 
 struct UiState {
     settings_state: SettingsState,
+    levels: LevelTable,
+    /// Last reported connection state of every source and when we got it
+    connections: BTreeMap<SourceId, (ConnectionState, Instant)>,
+    /// Offset typed into the replay box
+    replay_from: String,
+    /// Raw entries should be decoded again on next update
+    redecode: bool,
+    filter: String,
+    /// Entry shown in the details panel
+    selected: Option<EntryId>,
+    /// Past sessions, while the browser is open
+    sessions: Option<Vec<SessionInfo>>,
+    /// Name for the next session started from the browser
+    session_name: String,
+    /// Session the browser asks to confirm deleting
+    deleting: Option<String>,
+    /// Damaged files found so far, shown until dismissed
+    recoveries: Vec<Recovery>,
+    import: ImportState,
+    /// Files to import after the running import
+    queued_imports: VecDeque<(PathBuf, ImportTarget)>,
+    /// Running import should be stopped on next update
+    cancel_import: bool,
+    /// Undecodable messages kept since the last toast about them, and
+    /// when that was
+    undecodable: (usize, Option<Instant>),
     table_state: TableState,
     toasts: Toasts,
 }
 
 impl UiState {
     fn load(setttings: &Settings) -> Self {
+        let mut toasts = Toasts::default();
+        let levels = setttings.level_table().unwrap_or_else(|e| {
+            toasts.error(format!("Ignoring level settings: {e}"));
+            LevelTable::default()
+        });
         UiState {
-            settings_state: SettingsState::load(&setttings.rabbit_mq),
+            settings_state: SettingsState::load(setttings, &levels),
+            levels,
+            connections: BTreeMap::new(),
+            replay_from: String::new(),
+            redecode: false,
+            filter: String::new(),
+            selected: None,
+            sessions: None,
+            session_name: String::new(),
+            deleting: None,
+            recoveries: Vec::new(),
+            import: ImportState {
+                show: false,
+                paths: String::new(),
+                target: ImportTarget::NewSession,
+            },
+            queued_imports: VecDeque::new(),
+            cancel_import: false,
+            undecodable: (0, None),
             table_state: Default::default(),
-            toasts: Toasts::default(),
+            toasts,
         }
     }
 }
 
 pub struct App {
     assets: Assets,
-    state: std::cell::Cell<UiState>,
+    state: UiState,
+    /// Session entries are recorded to
     logs: Logs,
-    w_handle: WorkerHandle,
-    settings: std::cell::Cell<Settings>,
+    /// Past session opened read-only, shown instead of the recorded one
+    viewed: Option<Logs>,
+    /// Unset while another instance records, nothing is consumed then
+    w_handle: Option<WorkerHandle>,
+    /// Lock on the data directory, unless another instance holds it
+    lock: Option<InstanceLock>,
+    /// From later launches
+    requests: Receiver<Request>,
+    import: Option<Import>,
+    /// Session the running import goes to, unless it's merged
+    imported: Option<Logs>,
+    settings: Settings,
 }
 
 impl App {
-    pub fn new(ctx: &eframe::CreationContext<'_>, settings: Settings) -> Self {
-        // Start async worker
-        let egui_ctx = ctx.egui_ctx.clone();
-        let w_handle = Worker::new(settings.rabbit_mq.clone(), egui_ctx).start();
-
+    /// Without `lock` another instance records into the data directory,
+    /// so its logs are only shown. `files` are imported into sessions of
+    /// their own.
+    pub fn new(
+        ctx: &eframe::CreationContext<'_>,
+        settings: Settings,
+        mut lock: Option<InstanceLock>,
+        files: Vec<PathBuf>,
+    ) -> Self {
         egui_extras::install_image_loaders(&ctx.egui_ctx);
-        App {
+        let mut state = UiState::load(&settings);
+        state.queued_imports = files
+            .into_iter()
+            .map(|file| (file, ImportTarget::NewSession))
+            .collect();
+        let (logs, w_handle, requests) = match &mut lock {
+            Some(lock) => {
+                let logs = Logs::open(&settings.storage).unwrap_or_else(|e| {
+                    state
+                        .toasts
+                        .error(format!("Failed to open the last session: {e}"));
+                    Logs::start_session("", &settings.storage).expect("Failed to open logs storage")
+                });
+                // Start async worker
+                let egui_ctx = ctx.egui_ctx.clone();
+                let w_handle = Worker::new(&settings, egui_ctx).start();
+                (logs, Some(w_handle), lock.serve(ctx.egui_ctx.clone()))
+            }
+            None => {
+                // The recording instance may not have started a session yet
+                let logs = Logs::open_read_only().unwrap_or_else(|e| {
+                    state.toasts.warning(format!(
+                        "Failed to open the last session: {e}. Reload once there's one"
+                    ));
+                    Logs::empty()
+                });
+                (logs, None, std::sync::mpsc::channel().1)
+            }
+        };
+        let mut app = App {
             assets: Assets::load(),
-            state: std::cell::Cell::new(UiState::load(&settings)),
-            logs: Default::default(),
+            state,
+            logs,
+            viewed: None,
             w_handle,
-            settings: std::cell::Cell::new(settings),
+            lock,
+            requests,
+            import: None,
+            imported: None,
+            settings,
+        };
+        let recoveries = app.logs.take_recoveries();
+        app.report_recoveries(recoveries);
+        app
+    }
+
+    /// Tells about damaged files found while opening logs
+    fn report_recoveries(&mut self, recoveries: Vec<Recovery>) {
+        if recoveries.is_empty() {
+            return;
+        }
+        let salvaged: usize = recoveries.iter().map(|r| r.salvaged).sum();
+        self.state.toasts.warning(format!(
+            "Recovered {salvaged} entries from {} damaged file(s), see the report",
+            recoveries.len()
+        ));
+        self.state.recoveries.extend(recoveries);
+    }
+
+    /// Whether entries are consumed and recorded, or another instance
+    /// does that
+    fn is_recording(&self) -> bool {
+        self.lock.is_some()
+    }
+
+    fn command(&self, cmd: Command) {
+        if let Some(w_handle) = &self.w_handle {
+            w_handle.command(cmd);
+        }
+    }
+
+    fn handle_requests(&mut self, ctx: &egui::Context) {
+        while let Ok(request) = self.requests.try_recv() {
+            match request {
+                Request::Show => {}
+                Request::Import(file) => {
+                    self.state
+                        .queued_imports
+                        .push_back((file, ImportTarget::NewSession));
+                }
+            }
+            ctx.send_viewport_cmd(egui::ViewportCommand::Minimized(false));
+            ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+        }
+    }
+
+    /// Files dropped on the window are listed in the import window
+    fn handle_dropped_files(&mut self, ctx: &egui::Context) {
+        let dropped: Vec<String> = ctx.input(|i| {
+            i.raw
+                .dropped_files
+                .iter()
+                .filter_map(|f| f.path.as_ref().map(|p| p.display().to_string()))
+                .collect()
+        });
+        if dropped.is_empty() {
+            return;
+        }
+        let state = &mut self.state.import;
+        state.paths = dropped.join("\n");
+        state.show = true;
+    }
+
+    /// Queues files listed in the import window
+    fn import_files(&mut self) {
+        let state = &mut self.state.import;
+        let files: Vec<PathBuf> = state.paths.lines().filter_map(string_to_path).collect();
+        if files.is_empty() {
+            self.state.toasts.error("No files to import");
+            return;
+        }
+        let target = state.target;
+        state.show = false;
+        state.paths.clear();
+        self.state
+            .queued_imports
+            .extend(files.into_iter().map(|file| (file, target)));
+    }
+
+    /// Starts queued imports and stores what they decoded, for a limited
+    /// time per frame
+    fn run_imports(&mut self, ctx: &egui::Context) {
+        if std::mem::take(&mut self.state.cancel_import) {
+            self.finish_import(Some("Cancelled".to_string()));
+        }
+        while self.import.is_none() {
+            let Some((file, target)) = self.state.queued_imports.pop_front() else {
+                return;
+            };
+            self.start_import(file, target, ctx);
+        }
+        let started = Instant::now();
+        while started.elapsed() < IMPORT_FRAME_TIME {
+            let Some(progress) = self.import.as_ref().and_then(Import::try_next) else {
+                return;
+            };
+            match progress {
+                Progress::Entries(entries) => {
+                    let count = entries.len();
+                    if let Err(e) = self.store_imported(entries) {
+                        self.finish_import(Some(format!("Failed to store entries: {e}")));
+                        return;
+                    }
+                    if let Some(import) = &mut self.import {
+                        import.entries += count;
+                    }
+                }
+                Progress::Finished => {
+                    self.finish_import(None);
+                    return;
+                }
+                Progress::Failed(e) => {
+                    self.finish_import(Some(e));
+                    return;
+                }
+            }
+        }
+        // Out of time with more to store
+        ctx.request_repaint();
+    }
+
+    fn start_import(&mut self, file: PathBuf, target: ImportTarget, ctx: &egui::Context) {
+        let recording = self.is_recording();
+        let toasts = &mut self.state.toasts;
+        if !recording {
+            toasts.error(format!(
+                "Can't import {}, another instance is recording",
+                file.display()
+            ));
+            return;
+        }
+        let decoder = match self.settings.decoder() {
+            Ok(decoder) => decoder,
+            Err(e) => {
+                toasts.error(e);
+                return;
+            }
+        };
+        let import = match Import::start(file, target, decoder, ctx.clone()) {
+            Ok(import) => import,
+            Err(e) => {
+                toasts.error(format!("Failed to import: {e}"));
+                return;
+            }
+        };
+        if target == ImportTarget::NewSession {
+            match Logs::import_session(&import.path, &self.settings.storage) {
+                Ok(logs) => self.imported = Some(logs),
+                Err(e) => {
+                    toasts.error(format!("Failed to start a session: {e}"));
+                    return;
+                }
+            }
+        }
+        self.import = Some(import);
+    }
+
+    fn store_imported(&mut self, entries: Vec<LogEntry>) -> std::io::Result<()> {
+        for entry in entries {
+            match &mut self.imported {
+                Some(logs) => logs.append(entry)?,
+                None => self.logs.append(entry)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Stops the running import, showing the session it went to.
+    /// What was stored so far is kept.
+    fn finish_import(&mut self, error: Option<String>) {
+        let Some(import) = self.import.take() else {
+            return;
+        };
+        let toasts = &mut self.state.toasts;
+        let file = import.file_name();
+        match error {
+            None => toasts.info(format!("Imported {} entries from {file}", import.entries)),
+            Some(e) => toasts.error(format!(
+                "Import of {file} stopped after {} entries: {e}",
+                import.entries
+            )),
+        };
+        if let Some(mut logs) = self.imported.take() {
+            if let Err(e) = logs.close() {
+                self.state
+                    .toasts
+                    .error(format!("Failed to close the session: {e}"));
+            }
+            self.view_session(logs.session().clone());
         }
     }
 
@@ -82,64 +575,431 @@ impl App {
         uis::MainScreen::new(self).draw(ctx);
     }
 
-    fn update_state(&mut self) {
+    fn update_state(&mut self, ctx: &egui::Context) {
         use crate::worker::Notification;
-        let notifications = self.w_handle.get_notifications();
+        let Some(w_handle) = &self.w_handle else {
+            return;
+        };
+        let notifications = w_handle.get_notifications();
         if !notifications.is_empty() {
+            let mut stored = Vec::new();
             for n in notifications {
                 match n {
-                    Notification::LogEntry(log_entry) => {
-                        self.ui_state_mut()
-                            .toasts
-                            .info(format!("One more log: {}", log_entry.level.to_string()));
-                        self.logs.append(log_entry);
+                    Notification::LogEntry {
+                        entry: log_entry,
+                        delivery,
+                    } => {
+                        if log_entry.raw.is_some() {
+                            self.state.undecodable.0 += 1;
+                        }
+                        if let Err(e) = self.logs.append(*log_entry) {
+                            self.state
+                                .toasts
+                                .error(format!("Failed to store entry: {e}"));
+                            // Handed out again, unless the source can't
+                            w_handle.command(Command::Nack(delivery));
+                            continue;
+                        }
+                        stored.push(delivery);
+                    }
+                    Notification::Error(e) => {
+                        self.state.toasts.info(format!("Error: {}", e));
                     }
-                    Notification::ConnectionStatusChanged { status: Err(e) }
-                    | Notification::Error(e) => {
-                        self.ui_state_mut().toasts.info(format!("Error: {}", e));
+                    Notification::ConnectionStatusChanged { source, status } => {
+                        if let ConnectionState::Failed(e) = &status {
+                            self.state
+                                .toasts
+                                .error(format!("Gave up reconnecting {source}: {e}"));
+                        }
+                        self.state
+                            .connections
+                            .insert(source, (status, Instant::now()));
                     }
-                    Notification::ConnectionStatusChanged { status: Ok(()) } => {
-                        self.ui_state_mut().toasts.info("Connected!");
+                    Notification::SourceStopped { source } => {
+                        self.state.connections.remove(&source);
                     }
                 }
             }
+            if !stored.is_empty() {
+                w_handle.command(Command::Ack(stored));
+            }
+        }
+        self.report_undecodable(ctx);
+    }
+
+    /// One toast for many undecodable messages, as a source may send
+    /// nothing else
+    fn report_undecodable(&mut self, ctx: &egui::Context) {
+        let (count, last) = &mut self.state.undecodable;
+        if *count == 0 {
+            return;
         }
+        let since = last.map_or(UNDECODABLE_TOAST_INTERVAL, |last| last.elapsed());
+        if since < UNDECODABLE_TOAST_INTERVAL {
+            ctx.request_repaint_after(UNDECODABLE_TOAST_INTERVAL - since);
+            return;
+        }
+        let count = std::mem::take(count);
+        *last = Some(Instant::now());
+        self.state.toasts.warning(format!(
+            "Kept {count} undecodable message(s) as raw entries"
+        ));
+    }
+
+    /// Logs the table shows
+    fn shown_logs(&self) -> &Logs {
+        self.viewed.as_ref().unwrap_or(&self.logs)
     }
 
-    /// Drawing loop is one-line execution, so we can trust that
-    fn ui_state_mut(&self) -> &mut UiState {
-        unsafe { self.state.as_ptr().as_mut().unwrap() }
+    fn shown_logs_mut(&mut self) -> &mut Logs {
+        self.viewed.as_mut().unwrap_or(&mut self.logs)
     }
 
-    /// And that too
-    fn settings_mut(&self) -> &mut Settings {
-        unsafe { self.settings.as_ptr().as_mut().unwrap() }
+    fn show_sessions(&mut self) {
+        match SessionInfo::list() {
+            Ok(sessions) => self.state.sessions = Some(sessions),
+            Err(e) => {
+                self.state
+                    .toasts
+                    .error(format!("Failed to list sessions: {e}"));
+            }
+        }
     }
 
-    fn apply_settings(&self) {
-        let settings = &mut self.settings_mut().rabbit_mq;
-        let settings_ui = &mut self.ui_state_mut().settings_state;
+    /// Ends the recorded session and records into a new one from now on
+    fn new_session(&mut self) {
+        let name = std::mem::take(&mut self.state.session_name);
+        // Ended first, so starting the new one can evict it like any other
+        if let Err(e) = self.logs.close() {
+            self.state
+                .toasts
+                .error(format!("Failed to close the session: {e}"));
+        }
+        match Logs::start_session(&name, &self.settings.storage) {
+            Ok(logs) => self.logs = logs,
+            Err(e) => {
+                self.state
+                    .toasts
+                    .error(format!("Failed to start a session: {e}"));
+                if let Err(e) = self.logs.reopen() {
+                    self.state
+                        .toasts
+                        .error(format!("Failed to continue the session: {e}"));
+                }
+                return;
+            }
+        }
+        self.view_live();
+        self.show_sessions();
+    }
+
+    fn view_session(&mut self, session: SessionInfo) {
+        if session.id == self.logs.session().id {
+            self.view_live();
+            return;
+        }
+        match Logs::view(session) {
+            Ok(mut logs) => {
+                self.report_recoveries(logs.take_recoveries());
+                self.viewed = Some(logs);
+                self.state.selected = None;
+            }
+            Err(e) => {
+                self.state
+                    .toasts
+                    .error(format!("Failed to open the session: {e}"));
+            }
+        }
+    }
+
+    /// Removes a past session with its entries
+    fn delete_session(&mut self, session: &SessionInfo) {
+        if session.id == self.logs.session().id {
+            return;
+        }
+        if session.id == self.shown_logs().session().id {
+            // Its files are open while shown
+            self.view_live();
+        }
+        if let Err(e) = session.delete() {
+            self.state
+                .toasts
+                .error(format!("Failed to delete the session: {e}"));
+        }
+        self.show_sessions();
+    }
+
+    /// Re-reads the latest session while another instance records into it
+    fn reload_live(&mut self) {
+        match Logs::open_read_only() {
+            Ok(mut logs) => {
+                self.report_recoveries(logs.take_recoveries());
+                self.logs = logs;
+                self.view_live();
+            }
+            Err(e) => {
+                self.state
+                    .toasts
+                    .error(format!("Failed to reload logs: {e}"));
+            }
+        }
+    }
+
+    /// Shows the recorded session again
+    fn view_live(&mut self) {
+        self.viewed = None;
+        self.state.selected = None;
+    }
+
+    /// Returns false if some of the settings are invalid. Settings are
+    /// left to the recording instance.
+    fn apply_settings(&mut self) -> bool {
+        if !self.is_recording() {
+            self.state
+                .toasts
+                .error("Another instance is recording, change settings there");
+            return false;
+        }
+        // Nothing is applied before all of it is known to be valid
+        let (levels, level_table) = match self.level_definitions() {
+            Ok(levels) => levels,
+            Err(e) => {
+                self.state.toasts.error(e);
+                return false;
+            }
+        };
+        let settings = &mut self.settings.rabbit_mq;
+        let settings_ui = &mut self.state.settings_state;
+        let Ok(port) = settings_ui.port.trim().parse() else {
+            self.state.toasts.error("Port must be a number up to 65535");
+            return false;
+        };
+        let stream_offset = match settings_ui.stream_offset.parse::<StreamOffset>() {
+            Ok(offset) => offset,
+            Err(e) => {
+                self.state.toasts.error(e);
+                return false;
+            }
+        };
+        let time_formats: Vec<String> = settings_ui
+            .time_formats
+            .lines()
+            .map(str::trim)
+            .filter(|f| !f.is_empty())
+            .map(String::from)
+            .collect();
+        if let Err(e) = TimestampParser::new(&time_formats) {
+            self.state.toasts.error(e.to_string());
+            return false;
+        }
+        let retention = match settings_ui.retention() {
+            Ok(retention) => retention,
+            Err(e) => {
+                self.state.toasts.error(e);
+                return false;
+            }
+        };
+        let tail_paths: Vec<String> = settings_ui
+            .tail_paths
+            .lines()
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(String::from)
+            .collect();
+        if let Some(e) = tail_paths.iter().find_map(|p| {
+            glob::Pattern::new(p)
+                .err()
+                .map(|e| format!("Invalid path {p}: {e}"))
+        }) {
+            self.state.toasts.error(e);
+            return false;
+        }
+        let http = match settings_ui.http() {
+            Ok(http) => http,
+            Err(e) => {
+                self.state.toasts.error(e);
+                return false;
+            }
+        };
+        let syslog = match settings_ui.syslog() {
+            Ok(syslog) => syslog,
+            Err(e) => {
+                self.state.toasts.error(e);
+                return false;
+            }
+        };
+        let Ok(prefetch) = settings_ui.prefetch.trim().parse() else {
+            self.state
+                .toasts
+                .error("Prefetch must be a number up to 65535");
+            return false;
+        };
+        settings.enabled = settings_ui.rabbit_mq;
         settings.host = settings_ui.host.clone();
         settings.vhost = settings_ui.vhost.clone();
-        settings.port = settings_ui.port.parse().expect("Failed to parse port");
+        settings.port = port;
         settings.username = settings_ui.username.clone();
         settings.password = settings_ui.password.clone();
-        self.settings_mut()
-            .write_configuration()
-            .expect("Failed to write configuration");
-        self.w_handle
-            .command(crate::worker::Command::UpdateConfig(settings.clone()));
+        let topology = &mut settings.topology;
+        topology.mode = settings_ui.mode;
+        topology.queue = settings_ui.queue.trim().to_string();
+        topology.declare_queue = settings_ui.declare_queue;
+        topology.exchange = if settings_ui.exchange.trim().is_empty() {
+            None
+        } else {
+            Some(ExchangeSettings {
+                name: settings_ui.exchange.trim().to_string(),
+                kind: settings_ui.exchange_kind,
+                declare: settings_ui.declare_exchange,
+            })
+        };
+        topology.binding_keys = settings_ui
+            .binding_keys
+            .lines()
+            .map(str::trim)
+            .filter(|k| !k.is_empty())
+            .map(String::from)
+            .collect();
+        settings.stream.enabled = settings_ui.stream;
+        settings.stream.offset = stream_offset;
+        settings.prefetch = prefetch;
+        settings.tls.enabled = settings_ui.tls;
+        settings.tls.ca_file = string_to_path(&settings_ui.ca_file);
+        settings.tls.client_cert = string_to_path(&settings_ui.client_cert);
+        settings.tls.client_key = string_to_path(&settings_ui.client_key);
+        settings.tls.skip_verify = settings_ui.skip_verify;
+        self.settings.decode.format = settings_ui.format;
+        self.settings.decode.on_decode_error = settings_ui.on_decode_error;
+        self.settings.decode.time_formats = time_formats;
+        self.settings.storage.backend = settings_ui.storage_backend;
+        match self.logs.set_retention(&retention) {
+            Ok(0) => {}
+            Ok(evicted) => {
+                self.state.toasts.info(format!("Evicted {evicted} entries"));
+            }
+            Err(e) => {
+                self.state
+                    .toasts
+                    .error(format!("Failed to evict entries: {e}"));
+            }
+        }
+        self.settings.storage.retention = retention;
+        self.settings.storage.new_session_on_launch = settings_ui.new_session_on_launch;
+        self.settings.tail.paths = tail_paths;
+        self.settings.tail.from_start = settings_ui.tail_from_start;
+        self.settings.http = http;
+        self.settings.syslog = syslog;
+        self.settings.levels = levels;
+        self.state.levels = level_table;
+        if let Err(e) = self.settings.write_configuration() {
+            self.state
+                .toasts
+                .error(format!("Failed to save settings: {e}"));
+        }
+        self.command(Command::UpdateConfig(Box::new(self.settings.clone())));
+        true
+    }
+
+    /// Level definitions with the colors picked in the settings window,
+    /// along with the table they make up
+    fn level_definitions(&self) -> Result<(Vec<LevelDefinition>, LevelTable), String> {
+        let mut definitions = self.settings.levels.clone();
+        let current = &self.state.levels;
+        for (name, light, dark) in &self.state.settings_state.level_colors {
+            let Some(level) = current.levels().iter().find(|l| &l.name == name) else {
+                continue;
+            };
+            if current.color(level, false) == *light && current.color(level, true) == *dark {
+                continue;
+            }
+            let color = Some(LevelColor {
+                light: light.to_hex(),
+                dark: dark.to_hex(),
+            });
+            match definitions
+                .iter_mut()
+                .find(|d| d.name.eq_ignore_ascii_case(name))
+            {
+                Some(def) => def.color = color,
+                None => definitions.push(LevelDefinition {
+                    name: name.clone(),
+                    color,
+                    ..Default::default()
+                }),
+            }
+        }
+        let table = LevelTable::new(&definitions)?;
+        Ok((definitions, table))
+    }
+
+    fn redecode(&mut self) {
+        let decoder = match self.settings.decoder() {
+            Ok(decoder) => decoder,
+            Err(e) => {
+                self.state.toasts.error(e);
+                return;
+            }
+        };
+        let decoded = match self.shown_logs_mut().redecode(&decoder) {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                self.state
+                    .toasts
+                    .error(format!("Failed to store decoded entries: {e}"));
+                return;
+            }
+        };
+        // Batches decoded into many entries move the ones after them
+        self.state.selected = None;
+        let left = self.shown_logs_mut().raw_count();
+        self.state
+            .toasts
+            .info(format!("Decoded {decoded} raw entries, {left} left"));
+    }
+
+    fn replay(&mut self) {
+        match self.state.replay_from.parse() {
+            Ok(from) => self.command(Command::Replay { from }),
+            Err(e) => {
+                self.state.toasts.error(e);
+            }
+        }
     }
 }
 
 impl eframe::App for App {
     fn update(&mut self, ctx: &egui::Context, _: &mut eframe::Frame) {
-        self.update_state();
+        self.handle_requests(ctx);
+        self.handle_dropped_files(ctx);
+        self.update_state(ctx);
+        self.run_imports(ctx);
+        if std::mem::take(&mut self.state.redecode) {
+            self.redecode();
+        }
+        match self.logs.maintain_if_due() {
+            Ok(Some(next)) => ctx.request_repaint_after(next),
+            Ok(None) => {}
+            Err(e) => {
+                self.state
+                    .toasts
+                    .error(format!("Failed to evict entries: {e}"));
+            }
+        }
+        match self.logs.sync_if_due() {
+            Ok(Some(next)) => ctx.request_repaint_after(next),
+            Ok(None) => {}
+            Err(e) => {
+                self.state.toasts.error(format!("Failed to sync logs: {e}"));
+            }
+        }
         self.draw(ctx);
-        self.ui_state_mut().toasts.show(ctx);
+        self.state.toasts.show(ctx);
     }
 
     fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
-        self.logs.store().expect("Failed to store logs!");
+        if let Some(logs) = &mut self.imported {
+            trace_err!(logs.close(), ());
+        }
+        self.logs.close().expect("Failed to sync logs!");
     }
 }
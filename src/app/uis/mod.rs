@@ -1,107 +1,778 @@
-use std::fmt::Display;
+use std::time::{Duration, Instant};
 
 use eframe::egui;
-use egui::{Pos2, Rect, Rounding, Widget};
+use egui::{Color32, Pos2, Rect, Rounding, Widget};
 
-use crate::{default_time_format, worker};
+use crate::{
+    app::config::{ConsumeMode, DecodeErrorAction, ExchangeKind, StorageBackend},
+    display_time_format,
+    types::{
+        decode::LogFormat,
+        json_log::{LogEntry, Origin, RawPayload},
+    },
+    worker::{self, ConnectionState},
+};
 
-use super::{logs::Logs, App};
+use super::{import::ImportTarget, App};
 
 pub struct MainScreen<'a> {
-    app: &'a App,
+    app: &'a mut App,
 }
 
 impl<'a> MainScreen<'a> {
-    pub fn new(app: &'a App) -> Self {
+    pub fn new(app: &'a mut App) -> Self {
         MainScreen { app }
     }
 
-    pub fn draw(&self, ctx: &egui::Context) {
-        let app = self.app;
-        let assets = &app.assets;
-        let w_handle = &app.w_handle;
+    pub fn draw(&mut self, ctx: &egui::Context) {
+        let app = &mut *self.app;
+        let recording = app.is_recording();
+        let settings_icon = app.assets.settings_icon.clone();
+
+        let selected = app.state.selected.and_then(|id| app.shown_logs().get(id));
+        if let Some(entry) = selected {
+            egui::SidePanel::right("details")
+                .resizable(true)
+                .default_width(320.0)
+                .show(ctx, |ui| {
+                    egui::Sides::new().show(
+                        ui,
+                        |ui| {
+                            ui.heading("Details");
+                        },
+                        |ui| {
+                            if ui.button("✖").clicked() {
+                                app.state.selected = None;
+                            }
+                        },
+                    );
+                    egui::ScrollArea::vertical().show(ui, |ui| {
+                        details_ui(ui, &entry, app);
+                    });
+                });
+        }
 
         egui::CentralPanel::default().show(ctx, |ui| {
+            let (mut show_settings, mut show_sessions, mut show_import) = (false, false, false);
             egui::Sides::new().show(
                 ui,
                 |ui| {
-                    let response = egui::ImageButton::new(assets.reload_icon.clone())
+                    let response = egui::ImageButton::new(app.assets.reload_icon.clone())
                         .uv(Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0)).shrink(0.1))
                         .rounding(Rounding::same(5.0))
                         .ui(ui);
                     if response.clicked() {
-                        w_handle.command(worker::Command::Reconnect);
+                        if recording {
+                            app.command(worker::Command::Reconnect);
+                        } else {
+                            app.reload_live();
+                        }
                     }
                     egui_theme_switch::global_theme_switch(ui);
+                    if recording {
+                        let connections = &app.state.connections;
+                        for (source, connection) in connections {
+                            // Named only when there's more than one
+                            let name = (connections.len() > 1).then_some(source.as_str());
+                            connection_status_ui(ui, name, connection);
+                        }
+                    }
+                    let raw = app.shown_logs_mut().raw_count();
+                    if raw > 0
+                        && ui
+                            .button(format!("Re-decode {raw} raw"))
+                            .on_hover_text("Try to decode messages that failed before")
+                            .clicked()
+                    {
+                        app.state.redecode = true;
+                    }
+                    if recording && app.settings.rabbit_mq.stream.enabled {
+                        egui::TextEdit::singleline(&mut app.state.replay_from)
+                            .hint_text("1h")
+                            .desired_width(80.0)
+                            .show(ui);
+                        if ui
+                            .button("Replay")
+                            .on_hover_text("Re-read the stream from given offset")
+                            .clicked()
+                        {
+                            app.replay();
+                        }
+                    }
                 },
                 |ui| {
-                    let response = egui::ImageButton::new(assets.settings_icon.clone())
+                    let response = egui::ImageButton::new(settings_icon)
                         .uv(Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0)).shrink(0.1))
                         .rounding(Rounding::same(5.0))
                         .ui(ui);
-                    if response.clicked() {
-                        app.ui_state_mut().settings_state.show = true;
-                    }
+                    show_settings = response.clicked();
+                    show_sessions = ui.button("Sessions").clicked();
+                    show_import = ui
+                        .add_enabled(recording, egui::Button::new("Import"))
+                        .on_hover_text("Import log files, they can be dropped on the window too")
+                        .on_disabled_hover_text("Another instance is recording")
+                        .clicked();
                 },
             );
+            // The left side has the app borrowed
+            if show_settings {
+                app.state.settings_state.show = true;
+            }
+            if show_sessions {
+                app.show_sessions();
+            }
+            if show_import {
+                app.state.import.show = true;
+            }
+            if !recording {
+                ui.horizontal(|ui| {
+                    ui.colored_label(
+                        Color32::ORANGE,
+                        "Another bucklog instance is recording into this directory, \
+                         its logs are shown read-only",
+                    );
+                    if ui
+                        .button("Reload")
+                        .on_hover_text("Show what was recorded since")
+                        .clicked()
+                    {
+                        app.reload_live();
+                    }
+                });
+            }
+            if let Some(viewed) = &app.viewed {
+                let title = viewed.session().title();
+                ui.horizontal(|ui| {
+                    ui.colored_label(Color32::ORANGE, format!("Viewing {title} (read-only)"));
+                    if ui.button("Back to live").clicked() {
+                        app.view_live();
+                    }
+                });
+            }
+            if let Some(import) = &app.import {
+                ui.horizontal(|ui| {
+                    let into = match import.target {
+                        ImportTarget::NewSession => "",
+                        ImportTarget::Current => " into the current session",
+                    };
+                    if ui.button("Cancel").clicked() {
+                        app.state.cancel_import = true;
+                    }
+                    egui::ProgressBar::new(import.progress())
+                        .text(format!(
+                            "Importing {}{into}: {} entries",
+                            import.file_name(),
+                            import.entries
+                        ))
+                        .animate(true)
+                        .ui(ui);
+                });
+            }
+            egui::TextEdit::singleline(&mut app.state.filter)
+                .hint_text("Filter: text, level=error, origin.routing_key~billing")
+                .desired_width(f32::INFINITY)
+                .show(ui);
             egui::ScrollArea::vertical().show(ui, |ui| {
-                table_ui(ui, false, &app);
+                table_ui(ui, false, app);
             });
 
-            if app.ui_state_mut().settings_state.show {
+            if app.state.settings_state.show {
                 SettingsWindow::new(app).draw(ctx);
             }
+            if app.state.sessions.is_some() {
+                sessions_window(ctx, app);
+            }
+            if !app.state.recoveries.is_empty() {
+                recovery_window(ctx, app);
+            }
+            if app.state.import.show {
+                import_window(ctx, app);
+            }
+        });
+        if recording {
+            drop_overlay(ctx);
+        }
+    }
+}
+
+fn import_window(ctx: &egui::Context, app: &mut App) {
+    let mut open = true;
+    egui::Window::new("Import").open(&mut open).show(ctx, |ui| {
+        let state = &mut app.state.import;
+        ui.label(
+            "JSON, NDJSON and log files, gzipped or not. Lines that can't be decoded are kept raw.",
+        );
+        egui::TextEdit::multiline(&mut state.paths)
+            .hint_text("One file per line")
+            .desired_width(f32::INFINITY)
+            .desired_rows(3)
+            .show(ui);
+        ui.radio_value(
+            &mut state.target,
+            ImportTarget::NewSession,
+            "Into a new session, shown read-only",
+        );
+        ui.radio_value(
+            &mut state.target,
+            ImportTarget::Current,
+            "Merged into the current session",
+        );
+        if ui.button("Import").clicked() {
+            app.import_files();
+        }
+    });
+    if !open {
+        app.state.import.show = false;
+    }
+}
+
+/// Tells files held over the window can be dropped there
+fn drop_overlay(ctx: &egui::Context) {
+    if ctx.input(|i| i.raw.hovered_files.is_empty()) {
+        return;
+    }
+    let painter = ctx.layer_painter(egui::LayerId::new(
+        egui::Order::Foreground,
+        egui::Id::new("drop_overlay"),
+    ));
+    let rect = ctx.screen_rect();
+    painter.rect_filled(rect, 0.0, Color32::from_black_alpha(192));
+    painter.text(
+        rect.center(),
+        egui::Align2::CENTER_CENTER,
+        "Drop to import",
+        egui::TextStyle::Heading.resolve(&ctx.style()),
+        Color32::WHITE,
+    );
+}
+
+fn connection_status_ui(
+    ui: &mut egui::Ui,
+    source: Option<&str>,
+    (state, since): &(ConnectionState, Instant),
+) {
+    let (color, text) = match state {
+        ConnectionState::Connecting => (Color32::YELLOW, "Connecting…".to_string()),
+        ConnectionState::Connected => (Color32::GREEN, "Connected".to_string()),
+        ConnectionState::Backoff { retry_in, .. } => {
+            let left = retry_in.saturating_sub(since.elapsed());
+            // Keep the countdown ticking
+            ui.ctx().request_repaint_after(Duration::from_secs(1));
+            (
+                Color32::ORANGE,
+                format!("Retrying in {}s", left.as_secs_f32().ceil()),
+            )
+        }
+        ConnectionState::Failed(_) => (Color32::RED, "Disconnected".to_string()),
+        ConnectionState::Finished(reason) => (Color32::GRAY, reason.clone()),
+    };
+    let text = match source {
+        Some(source) => format!("● {source}: {text}"),
+        None => format!("● {text}"),
+    };
+    let response = ui.colored_label(color, text);
+    match state {
+        ConnectionState::Backoff { error, .. } | ConnectionState::Failed(error) => {
+            response.on_hover_text(error);
+        }
+        _ => {}
+    }
+}
+
+fn sessions_window(ctx: &egui::Context, app: &mut App) {
+    let mut open = true;
+    egui::Window::new("Sessions")
+        .open(&mut open)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                egui::TextEdit::singleline(&mut app.state.session_name)
+                    .hint_text("Name (optional)")
+                    .show(ui);
+                if ui
+                    .add_enabled(app.is_recording(), egui::Button::new("Start new session"))
+                    .on_hover_text("End the current session and record into a new one")
+                    .on_disabled_hover_text("Another instance is recording")
+                    .clicked()
+                {
+                    app.new_session();
+                }
+            });
+            ui.separator();
+            let live = app.logs.session().clone();
+            let shown = app.shown_logs().session().id.clone();
+            let recording = app.is_recording();
+            let state = &mut app.state;
+            let Some(sessions) = &state.sessions else {
+                return;
+            };
+            let deleting = &mut state.deleting;
+            let mut open_session = None;
+            let mut delete_session = None;
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                egui::Grid::new("sessions")
+                    .num_columns(7)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.strong("Session");
+                        ui.strong("Started");
+                        ui.strong("Ended");
+                        ui.strong("Entries");
+                        ui.strong("Levels");
+                        ui.label("");
+                        ui.label("");
+                        ui.end_row();
+                        for session in sessions {
+                            let recorded = session.id == live.id;
+                            // The recorded one is ahead of what's saved
+                            let session = if recorded { &live } else { session };
+                            let title = ui.label(session.title());
+                            if let Some(file) = &session.imported {
+                                title.on_hover_text(format!("Imported from {}", file.display()));
+                            }
+                            ui.label(format_time(session.started));
+                            match session.ended {
+                                Some(ended) => ui.label(format_time(ended)),
+                                None if recorded => ui.colored_label(Color32::GREEN, "recording"),
+                                None => ui.label("—"),
+                            };
+                            ui.label(session.entries.to_string());
+                            let levels = session
+                                .levels
+                                .iter()
+                                .map(|(level, count)| format!("{level} {count}"))
+                                .collect::<Vec<_>>()
+                                .join(" · ");
+                            ui.label(levels);
+                            if session.id == shown {
+                                ui.label("shown");
+                            } else if ui.button("Open").clicked() {
+                                open_session = Some(session.clone());
+                            }
+                            if recorded {
+                                ui.label("");
+                            } else if deleting.as_ref() == Some(&session.id) {
+                                ui.horizontal(|ui| {
+                                    if ui.button("Really delete").clicked() {
+                                        delete_session = Some(session.clone());
+                                    }
+                                    if ui.button("Keep").clicked() {
+                                        *deleting = None;
+                                    }
+                                });
+                            } else if ui
+                                .add_enabled(recording, egui::Button::new("Delete"))
+                                .on_disabled_hover_text("Another instance is recording")
+                                .clicked()
+                            {
+                                *deleting = Some(session.id.clone());
+                            }
+                            ui.end_row();
+                        }
+                    });
+            });
+            if let Some(session) = open_session {
+                app.view_session(session);
+            }
+            if let Some(session) = delete_session {
+                app.state.deleting = None;
+                app.delete_session(&session);
+            }
         });
+    if !open {
+        app.state.sessions = None;
     }
 }
 
+fn recovery_window(ctx: &egui::Context, app: &mut App) {
+    let mut open = true;
+    egui::Window::new("Recovery report")
+        .open(&mut open)
+        .show(ctx, |ui| {
+            ui.label("Some stored logs were damaged and have been read as far as possible.");
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for recovery in &app.state.recoveries {
+                    ui.separator();
+                    egui::Grid::new(&recovery.file)
+                        .num_columns(2)
+                        .show(ui, |ui| {
+                            ui.strong("File");
+                            ui.label(recovery.file.display().to_string());
+                            ui.end_row();
+                            ui.strong("Error");
+                            ui.label(&recovery.error);
+                            ui.end_row();
+                            ui.strong("Salvaged");
+                            ui.label(format!("{} entries", recovery.salvaged));
+                            ui.end_row();
+                            ui.strong("Lost");
+                            ui.label(match recovery.lost {
+                                Some(lost) => format!("{lost} entries"),
+                                None => "unknown, the rest was unreadable".to_string(),
+                            });
+                            ui.end_row();
+                            ui.strong("Original");
+                            ui.label(match &recovery.moved_to {
+                                Some(path) => format!("moved to {}", path.display()),
+                                None => "left as is".to_string(),
+                            });
+                            ui.end_row();
+                        });
+                }
+            });
+        });
+    if !open {
+        app.state.recoveries.clear();
+    }
+}
+
+fn format_time(time: time::OffsetDateTime) -> String {
+    time.format(display_time_format()).unwrap_or_default()
+}
+
 pub struct SettingsWindow<'a> {
-    app: &'a App,
+    app: &'a mut App,
 }
 
 impl<'a> SettingsWindow<'a> {
-    pub fn new(app: &'a App) -> Self {
+    pub fn new(app: &'a mut App) -> Self {
         SettingsWindow { app }
     }
 
     pub fn draw(&mut self, ctx: &egui::Context) {
-        let state = &mut self.app.ui_state_mut().settings_state;
-        let win_close = &mut self.app.ui_state_mut().settings_state.show;
+        let recording = self.app.is_recording();
+        let state = &mut self.app.state.settings_state;
+        let mut open = state.show;
+        let mut submit = false;
         egui::Window::new("Settings")
-            .open(win_close)
+            .open(&mut open)
             .show(ctx, |ui| {
-                ui.label("RabbitMQ host");
-                egui::TextEdit::singleline(&mut state.host)
-                    .hint_text("myhost.com")
-                    .show(ui);
-                ui.label("RabbitMQ virtual host");
-                egui::TextEdit::singleline(&mut state.vhost)
-                    .hint_text("demo-vhost")
-                    .show(ui);
-                ui.label("RabbitMQ port");
-                egui::TextEdit::singleline(&mut state.port)
-                    .hint_text("5672")
+                ui.checkbox(&mut state.rabbit_mq, "Consume from RabbitMQ");
+                ui.add_enabled_ui(state.rabbit_mq, |ui| {
+                    ui.label("RabbitMQ host");
+                    egui::TextEdit::singleline(&mut state.host)
+                        .hint_text("myhost.com")
+                        .show(ui);
+                    ui.label("RabbitMQ virtual host");
+                    egui::TextEdit::singleline(&mut state.vhost)
+                        .hint_text("demo-vhost")
+                        .show(ui);
+                    ui.label("RabbitMQ port");
+                    egui::TextEdit::singleline(&mut state.port)
+                        .hint_text("5672")
+                        .show(ui);
+                    ui.label("RabbitMQ user");
+                    egui::TextEdit::singleline(&mut state.username)
+                        .hint_text("rmquser")
+                        .show(ui);
+                    ui.label("RabbitMQ password");
+                    egui::TextEdit::singleline(&mut state.password)
+                        .password(true)
+                        .show(ui);
+                    ui.checkbox(&mut state.tls, "Use TLS (amqps)");
+                    ui.add_enabled_ui(state.tls, |ui| {
+                        ui.label("CA bundle (PEM)");
+                        egui::TextEdit::singleline(&mut state.ca_file)
+                            .hint_text("/etc/rabbitmq/ca.pem")
+                            .show(ui);
+                        ui.label("Client certificate (PEM)");
+                        egui::TextEdit::singleline(&mut state.client_cert)
+                            .hint_text("client.pem")
+                            .show(ui);
+                        ui.label("Client key (PEM)");
+                        egui::TextEdit::singleline(&mut state.client_key)
+                            .hint_text("client.key")
+                            .show(ui);
+                        ui.checkbox(&mut state.skip_verify, "Skip certificate verification")
+                            .on_hover_text("Accept any broker certificate. Development only!");
+                    });
+                    ui.separator();
+                    ui.horizontal(|ui| {
+                        ui.radio_value(&mut state.mode, ConsumeMode::Shared, "Shared queue");
+                        ui.radio_value(&mut state.mode, ConsumeMode::Tap, "Private tap")
+                            .on_hover_text(
+                                "Get a full copy of the stream through a temporary queue \
+                                 bound to the exchange",
+                            );
+                    });
+                    ui.add_enabled_ui(state.mode == ConsumeMode::Shared, |ui| {
+                        ui.label("Queue");
+                        egui::TextEdit::singleline(&mut state.queue)
+                            .hint_text("log")
+                            .show(ui);
+                        ui.checkbox(&mut state.declare_queue, "Declare queue");
+                    });
+                    ui.label("Exchange (empty to consume the queue as is)");
+                    egui::TextEdit::singleline(&mut state.exchange)
+                        .hint_text("logs")
+                        .show(ui);
+                    ui.add_enabled_ui(!state.exchange.trim().is_empty(), |ui| {
+                        egui::ComboBox::from_label("Exchange type")
+                            .selected_text(state.exchange_kind.as_str())
+                            .show_ui(ui, |ui| {
+                                for kind in ExchangeKind::ALL {
+                                    ui.selectable_value(
+                                        &mut state.exchange_kind,
+                                        kind,
+                                        kind.as_str(),
+                                    );
+                                }
+                            });
+                        ui.checkbox(&mut state.declare_exchange, "Declare exchange");
+                        ui.label("Binding keys (one per line)");
+                        egui::TextEdit::multiline(&mut state.binding_keys)
+                            .hint_text("billing.*\napp=billing,env=prod")
+                            .desired_rows(3)
+                            .show(ui);
+                    });
+                    ui.separator();
+                    ui.add_enabled_ui(state.mode == ConsumeMode::Shared, |ui| {
+                        ui.checkbox(&mut state.stream, "Queue is a stream");
+                    });
+                    ui.add_enabled_ui(state.stream, |ui| {
+                        ui.label("Start reading stream from");
+                        egui::TextEdit::singleline(&mut state.stream_offset)
+                            .hint_text("first, last, next, offset, timestamp or 1h")
+                            .show(ui);
+                    });
+                });
+                ui.separator();
+                egui::ComboBox::from_label("Log format")
+                    .selected_text(state.format.as_str())
+                    .show_ui(ui, |ui| {
+                        for format in LogFormat::ALL {
+                            ui.selectable_value(&mut state.format, format, format.as_str());
+                        }
+                    });
+                egui::ComboBox::from_label("Undecodable messages")
+                    .selected_text(state.on_decode_error.as_str())
+                    .show_ui(ui, |ui| {
+                        for action in DecodeErrorAction::ALL {
+                            ui.selectable_value(
+                                &mut state.on_decode_error,
+                                action,
+                                action.as_str(),
+                            );
+                        }
+                    })
+                    .response
+                    .on_hover_text(
+                        "Dead-lettered messages are rejected without requeue and routed \
+                         to the dead-letter exchange of the queue, if it has one. Other \
+                         sources keep them.",
+                    );
+                ui.label("Extra timestamp formats (one per line)");
+                egui::TextEdit::multiline(&mut state.time_formats)
+                    .hint_text("[day].[month].[year] [hour]:[minute]:[second]")
+                    .desired_rows(2)
                     .show(ui);
-                ui.label("RabbitMQ user");
-                egui::TextEdit::singleline(&mut state.username)
-                    .hint_text("rmquser")
+                egui::CollapsingHeader::new("Level colors").show(ui, |ui| {
+                    egui::Grid::new("level_colors").show(ui, |ui| {
+                        ui.label("");
+                        ui.label("Light");
+                        ui.label("Dark");
+                        ui.end_row();
+                        for (name, light, dark) in &mut state.level_colors {
+                            ui.label(name.as_str());
+                            ui.color_edit_button_srgba(light);
+                            ui.color_edit_button_srgba(dark);
+                            ui.end_row();
+                        }
+                    });
+                });
+                ui.label("Prefetch");
+                egui::TextEdit::singleline(&mut state.prefetch)
+                    .hint_text("100")
                     .show(ui);
-                ui.label("RabbitMQ password");
-                egui::TextEdit::singleline(&mut state.password)
-                    .password(true)
+                ui.separator();
+                ui.label("Follow files (one path or glob per line)");
+                egui::TextEdit::multiline(&mut state.tail_paths)
+                    .hint_text("/var/log/app/*.json")
+                    .desired_rows(2)
                     .show(ui);
-                let resp = ui.button("Submit");
-                if resp.clicked() {
-                    self.app.apply_settings();
-                    state.show = false;
-                }
+                ui.checkbox(&mut state.tail_from_start, "Read files from the start")
+                    .on_hover_text("Files followed before continue where they were left");
+                ui.separator();
+                ui.checkbox(&mut state.http_enabled, "Accept logs over HTTP")
+                    .on_hover_text("POST /logs with a JSON entry, an array or NDJSON");
+                ui.add_enabled_ui(state.http_enabled, |ui| {
+                    egui::Grid::new("http").show(ui, |ui| {
+                        ui.label("Listen on");
+                        egui::TextEdit::singleline(&mut state.http_bind)
+                            .hint_text("127.0.0.1:9880")
+                            .show(ui);
+                        ui.end_row();
+                        ui.label("Bearer token");
+                        egui::TextEdit::singleline(&mut state.http_token)
+                            .hint_text("None")
+                            .password(true)
+                            .show(ui);
+                        ui.end_row();
+                        ui.label("Max body, MB");
+                        egui::TextEdit::singleline(&mut state.http_max_body_mb)
+                            .hint_text("10")
+                            .show(ui);
+                        ui.end_row();
+                    });
+                });
+                ui.checkbox(&mut state.syslog_enabled, "Receive syslog")
+                    .on_hover_text("RFC 5424 and RFC 3164 messages, JSON in them is decoded");
+                ui.add_enabled_ui(state.syslog_enabled, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("Listen on");
+                        egui::TextEdit::singleline(&mut state.syslog_bind)
+                            .hint_text("0.0.0.0:514")
+                            .show(ui);
+                        ui.checkbox(&mut state.syslog_udp, "UDP");
+                        ui.checkbox(&mut state.syslog_tcp, "TCP");
+                    });
+                });
+                ui.separator();
+                egui::ComboBox::from_label("Storage")
+                    .selected_text(state.storage_backend.as_str())
+                    .show_ui(ui, |ui| {
+                        for backend in StorageBackend::ALL {
+                            ui.selectable_value(
+                                &mut state.storage_backend,
+                                backend,
+                                backend.as_str(),
+                            );
+                        }
+                    })
+                    .response
+                    .on_hover_text("Applies to new sessions");
+                ui.checkbox(
+                    &mut state.new_session_on_launch,
+                    "Start a new session on launch",
+                );
+                ui.label("Keep at most (empty for no limit)");
+                egui::Grid::new("retention").show(ui, |ui| {
+                    ui.label("Entries");
+                    egui::TextEdit::singleline(&mut state.max_entries)
+                        .hint_text("1000000")
+                        .show(ui);
+                    ui.end_row();
+                    ui.label("Age");
+                    egui::TextEdit::singleline(&mut state.max_age)
+                        .hint_text("7d")
+                        .show(ui);
+                    ui.end_row();
+                    ui.label("Size, MB");
+                    egui::TextEdit::singleline(&mut state.max_size_mb)
+                        .hint_text("1024")
+                        .show(ui);
+                    ui.end_row();
+                });
+                ui.checkbox(&mut state.archive_evicted, "Archive evicted entries")
+                    .on_hover_text("Gzipped NDJSON files next to the logs, one per day");
+                submit = ui
+                    .add_enabled(recording, egui::Button::new("Submit"))
+                    .on_disabled_hover_text("Another instance is recording, change them there")
+                    .clicked();
             });
+        state.show = open;
+        if submit && self.app.apply_settings() {
+            self.app.state.settings_state.show = false;
+        }
     }
 }
 
-pub fn table_ui(ui: &mut egui::Ui, reset: bool, app: &App) {
+fn raw_payload_ui(ui: &mut egui::Ui, raw: &RawPayload) {
+    ui.strong("Failed to decode");
+    ui.label(&raw.error);
+    ui.label(format!("{} bytes", raw.data.len()));
+}
+
+fn details_ui(ui: &mut egui::Ui, entry: &LogEntry, app: &App) {
+    let json = |v: &serde_json::Value| match v {
+        serde_json::Value::String(s) => s.clone(),
+        other => serde_json::to_string_pretty(other).expect("Failed to serialize value"),
+    };
+    let color = app.state.levels.color(&entry.level, ui.visuals().dark_mode);
+    egui::Grid::new("entry").num_columns(2).show(ui, |ui| {
+        ui.strong("Timestamp");
+        ui.label(entry.timestamp.format(display_time_format()).unwrap());
+        ui.end_row();
+        ui.strong("Level");
+        ui.colored_label(color, entry.level.to_string());
+        ui.end_row();
+        ui.strong("Source");
+        ui.label(entry.source.to_string());
+        ui.end_row();
+        if let Some(target) = &entry.source.target {
+            ui.strong("Target");
+            ui.label(target);
+            ui.end_row();
+        }
+        if let Some(thread) = &entry.source.thread {
+            ui.strong("Thread");
+            ui.label(thread);
+            ui.end_row();
+        }
+        if let Some(source_id) = &entry.source_id {
+            ui.strong("Received from");
+            ui.label(source_id);
+            ui.end_row();
+        }
+    });
+    ui.separator();
+    ui.label(&entry.message);
+    if !entry.fields.is_empty() {
+        egui::CollapsingHeader::new("Fields")
+            .default_open(true)
+            .show(ui, |ui| {
+                egui::Grid::new("fields").num_columns(2).show(ui, |ui| {
+                    for (k, v) in &entry.fields {
+                        ui.strong(k);
+                        ui.label(json(v));
+                        ui.end_row();
+                    }
+                });
+            });
+    }
+    if !entry.spans.is_empty() || entry.span.is_some() {
+        egui::CollapsingHeader::new("Spans").show(ui, |ui| {
+            for span in entry.spans.iter().chain(&entry.span) {
+                let span: serde_json::Map<_, _> =
+                    span.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+                ui.label(json(&serde_json::Value::Object(span)));
+            }
+        });
+    }
+    if let Some(origin) = &entry.origin {
+        egui::CollapsingHeader::new("Origin")
+            .default_open(true)
+            .show(ui, |ui| {
+                egui::Grid::new("origin").num_columns(2).show(ui, |ui| {
+                    for key in Origin::KEYS {
+                        if let Some(value) = origin.get(key) {
+                            ui.strong(key);
+                            ui.label(value);
+                            ui.end_row();
+                        }
+                    }
+                    for (k, v) in &origin.headers {
+                        ui.strong(format!("headers.{k}"));
+                        ui.label(json(v));
+                        ui.end_row();
+                    }
+                });
+            });
+    }
+    if let Some(raw) = &entry.raw {
+        egui::CollapsingHeader::new("Raw payload")
+            .default_open(true)
+            .show(ui, |ui| {
+                raw_payload_ui(ui, raw);
+                ui.label(String::from_utf8_lossy(&raw.data));
+            });
+    }
+}
+
+pub fn table_ui(ui: &mut egui::Ui, reset: bool, app: &mut App) {
     use egui_extras::{Column, TableBuilder};
-    let logs = &app.logs;
+    let filter = app.state.filter.clone();
+    app.shown_logs_mut().set_filter(&filter);
+    let count = app.shown_logs_mut().count();
 
     let text_height = egui::TextStyle::Body
         .resolve(ui.style())
@@ -167,8 +838,8 @@ pub fn table_ui(ui: &mut egui::Ui, reset: bool, app: &App) {
                         ui.strong("Timestamp");
                     },
                     |ui| {
-                        let as_countdown = app.ui_state_mut().table_state.as_countdown;
-                        app.ui_state_mut().table_state.as_countdown ^=
+                        let as_countdown = app.state.table_state.as_countdown;
+                        app.state.table_state.as_countdown ^=
                             ui.button(if as_countdown { "⬆" } else { "⬇" }).clicked();
                     },
                 );
@@ -187,37 +858,42 @@ pub fn table_ui(ui: &mut egui::Ui, reset: bool, app: &App) {
             });
         })
         .body(|body| {
-            body.rows(text_height, logs.entries().len(), |mut row| {
-                let row_index = row.index();
-                let entry = &logs.entries()[row_index];
+            body.rows(text_height, count, |mut row| {
+                let Some((id, entry)) = app.shown_logs_mut().row(row.index()).cloned() else {
+                    return;
+                };
+                row.set_selected(app.state.selected == Some(id));
                 row.col(|ui| {
-                    ui.label(row_index.to_string());
+                    ui.label(id.to_string());
                 });
                 row.col(|ui| {
-                    let as_countdown = app.ui_state_mut().table_state.as_countdown;
+                    let as_countdown = app.state.table_state.as_countdown;
 
                     if as_countdown {
                         let duration = std::time::Duration::from_secs(
                             (time::OffsetDateTime::now_utc() - entry.timestamp).whole_seconds()
                                 as u64,
                         );
-                        ui.label(format!(
-                            "{} ago",
-                            humantime::format_duration(duration).to_string()
-                        ));
+                        ui.label(format!("{} ago", humantime::format_duration(duration)));
                     } else {
-                        ui.label(format!(
-                            "{}",
-                            entry.timestamp.format(default_time_format()).unwrap()
-                        ));
+                        ui.label(entry.timestamp.format(display_time_format()).unwrap());
                     }
                 });
                 row.col(|ui| {
-                    ui.colored_label(entry.level.color(), entry.level.to_string());
+                    let color = app.state.levels.color(&entry.level, ui.visuals().dark_mode);
+                    match &entry.raw {
+                        Some(raw) => {
+                            ui.colored_label(color, "⚠ RAW")
+                                .on_hover_ui(|ui| raw_payload_ui(ui, raw));
+                        }
+                        None => {
+                            ui.colored_label(color, entry.level.to_string());
+                        }
+                    }
                 });
                 row.col(|ui| {
                     ui.vertical(|ui| {
-                        ui.label(format!("{}:{}", entry.source.file, entry.source.line));
+                        ui.label(entry.source.to_string());
                     });
                 });
                 row.col(|ui| {
@@ -229,6 +905,9 @@ pub fn table_ui(ui: &mut egui::Ui, reset: bool, app: &App) {
                         ui.label(serde_json::to_string_pretty(v).expect("Failed to seriaze value"));
                     }
                 });
+                if row.response().clicked() {
+                    app.state.selected = Some(id);
+                }
             })
         });
 }
@@ -13,6 +13,16 @@ pub fn default_time_format() -> &'static [time::format_description::FormatItem<'
     })
 }
 
+/// How timestamps are shown, keeping milliseconds to tell close entries apart
+pub fn display_time_format() -> &'static [time::format_description::FormatItem<'static>] {
+    static MEM: OnceLock<&[time::format_description::FormatItem<'static>]> = OnceLock::new();
+    MEM.get_or_init(|| {
+        time::macros::format_description!(
+            "[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond digits:3] [offset_hour sign:mandatory]:[offset_minute]"
+        )
+    })
+}
+
 /// This macro is for tracing error and returning Result if there are some
 /// meaningful Ok() case, and returning () if there are no meaningful result.
 /// It is useful to simply trace error message on fallible operations which doesn't
@@ -33,7 +43,6 @@ macro_rules! trace_err {
             Ok(()) => (),
             Err(e) => {
                 println!("{e}");
-                ()
             }
         }
     };
@@ -2,10 +2,69 @@
 
 use bucklog::app;
 use bucklog::app::config;
+use bucklog::app::instance::{self, Launch, Request};
 use egui::{Vec2, ViewportBuilder};
 
+const USAGE: &str = "Usage: bucklog [--stdin] [--] [FILE]...
+
+Shows logs received from the configured sources. FILEs are imported into
+sessions of their own, by the running instance if there is one.
+
+Options:
+  --stdin     Read log lines from stdin, on by default when it's piped
+  -h, --help  Print this help";
+
 fn main() {
-    let config = config::Settings::load_configuration();
+    // Fails once there are other threads
+    let local_offset = time::UtcOffset::current_local_offset().ok();
+    let mut stdin = false;
+    // Files to import, made absolute for the running instance
+    let mut files = Vec::new();
+    let mut args = std::env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--stdin") => stdin = true,
+            Some("-h" | "--help") => {
+                println!("{USAGE}");
+                return;
+            }
+            // Everything after is a file, even if it starts with a dash
+            Some("--") => {
+                files.extend(args.by_ref());
+                break;
+            }
+            Some(flag) if flag.starts_with('-') && flag != "-" => {
+                eprintln!("Unknown option {flag}\n\n{USAGE}");
+                std::process::exit(2);
+            }
+            _ => files.push(arg),
+        }
+    }
+    let files: Vec<_> = files
+        .into_iter()
+        .filter_map(|arg| std::path::absolute(arg).ok())
+        .collect();
+    let stdin = stdin || stdin_piped();
+    // Piped lines can't be handed over, only the recording instance reads them
+    let requests: Vec<_> = if stdin {
+        Vec::new()
+    } else if files.is_empty() {
+        vec![Request::Show]
+    } else {
+        files.iter().cloned().map(Request::Import).collect()
+    };
+    let lock = match instance::launch(&requests) {
+        Launch::Primary(lock) => Some(lock),
+        Launch::HandedOff => return,
+        Launch::ReadOnly if stdin => {
+            eprintln!("Another bucklog instance is recording, quit it to read from stdin");
+            std::process::exit(1);
+        }
+        Launch::ReadOnly => None,
+    };
+    let mut config = config::Settings::load_configuration();
+    config.stdin = stdin;
+    config.local_offset = local_offset;
     let native_options = eframe::NativeOptions {
         viewport: ViewportBuilder::default()
             .with_always_on_top()
@@ -15,7 +74,25 @@ fn main() {
     eframe::run_native(
         "My egui App",
         native_options,
-        Box::new(|cc| Ok(Box::new(app::App::new(cc, config)))),
+        Box::new(|cc| Ok(Box::new(app::App::new(cc, config, lock, files)))),
     )
     .expect("Failed to run app");
 }
+
+/// Stdin is a pipe or a file. Launchers often pass `/dev/null`, which is no
+/// terminal either.
+#[cfg(unix)]
+fn stdin_piped() -> bool {
+    use std::os::{fd::AsFd, unix::fs::FileTypeExt};
+    std::io::stdin()
+        .as_fd()
+        .try_clone_to_owned()
+        .and_then(|fd| std::fs::File::from(fd).metadata())
+        .is_ok_and(|meta| meta.file_type().is_fifo() || meta.is_file())
+}
+
+/// GUI processes get no usable stdin there, only `--stdin` turns it on
+#[cfg(not(unix))]
+fn stdin_piped() -> bool {
+    false
+}
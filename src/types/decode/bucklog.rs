@@ -0,0 +1,72 @@
+use serde_json::Value;
+use time::format_description::well_known::Rfc3339;
+
+use super::{DecodeError, Decoder, JsonObject, LogDecoder, LogFormat};
+use crate::types::json_log::LogEntry;
+
+/// Set by us when receiving an entry, producers don't get to fill them in
+const INTERNAL_KEYS: [&str; 3] = ["origin", "source_id", "raw"];
+
+/// Layout [`LogEntry`] is serialized with
+pub struct Bucklog;
+
+impl LogDecoder for Bucklog {
+    fn format(&self) -> LogFormat {
+        LogFormat::Bucklog
+    }
+
+    fn detect(&self, obj: &JsonObject) -> bool {
+        obj.get("source").is_some_and(Value::is_object)
+            && obj.get("message").is_some_and(Value::is_string)
+    }
+
+    fn decode(&self, mut obj: JsonObject, ctx: &Decoder) -> Result<LogEntry, DecodeError> {
+        // Serde only knows built-in levels and timestamp formats, resolve
+        // configured ones first
+        let level = ctx.take_level(&mut obj, &["level"])?;
+        obj.insert("level".into(), serde_json::to_value(level)?);
+        let timestamp = ctx.take_timestamp(&mut obj, &["timestamp"])?;
+        let timestamp = timestamp
+            .format(&Rfc3339)
+            .map_err(|e| DecodeError(e.to_string()))?;
+        obj.insert("timestamp".into(), Value::String(timestamp));
+        for key in INTERNAL_KEYS {
+            obj.remove(key);
+        }
+        Ok(serde_json::from_value(Value::Object(obj))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use time::macros::datetime;
+
+    use super::*;
+
+    #[test]
+    fn uses_configured_timestamps_and_skips_internal_keys() {
+        let decoder = Decoder::new(
+            LogFormat::Bucklog,
+            &["[day].[month].[year] [hour]:[minute]:[second]".to_string()],
+            Default::default(),
+            None,
+        )
+        .unwrap();
+        let entry = decoder
+            .decode(json!({
+                "timestamp": "24.12.2024 18:30:00",
+                "level": "INFO",
+                "message": "hi",
+                "source": {},
+                "source_id": "forged",
+                "origin": {"exchange": "x", "routing_key": "y"},
+                "raw": {"error": "", "data": ""},
+            }))
+            .unwrap();
+        assert_eq!(entry.timestamp, datetime!(2024-12-24 18:30 UTC));
+        assert!(entry.source_id.is_none());
+        assert!(entry.origin.is_none());
+        assert!(!entry.is_raw());
+    }
+}
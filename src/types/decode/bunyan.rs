@@ -0,0 +1,47 @@
+use serde_json::Value;
+
+use super::{
+    into_fields, take_string, take_usize, DecodeError, Decoder, JsonObject, LogDecoder, LogFormat,
+};
+use crate::types::json_log::{LogEntry, Source};
+
+/// Node's bunyan: `{"v":0,"level":30,"name":..,"hostname":..,"pid":..,"time":..,"msg":..}`
+pub struct Bunyan;
+
+impl LogDecoder for Bunyan {
+    fn format(&self) -> LogFormat {
+        LogFormat::Bunyan
+    }
+
+    fn detect(&self, obj: &JsonObject) -> bool {
+        obj.get("v").is_some_and(Value::is_number)
+            && obj.contains_key("msg")
+            && obj.contains_key("hostname")
+    }
+
+    fn decode(&self, mut obj: JsonObject, ctx: &Decoder) -> Result<LogEntry, DecodeError> {
+        obj.remove("v");
+        let timestamp = ctx.take_timestamp(&mut obj, &["time"])?;
+        let level = ctx.take_level(&mut obj, &["level"])?;
+        let message = take_string(&mut obj, &["msg"]).unwrap_or_default();
+        let source = Source {
+            target: take_string(&mut obj, &["name"]),
+            function: take_string(&mut obj, &["src", "func"]),
+            file: take_string(&mut obj, &["src", "file"]),
+            line: take_usize(&mut obj, &["src", "line"]),
+            thread: None,
+        };
+        Ok(LogEntry {
+            timestamp,
+            level,
+            message,
+            fields: into_fields(obj),
+            span: None,
+            spans: Vec::new(),
+            source,
+            origin: None,
+            source_id: None,
+            raw: None,
+        })
+    }
+}
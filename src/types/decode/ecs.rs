@@ -0,0 +1,53 @@
+use super::{
+    into_fields, take_path, take_string, take_usize, DecodeError, Decoder, JsonObject, LogDecoder,
+    LogFormat,
+};
+use crate::types::json_log::{LogEntry, Source};
+
+/// Logstash JSON (`logger_name`, `thread_name`) and Elastic Common Schema
+/// (`log.level`, `log.logger`, `log.origin`), both keyed by `@timestamp`
+pub struct Ecs;
+
+impl LogDecoder for Ecs {
+    fn format(&self) -> LogFormat {
+        LogFormat::Ecs
+    }
+
+    fn detect(&self, obj: &JsonObject) -> bool {
+        obj.contains_key("@timestamp")
+    }
+
+    fn decode(&self, mut obj: JsonObject, ctx: &Decoder) -> Result<LogEntry, DecodeError> {
+        let timestamp = ctx.take_timestamp(&mut obj, &["@timestamp"])?;
+        let level = take_path(&mut obj, &["log", "level"])
+            .or_else(|| take_path(&mut obj, &["level"]))
+            .ok_or_else(|| DecodeError("Missing \"log.level\"".into()))?;
+        let level = ctx.levels.parse(&level)?;
+        let message = take_string(&mut obj, &["message"]).unwrap_or_default();
+        let source = Source {
+            target: take_string(&mut obj, &["log", "logger"])
+                .or_else(|| take_string(&mut obj, &["logger_name"])),
+            function: take_string(&mut obj, &["log", "origin", "function"]),
+            file: take_string(&mut obj, &["log", "origin", "file", "name"]),
+            line: take_usize(&mut obj, &["log", "origin", "file", "line"]),
+            thread: take_string(&mut obj, &["process", "thread", "name"])
+                .or_else(|| take_string(&mut obj, &["thread_name"])),
+        };
+        for meta in ["@version", "level_value"] {
+            obj.remove(meta);
+        }
+        take_path(&mut obj, &["ecs", "version"]);
+        Ok(LogEntry {
+            timestamp,
+            level,
+            message,
+            fields: into_fields(obj),
+            span: None,
+            spans: Vec::new(),
+            source,
+            origin: None,
+            source_id: None,
+            raw: None,
+        })
+    }
+}
@@ -0,0 +1,345 @@
+//! Turns JSON produced by various logging libraries into [`LogEntry`].
+//!
+//! Every supported layout is a [`LogDecoder`]. A source either names the
+//! layout it receives with [`LogFormat`] or lets us guess it per message.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use time::{OffsetDateTime, UtcOffset};
+
+use payload::Payload;
+
+use super::{
+    json_log::{Level, LogEntry, RawPayload},
+    level::LevelTable,
+    timestamp::TimestampParser,
+};
+
+mod bucklog;
+mod bunyan;
+mod ecs;
+mod payload;
+mod pino;
+pub mod syslog;
+mod tracing;
+
+pub type JsonObject = Map<String, Value>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeError(pub String);
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<serde_json::Error> for DecodeError {
+    fn from(e: serde_json::Error) -> Self {
+        DecodeError(e.to_string())
+    }
+}
+
+/// Item of a batched message that failed to decode
+#[derive(Debug)]
+pub struct BatchError {
+    pub index: usize,
+    /// The item alone as JSON, or the line as it was
+    pub data: Vec<u8>,
+    pub error: DecodeError,
+}
+
+/// One log layout we know how to read
+pub trait LogDecoder: Sync {
+    fn format(&self) -> LogFormat;
+    /// Cheap check whether the object looks like it was written in this layout
+    fn detect(&self, obj: &JsonObject) -> bool;
+    fn decode(&self, obj: JsonObject, ctx: &Decoder) -> Result<LogEntry, DecodeError>;
+}
+
+/// Order matters for auto detection: more specific layouts go first
+static DECODERS: &[&dyn LogDecoder] = &[
+    &bucklog::Bucklog,
+    &ecs::Ecs,
+    &bunyan::Bunyan,
+    &pino::Pino,
+    &tracing::Tracing,
+];
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Guess the layout of every message
+    #[default]
+    Auto,
+    /// Our own layout
+    Bucklog,
+    /// `tracing_subscriber::fmt().json()`
+    Tracing,
+    Bunyan,
+    Pino,
+    /// Logstash JSON and Elastic Common Schema
+    Ecs,
+}
+
+impl LogFormat {
+    pub const ALL: [LogFormat; 6] = [
+        LogFormat::Auto,
+        LogFormat::Bucklog,
+        LogFormat::Tracing,
+        LogFormat::Bunyan,
+        LogFormat::Pino,
+        LogFormat::Ecs,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogFormat::Auto => "auto",
+            LogFormat::Bucklog => "bucklog",
+            LogFormat::Tracing => "tracing",
+            LogFormat::Bunyan => "bunyan",
+            LogFormat::Pino => "pino",
+            LogFormat::Ecs => "logstash/ecs",
+        }
+    }
+}
+
+/// Everything needed to turn a message of a source into [`LogEntry`]
+#[derive(Default)]
+pub struct Decoder {
+    format: LogFormat,
+    timestamps: TimestampParser,
+    pub(crate) levels: LevelTable,
+    /// BSD syslog timestamps are local time at that offset, UTC if unknown
+    pub(crate) local_offset: Option<UtcOffset>,
+}
+
+impl Decoder {
+    /// `time_formats` are extra timestamp formats, see [`TimestampParser::new`]
+    pub fn new(
+        format: LogFormat,
+        time_formats: &[String],
+        levels: LevelTable,
+        local_offset: Option<UtcOffset>,
+    ) -> Result<Self, DecodeError> {
+        Ok(Decoder {
+            format,
+            timestamps: TimestampParser::new(time_formats)?,
+            levels,
+            local_offset,
+        })
+    }
+
+    pub fn decode(&self, value: Value) -> Result<LogEntry, DecodeError> {
+        let Value::Object(obj) = value else {
+            return Err(DecodeError(format!(
+                "Expected a JSON object, got {}",
+                type_name(&value)
+            )));
+        };
+        let decoder = match self.format {
+            LogFormat::Auto => DECODERS
+                .iter()
+                .find(|d| d.detect(&obj))
+                .ok_or_else(|| DecodeError("Unrecognized log layout".into()))?,
+            format => DECODERS
+                .iter()
+                .find(|d| d.format() == format)
+                .expect("Every format has a decoder"),
+        };
+        decoder.decode(obj, self)
+    }
+
+    /// Decodes a message as it arrived from a source, see [`payload::to_value`]
+    pub fn decode_payload(
+        &self,
+        data: &[u8],
+        content_type: Option<&str>,
+        content_encoding: Option<&str>,
+    ) -> Result<LogEntry, DecodeError> {
+        self.decode(payload::to_value(data, content_type, content_encoding)?)
+    }
+
+    /// Decodes a message that may hold many log lines, see
+    /// [`payload::to_payload`]. A line failing does not affect the others,
+    /// `Err` means the message as a whole is unreadable.
+    pub fn decode_batch(
+        &self,
+        data: &[u8],
+        content_type: Option<&str>,
+        content_encoding: Option<&str>,
+    ) -> Result<Vec<Result<LogEntry, BatchError>>, DecodeError> {
+        if content_type == Some(syslog::CONTENT_TYPE) {
+            return Ok(vec![Ok(self.decode_syslog(data)?)]);
+        }
+        let items = match payload::to_payload(data, content_type, content_encoding)? {
+            Payload::Single(value) => return Ok(vec![Ok(self.decode(value)?)]),
+            Payload::Batch(items) => items,
+        };
+        let entries = items
+            .into_iter()
+            .enumerate()
+            .map(|(index, item)| {
+                let value = item.map_err(|(data, error)| BatchError { index, data, error })?;
+                // Decoding consumes the value, keep a copy for the error
+                self.decode(value.clone()).map_err(|error| BatchError {
+                    index,
+                    data: serde_json::to_vec(&value).unwrap_or_default(),
+                    error,
+                })
+            })
+            .collect();
+        Ok(entries)
+    }
+
+    /// Like [`Decoder::decode_batch`], but lines that failed are kept as
+    /// raw entries
+    pub fn decode_entries(
+        &self,
+        data: &[u8],
+        content_type: Option<&str>,
+        content_encoding: Option<&str>,
+    ) -> Result<Vec<LogEntry>, DecodeError> {
+        let items = self.decode_batch(data, content_type, content_encoding)?;
+        let entries = items
+            .into_iter()
+            .map(|item| {
+                item.unwrap_or_else(|e| {
+                    LogEntry::raw(RawPayload {
+                        error: format!("Line {}: {}", e.index + 1, e.error),
+                        data: e.data,
+                        batch_index: Some(e.index),
+                    })
+                })
+            })
+            .collect();
+        Ok(entries)
+    }
+
+    pub(crate) fn take_level(
+        &self,
+        obj: &mut JsonObject,
+        path: &[&str],
+    ) -> Result<Level, DecodeError> {
+        let value = take_path(obj, path)
+            .ok_or_else(|| DecodeError(format!("Missing \"{}\"", path.join("."))))?;
+        self.levels.parse(&value)
+    }
+
+    pub(crate) fn take_timestamp(
+        &self,
+        obj: &mut JsonObject,
+        path: &[&str],
+    ) -> Result<OffsetDateTime, DecodeError> {
+        let value = take_path(obj, path)
+            .ok_or_else(|| DecodeError(format!("Missing \"{}\"", path.join("."))))?;
+        self.timestamps.parse(&value)
+    }
+}
+
+fn type_name(v: &Value) -> &'static str {
+    match v {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+/// Removes the value under `path` looking both for a dotted key
+/// (`"log.level"`) and for nested objects (`{"log": {"level": ..}}`).
+/// Parent objects left empty are removed too.
+pub(crate) fn take_path(obj: &mut JsonObject, path: &[&str]) -> Option<Value> {
+    if let Some(v) = obj.remove(&path.join(".")) {
+        return Some(v);
+    }
+    let (first, rest) = path.split_first()?;
+    if rest.is_empty() {
+        return obj.remove(*first);
+    }
+    let Some(Value::Object(child)) = obj.get_mut(*first) else {
+        return None;
+    };
+    let v = take_path(child, rest);
+    if child.is_empty() {
+        obj.remove(*first);
+    }
+    v
+}
+
+pub(crate) fn take_string(obj: &mut JsonObject, path: &[&str]) -> Option<String> {
+    match take_path(obj, path)? {
+        Value::String(s) => Some(s),
+        Value::Null => None,
+        other => Some(other.to_string()),
+    }
+}
+
+pub(crate) fn take_usize(obj: &mut JsonObject, path: &[&str]) -> Option<usize> {
+    match take_path(obj, path)? {
+        Value::Number(n) => n.as_u64().map(|n| n as usize),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Whatever is left in the object after taking known keys becomes fields
+pub(crate) fn into_fields(obj: JsonObject) -> std::collections::HashMap<String, Value> {
+    obj.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINE: &str = r#"{"timestamp":"2024-03-01T10:20:30Z","level":"INFO","target":"app","fields":{"message":"hi"}}"#;
+
+    #[test]
+    fn keeps_bad_lines_of_batches() {
+        let data = format!("{LINE}\nnot json\n{{\"unknown\":1}}\n{LINE}\n");
+        let entries = Decoder::default()
+            .decode_entries(data.as_bytes(), Some("application/x-ndjson"), None)
+            .unwrap();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0].message, "hi");
+        assert_eq!(entries[3].message, "hi");
+
+        let raw = entries[1].raw.as_ref().unwrap();
+        assert_eq!(raw.batch_index, Some(1));
+        assert!(raw.error.starts_with("Line 2: "), "{}", raw.error);
+        assert_eq!(raw.data, b"not json");
+
+        let raw = entries[2].raw.as_ref().unwrap();
+        assert_eq!(raw.batch_index, Some(2));
+        assert_eq!(raw.error, "Line 3: Unrecognized log layout");
+        assert_eq!(raw.data, br#"{"unknown":1}"#);
+    }
+
+    #[test]
+    fn decodes_arrays_item_by_item() {
+        let data = format!("[{LINE}, 5]");
+        let entries = Decoder::default()
+            .decode_entries(data.as_bytes(), None, None)
+            .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(!entries[0].is_raw());
+        let raw = entries[1].raw.as_ref().unwrap();
+        assert_eq!(raw.data, b"5");
+        assert_eq!(raw.error, "Line 2: Expected a JSON object, got a number");
+    }
+
+    #[test]
+    fn fails_unreadable_messages_whole() {
+        let decoder = Decoder::default();
+        assert!(decoder.decode_entries(b"not json", None, None).is_err());
+        let single = decoder.decode_entries(LINE.as_bytes(), None, None).unwrap();
+        assert_eq!(single.len(), 1);
+        assert!(decoder
+            .decode_entries(br#"{"unknown":1}"#, None, None)
+            .is_err());
+    }
+}
@@ -0,0 +1,176 @@
+//! Turns message bytes into JSON according to its `content_encoding` and
+//! `content_type`. Messages without them are taken as plain JSON.
+
+use std::io::Read;
+
+use serde_json::Value;
+
+use super::DecodeError;
+
+/// Decompressed messages bigger than that are refused
+const MAX_DECOMPRESSED: u64 = 64 * 1024 * 1024;
+
+/// Message holding one or many log lines
+pub enum Payload {
+    Single(Value),
+    /// JSON array or newline delimited JSON. Lines that failed to parse
+    /// come with their bytes.
+    Batch(Vec<Result<Value, (Vec<u8>, DecodeError)>>),
+}
+
+pub fn to_value(
+    data: &[u8],
+    content_type: Option<&str>,
+    content_encoding: Option<&str>,
+) -> Result<Value, DecodeError> {
+    match to_payload(data, content_type, content_encoding)? {
+        Payload::Single(value) => Ok(value),
+        Payload::Batch(_) => Err(DecodeError("Expected a single message, got a batch".into())),
+    }
+}
+
+pub fn to_payload(
+    data: &[u8],
+    content_type: Option<&str>,
+    content_encoding: Option<&str>,
+) -> Result<Payload, DecodeError> {
+    let data = decompress(data, content_encoding)?;
+    // Parameters like `; charset=utf-8` don't matter to us
+    let mime = content_type
+        .and_then(|t| t.split(';').next())
+        .map(|t| t.trim().to_lowercase());
+    let value = match mime.as_deref() {
+        Some("application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack") => {
+            rmp_serde::from_slice(&data)
+                .map_err(|e| DecodeError(format!("Invalid MessagePack: {e}")))?
+        }
+        Some("application/cbor") => ciborium::from_reader(data.as_slice())
+            .map_err(|e| DecodeError(format!("Invalid CBOR: {e}")))?,
+        Some(
+            "application/x-ndjson"
+            | "application/ndjson"
+            | "application/jsonl"
+            | "application/x-jsonlines",
+        ) => return Ok(lines(&data)),
+        // JSON, text or whatever producers put there
+        _ => match serde_json::from_slice(&data) {
+            Ok(value) => value,
+            // Could be NDJSON sent without content type
+            Err(_) if looks_like_ndjson(&data) => return Ok(lines(&data)),
+            Err(e) => return Err(e.into()),
+        },
+    };
+    Ok(match value {
+        Value::Array(items) => Payload::Batch(items.into_iter().map(Ok).collect()),
+        value => Payload::Single(value),
+    })
+}
+
+fn non_empty_lines(data: &[u8]) -> impl Iterator<Item = &[u8]> {
+    data.split(|&b| b == b'\n')
+        .filter(|line| !line.trim_ascii().is_empty())
+}
+
+/// Many lines, each an object. Anything else is rather text with a line
+/// break or two, better kept whole.
+fn looks_like_ndjson(data: &[u8]) -> bool {
+    non_empty_lines(data).take(2).count() > 1
+        && non_empty_lines(data).all(|line| line.trim_ascii_start().starts_with(b"{"))
+}
+
+fn lines(data: &[u8]) -> Payload {
+    Payload::Batch(
+        non_empty_lines(data)
+            .map(|line| serde_json::from_slice(line).map_err(|e| (line.to_vec(), e.into())))
+            .collect(),
+    )
+}
+
+fn decompress(data: &[u8], content_encoding: Option<&str>) -> Result<Vec<u8>, DecodeError> {
+    let encoding = content_encoding.map(|e| e.trim().to_lowercase());
+    let reader: Box<dyn Read + '_> = match encoding.as_deref() {
+        None | Some("" | "identity" | "utf-8" | "utf8") => return Ok(data.to_vec()),
+        Some("gzip" | "x-gzip") => Box::new(flate2::read::MultiGzDecoder::new(data)),
+        Some("deflate") => Box::new(flate2::read::ZlibDecoder::new(data)),
+        Some("zstd") => Box::new(
+            zstd::Decoder::new(data).map_err(|e| DecodeError(format!("Invalid zstd: {e}")))?,
+        ),
+        Some(other) => return Err(DecodeError(format!("Unsupported content encoding {other}"))),
+    };
+    let mut out = Vec::new();
+    reader
+        .take(MAX_DECOMPRESSED + 1)
+        .read_to_end(&mut out)
+        .map_err(|e| DecodeError(format!("Failed to decompress {encoding:?} message: {e}")))?;
+    if out.len() as u64 > MAX_DECOMPRESSED {
+        return Err(DecodeError(format!(
+            "Message decompresses to more than {} MiB",
+            MAX_DECOMPRESSED / 1024 / 1024
+        )));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use serde_json::json;
+
+    use super::*;
+
+    fn batch(payload: Payload) -> Vec<Result<Value, (Vec<u8>, DecodeError)>> {
+        match payload {
+            Payload::Batch(items) => items,
+            Payload::Single(value) => panic!("Expected a batch, got {value}"),
+        }
+    }
+
+    #[test]
+    fn splits_ndjson() {
+        let data = b"{\"a\":1}\n\n{\"a\":2}\r\n{broken\n";
+        let items =
+            batch(to_payload(data, Some("application/x-ndjson; charset=utf-8"), None).unwrap());
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[0].as_ref().unwrap(), &json!({"a": 1}));
+        assert_eq!(items[1].as_ref().unwrap(), &json!({"a": 2}));
+        let (line, _) = items[2].as_ref().unwrap_err();
+        assert_eq!(line, b"{broken");
+
+        // Told apart by its lines without a content type
+        let items = batch(to_payload(b"{\"a\":1}\n{\"a\":2}\n", None, None).unwrap());
+        assert_eq!(items.len(), 2);
+    }
+
+    #[test]
+    fn keeps_single_messages_whole() {
+        let single = to_payload(b"{\"a\":\n1}\n", Some("application/json"), None).unwrap();
+        assert!(matches!(single, Payload::Single(v) if v == json!({"a": 1})));
+
+        // Text with line breaks isn't NDJSON
+        assert!(to_payload(b"{\"a\":1}\nplain text\n", None, None).is_err());
+        assert!(to_payload(b"{\"a\":1\n", None, None).is_err());
+
+        assert!(to_value(b"[1, 2]", None, None).is_err());
+    }
+
+    #[test]
+    fn expands_arrays() {
+        let items = batch(to_payload(b"[{\"a\":1}, 2]", None, None).unwrap());
+        let values: Vec<_> = items.into_iter().map(Result::unwrap).collect();
+        assert_eq!(values, [json!({"a": 1}), json!(2)]);
+    }
+
+    #[test]
+    fn decompresses() {
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(b"{\"a\":1}").unwrap();
+        let data = gz.finish().unwrap();
+        assert_eq!(
+            to_value(&data, None, Some("GZIP")).unwrap(),
+            json!({"a": 1})
+        );
+        assert!(to_value(&data, None, Some("br")).is_err());
+        assert!(to_value(b"not gzip", None, Some("gzip")).is_err());
+    }
+}
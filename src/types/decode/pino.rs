@@ -0,0 +1,37 @@
+use super::{into_fields, take_string, DecodeError, Decoder, JsonObject, LogDecoder, LogFormat};
+use crate::types::json_log::{LogEntry, Source};
+
+/// Node's pino: `{"level":30,"time":1531171074631,"pid":..,"hostname":..,"msg":..}`
+pub struct Pino;
+
+impl LogDecoder for Pino {
+    fn format(&self) -> LogFormat {
+        LogFormat::Pino
+    }
+
+    fn detect(&self, obj: &JsonObject) -> bool {
+        obj.contains_key("msg") && obj.contains_key("time") && obj.contains_key("level")
+    }
+
+    fn decode(&self, mut obj: JsonObject, ctx: &Decoder) -> Result<LogEntry, DecodeError> {
+        let timestamp = ctx.take_timestamp(&mut obj, &["time"])?;
+        let level = ctx.take_level(&mut obj, &["level"])?;
+        let message = take_string(&mut obj, &["msg"]).unwrap_or_default();
+        let source = Source {
+            target: take_string(&mut obj, &["name"]),
+            ..Default::default()
+        };
+        Ok(LogEntry {
+            timestamp,
+            level,
+            message,
+            fields: into_fields(obj),
+            span: None,
+            spans: Vec::new(),
+            source,
+            origin: None,
+            source_id: None,
+            raw: None,
+        })
+    }
+}
@@ -0,0 +1,471 @@
+//! Syslog messages, RFC 5424 and the older BSD format of RFC 3164. The
+//! header goes to fields, JSON embedded in the message text is decoded like
+//! any other message.
+
+use serde_json::{Map, Value};
+use time::{
+    format_description::well_known::Rfc3339, Date, Month, OffsetDateTime, PrimitiveDateTime, Time,
+    UtcOffset,
+};
+
+use super::{into_fields, take_string, DecodeError, Decoder, JsonObject};
+use crate::types::json_log::{Level, LogEntry, Source};
+
+/// Content type of deliveries holding a syslog message
+pub const CONTENT_TYPE: &str = "application/x-syslog";
+
+/// Keywords of RFC 5424 severities, each an alias of a level of its own
+const SEVERITIES: [&str; 8] = [
+    "emerg", "alert", "crit", "err", "warning", "notice", "info", "debug",
+];
+
+const FACILITIES: [&str; 24] = [
+    "kern",
+    "user",
+    "mail",
+    "daemon",
+    "auth",
+    "syslog",
+    "lpr",
+    "news",
+    "uucp",
+    "cron",
+    "authpriv",
+    "ftp",
+    "ntp",
+    "security",
+    "console",
+    "solaris-cron",
+    "local0",
+    "local1",
+    "local2",
+    "local3",
+    "local4",
+    "local5",
+    "local6",
+    "local7",
+];
+
+/// Message with its header split off
+#[derive(Debug, Default)]
+struct Frame<'a> {
+    facility: u8,
+    severity: u8,
+    timestamp: Option<OffsetDateTime>,
+    hostname: Option<&'a str>,
+    app_name: Option<&'a str>,
+    procid: Option<&'a str>,
+    msgid: Option<&'a str>,
+    /// SD-ID to its params
+    structured_data: JsonObject,
+    msg: &'a str,
+}
+
+impl Decoder {
+    pub fn decode_syslog(&self, data: &[u8]) -> Result<LogEntry, DecodeError> {
+        let text = String::from_utf8_lossy(data);
+        let offset = self.local_offset.unwrap_or(UtcOffset::UTC);
+        let frame = parse(text.trim_end_matches(['\r', '\n', '\0']), offset)?;
+        let level = self.levels.parse_str(SEVERITIES[frame.severity as usize]);
+        let mut entry = match embedded_json(frame.msg) {
+            Some((prefix, obj)) => match self.decode(Value::Object(obj.clone())) {
+                Ok(entry) => entry,
+                // Not a layout we know, its keys are fields still
+                Err(_) => {
+                    let mut obj = obj;
+                    let message = take_string(&mut obj, &["message"])
+                        .or_else(|| take_string(&mut obj, &["msg"]))
+                        .unwrap_or_else(|| prefix.to_string());
+                    plain_entry(&frame, level, message, obj)
+                }
+            },
+            None => plain_entry(&frame, level, frame.msg.to_string(), Map::new()),
+        };
+        let header = [
+            ("facility", Some(FACILITIES[frame.facility as usize])),
+            ("hostname", frame.hostname),
+            ("app_name", frame.app_name),
+            ("procid", frame.procid),
+            ("msgid", frame.msgid),
+        ];
+        for (key, value) in header {
+            if let Some(value) = value {
+                entry
+                    .fields
+                    .entry(key.to_string())
+                    .or_insert_with(|| Value::String(value.to_string()));
+            }
+        }
+        if !frame.structured_data.is_empty() {
+            entry
+                .fields
+                .entry("structured_data".to_string())
+                .or_insert(Value::Object(frame.structured_data));
+        }
+        Ok(entry)
+    }
+}
+
+fn plain_entry(frame: &Frame, level: Level, message: String, fields: JsonObject) -> LogEntry {
+    LogEntry {
+        timestamp: frame.timestamp.unwrap_or_else(OffsetDateTime::now_utc),
+        level,
+        message,
+        fields: into_fields(fields),
+        span: None,
+        spans: Vec::new(),
+        source: Source::default(),
+        origin: None,
+        source_id: None,
+        raw: None,
+    }
+}
+
+/// BSD timestamps are taken in `local_offset`
+fn parse(text: &str, local_offset: UtcOffset) -> Result<Frame<'_>, DecodeError> {
+    let (pri, rest) = text
+        .strip_prefix('<')
+        .and_then(|rest| rest.split_once('>'))
+        .ok_or_else(|| DecodeError("Missing syslog priority".into()))?;
+    let pri: u8 = match pri.parse() {
+        Ok(pri) if pri <= 191 => pri,
+        _ => return Err(DecodeError(format!("Invalid syslog priority <{pri}>"))),
+    };
+    let mut frame = match rest.strip_prefix("1 ") {
+        Some(rest) => parse_5424(rest)?,
+        None => parse_3164(rest, local_offset),
+    };
+    frame.facility = pri / 8;
+    frame.severity = pri % 8;
+    Ok(frame)
+}
+
+/// `1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA [MSG]`,
+/// version already stripped
+fn parse_5424(text: &str) -> Result<Frame<'_>, DecodeError> {
+    let mut rest = text;
+    let mut next = || {
+        let (field, tail) = rest.split_once(' ').unwrap_or((rest, ""));
+        rest = tail;
+        // `-` stands for a missing value
+        (field != "-" && !field.is_empty()).then_some(field)
+    };
+    let timestamp = next()
+        .map(|ts| {
+            OffsetDateTime::parse(ts, &Rfc3339)
+                .map_err(|e| DecodeError(format!("Invalid syslog timestamp \"{ts}\": {e}")))
+        })
+        .transpose()?;
+    let hostname = next();
+    let app_name = next();
+    let procid = next();
+    let msgid = next();
+    let (structured_data, msg) = structured_data(rest)?;
+    Ok(Frame {
+        timestamp,
+        hostname,
+        app_name,
+        procid,
+        msgid,
+        structured_data,
+        msg: msg.strip_prefix('\u{feff}').unwrap_or(msg),
+        ..Default::default()
+    })
+}
+
+/// `-` or `[id name="value" ...]...`, returns what follows
+fn structured_data(text: &str) -> Result<(JsonObject, &str), DecodeError> {
+    let invalid = || DecodeError("Invalid syslog structured data".into());
+    let mut elements = Map::new();
+    if let Some(rest) = text.strip_prefix('-') {
+        return Ok((elements, rest.strip_prefix(' ').unwrap_or(rest)));
+    }
+    let mut rest = text;
+    while let Some(element) = rest.strip_prefix('[') {
+        let id_end = element.find([' ', ']']).ok_or_else(invalid)?;
+        let id = &element[..id_end];
+        rest = &element[id_end..];
+        let mut params = Map::new();
+        while let Some(param) = rest.strip_prefix(' ') {
+            let (name, value) = param.split_once("=\"").ok_or_else(invalid)?;
+            let (value, tail) = param_value(value).ok_or_else(invalid)?;
+            params.insert(name.to_string(), Value::String(value));
+            rest = tail;
+        }
+        rest = rest.strip_prefix(']').ok_or_else(invalid)?;
+        elements.insert(id.to_string(), Value::Object(params));
+    }
+    if elements.is_empty() {
+        return Err(invalid());
+    }
+    Ok((elements, rest.strip_prefix(' ').unwrap_or(rest)))
+}
+
+/// Value up to the closing quote, with `\"`, `\\` and `\]` unescaped
+fn param_value(text: &str) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = text.char_indices();
+    while let Some((idx, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, &text[idx + 1..])),
+            '\\' => match chars.next() {
+                Some((_, escaped @ ('"' | '\\' | ']'))) => value.push(escaped),
+                Some((_, other)) => {
+                    value.push('\\');
+                    value.push(other);
+                }
+                None => return None,
+            },
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+/// `Mmm dd hh:mm:ss HOSTNAME TAG[PID]: MSG`. Senders take liberties with
+/// it, whatever doesn't fit is left in the message.
+fn parse_3164(text: &str, local_offset: UtcOffset) -> Frame<'_> {
+    let mut frame = Frame::default();
+    let mut rest = text;
+    let timestamp = text
+        .get(..15)
+        .and_then(|ts| {
+            let now = OffsetDateTime::now_utc().to_offset(local_offset);
+            bsd_timestamp(ts, now)
+        })
+        .map(|ts| (ts, &text[15..]))
+        .or_else(|| {
+            // Some write RFC 3339 instead
+            let (ts, tail) = text.split_once(' ')?;
+            Some((OffsetDateTime::parse(ts, &Rfc3339).ok()?, tail))
+        });
+    if let Some((ts, tail)) = timestamp {
+        frame.timestamp = Some(ts);
+        rest = tail.trim_start();
+        // Hostname only follows a timestamp
+        if let Some((hostname, tail)) = rest.split_once(' ') {
+            if !hostname.ends_with(':') && !hostname.contains('[') {
+                frame.hostname = Some(hostname);
+                rest = tail;
+            }
+        }
+    }
+    if let Some((tag, msg)) = rest.split_once(':') {
+        let (app_name, procid) = match tag.split_once('[') {
+            Some((app_name, pid)) => (app_name, pid.strip_suffix(']')),
+            None => (tag, None),
+        };
+        let valid = |s: &str| !s.is_empty() && !s.contains(char::is_whitespace);
+        if valid(app_name) && procid.is_none_or(valid) {
+            frame.app_name = Some(app_name);
+            frame.procid = procid;
+            rest = msg.strip_prefix(' ').unwrap_or(msg);
+        }
+    }
+    frame.msg = rest;
+    frame
+}
+
+/// `Oct  9 22:14:15`, taken at the offset of `now` in the last year it
+/// could be
+fn bsd_timestamp(text: &str, now: OffsetDateTime) -> Option<OffsetDateTime> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let month = MONTHS.iter().position(|m| Some(*m) == text.get(..3))? as u8 + 1;
+    let month = Month::try_from(month).ok()?;
+    let day: u8 = text.get(4..6)?.trim_start().parse().ok()?;
+    let mut time = text.get(7..)?.split(':').map(|n| n.parse::<u8>().ok());
+    let time = Time::from_hms(time.next()??, time.next()??, time.next()??).ok()?;
+    // Messages from the end of last year arrive early in January
+    [now.year(), now.year() - 1].into_iter().find_map(|year| {
+        let date = Date::from_calendar_date(year, month, day).ok()?;
+        let ts = PrimitiveDateTime::new(date, time).assume_offset(now.offset());
+        (ts <= now + time::Duration::days(1)).then_some(ts)
+    })
+}
+
+/// JSON object ending the message, with the text before it. CEE's
+/// `@cee:` cookie isn't part of the text.
+fn embedded_json(msg: &str) -> Option<(&str, JsonObject)> {
+    let start = msg.find('{')?;
+    let Ok(Value::Object(obj)) = serde_json::from_str(msg[start..].trim_end()) else {
+        return None;
+    };
+    let prefix = msg[..start].trim();
+    let prefix = prefix.strip_prefix("@cee:").unwrap_or(prefix).trim();
+    Some((prefix, obj))
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::{datetime, offset};
+
+    use super::*;
+
+    fn decode(text: &str) -> LogEntry {
+        Decoder::default().decode_syslog(text.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn parses_5424() {
+        let frame = parse(
+            "<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 \
+             [exampleSDID@32473 iut=\"3\" eventSource=\"Application\"][meta seq=\"1\"] \
+             \u{feff}An application event",
+            UtcOffset::UTC,
+        )
+        .unwrap();
+        assert_eq!((frame.facility, frame.severity), (20, 5));
+        assert_eq!(
+            frame.timestamp,
+            Some(datetime!(2003-10-11 22:14:15.003 UTC))
+        );
+        assert_eq!(frame.hostname, Some("mymachine.example.com"));
+        assert_eq!(frame.app_name, Some("evntslog"));
+        assert_eq!(frame.procid, None);
+        assert_eq!(frame.msgid, Some("ID47"));
+        assert_eq!(
+            Value::Object(frame.structured_data),
+            serde_json::json!({
+                "exampleSDID@32473": {"iut": "3", "eventSource": "Application"},
+                "meta": {"seq": "1"},
+            })
+        );
+        assert_eq!(frame.msg, "An application event");
+
+        let frame = parse_5424("- - - - - -").unwrap();
+        assert_eq!(frame.timestamp, None);
+        assert_eq!(frame.hostname, None);
+        assert!(frame.structured_data.is_empty());
+        assert_eq!(frame.msg, "");
+
+        assert!(parse_5424("yesterday host app - - -").is_err());
+    }
+
+    #[test]
+    fn refuses_invalid_priority() {
+        for text in ["no priority", "<192>1 - - - - - -", "<x>msg", "<13"] {
+            assert!(parse(text, UtcOffset::UTC).is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn unescapes_structured_data() {
+        let (elements, rest) = structured_data(r#"[id a="q\"b\\s\]" b="\n" c=""] msg"#).unwrap();
+        assert_eq!(
+            Value::Object(elements),
+            serde_json::json!({"id": {"a": "q\"b\\s]", "b": "\\n", "c": ""}})
+        );
+        assert_eq!(rest, "msg");
+
+        assert_eq!(
+            param_value(r#"ab\"c" tail"#),
+            Some(("ab\"c".into(), " tail"))
+        );
+        assert_eq!(param_value("unterminated"), None);
+        assert_eq!(param_value("ends in \\"), None);
+
+        for invalid in ["[id a=\"1\"", "[id a=1]", "[id", "x"] {
+            assert!(structured_data(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn parses_3164() {
+        let offset = offset!(+2);
+        let frame = parse_3164(
+            "Oct 11 22:14:15 mymachine su[123]: 'su root' failed",
+            offset,
+        );
+        assert_eq!(frame.timestamp.map(|ts| ts.offset()), Some(offset));
+        assert_eq!(frame.hostname, Some("mymachine"));
+        assert_eq!(frame.app_name, Some("su"));
+        assert_eq!(frame.procid, Some("123"));
+        assert_eq!(frame.msg, "'su root' failed");
+
+        let frame = parse_3164("2024-03-01T10:00:00+01:00 host cron: job done", offset);
+        assert_eq!(frame.timestamp, Some(datetime!(2024-03-01 10:00 +1)));
+        assert_eq!(frame.hostname, Some("host"));
+        assert_eq!(frame.app_name, Some("cron"));
+        assert_eq!(frame.msg, "job done");
+
+        // Without timestamp there's no hostname, the tag may still be there
+        let frame = parse_3164("kernel: oops", offset);
+        assert_eq!(frame.timestamp, None);
+        assert_eq!(frame.hostname, None);
+        assert_eq!(frame.app_name, Some("kernel"));
+        assert_eq!(frame.msg, "oops");
+
+        let frame = parse_3164("just some text: with a colon", offset);
+        assert_eq!(frame.app_name, None);
+        assert_eq!(frame.msg, "just some text: with a colon");
+    }
+
+    #[test]
+    fn bsd_timestamps_roll_over_the_year() {
+        let now = datetime!(2025-01-01 00:30 +1);
+        assert_eq!(
+            bsd_timestamp("Dec 31 23:59:58", now),
+            Some(datetime!(2024-12-31 23:59:58 +1))
+        );
+        assert_eq!(
+            bsd_timestamp("Jan  1 00:29:00", now),
+            Some(datetime!(2025-01-01 00:29 +1))
+        );
+        // Clocks of the sender may be a bit ahead
+        assert_eq!(
+            bsd_timestamp("Jan  2 00:00:00", now),
+            Some(datetime!(2025-01-02 00:00 +1))
+        );
+        // Leap days are only in some years
+        assert_eq!(
+            bsd_timestamp("Feb 29 12:00:00", datetime!(2025-03-01 00:00 UTC)),
+            Some(datetime!(2024-02-29 12:00 UTC))
+        );
+        for invalid in [
+            "Foo  1 00:00:00",
+            "Jan 32 00:00:00",
+            "Jan  1 24:00:00",
+            "Jan  1",
+        ] {
+            assert_eq!(bsd_timestamp(invalid, now), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn finds_embedded_json() {
+        let (prefix, obj) = embedded_json(r#"started {"port": 80}"#).unwrap();
+        assert_eq!(prefix, "started");
+        assert_eq!(obj["port"], 80);
+
+        let (prefix, obj) = embedded_json(r#"@cee: {"msg": "hi"}  "#).unwrap();
+        assert_eq!(prefix, "");
+        assert_eq!(obj["msg"], "hi");
+
+        assert!(embedded_json("no json").is_none());
+        assert!(embedded_json("{not json}").is_none());
+        assert!(embedded_json(r#"{"a": 1} trailing"#).is_none());
+    }
+
+    #[test]
+    fn decodes_messages() {
+        let entry = decode("<11>1 2003-10-11T22:14:15Z host app 42 - - disk full\r\n");
+        assert_eq!(entry.level, Level::ERROR);
+        assert_eq!(entry.message, "disk full");
+        assert_eq!(entry.fields["facility"], "user");
+        assert_eq!(entry.fields["hostname"], "host");
+        assert_eq!(entry.fields["procid"], "42");
+        assert!(!entry.fields.contains_key("msgid"));
+
+        // Unknown layout, its keys become fields
+        let entry = decode(r#"<14>Oct 11 22:14:15 host app: @cee: {"msg": "hi", "user": "bob"}"#);
+        assert_eq!(entry.message, "hi");
+        assert_eq!(entry.fields["user"], "bob");
+        assert_eq!(entry.fields["app_name"], "app");
+
+        let entry = decode(r#"<14>app: {"user": "bob"}"#);
+        assert_eq!(entry.message, "");
+        assert_eq!(entry.fields["user"], "bob");
+    }
+}
@@ -0,0 +1,67 @@
+use serde_json::Value;
+
+use super::{
+    into_fields, take_string, take_usize, DecodeError, Decoder, JsonObject, LogDecoder, LogFormat,
+};
+use crate::types::json_log::{LogEntry, Source};
+
+/// Output of `tracing_subscriber::fmt().json()`, with or without `flatten_event`
+pub struct Tracing;
+
+impl LogDecoder for Tracing {
+    fn format(&self) -> LogFormat {
+        LogFormat::Tracing
+    }
+
+    fn detect(&self, obj: &JsonObject) -> bool {
+        obj.get("fields").is_some_and(Value::is_object)
+            || (obj.contains_key("target") && obj.contains_key("timestamp"))
+    }
+
+    fn decode(&self, mut obj: JsonObject, ctx: &Decoder) -> Result<LogEntry, DecodeError> {
+        let timestamp = ctx.take_timestamp(&mut obj, &["timestamp"])?;
+        let level = ctx.take_level(&mut obj, &["level"])?;
+        let mut fields = match obj.remove("fields") {
+            Some(Value::Object(fields)) => fields,
+            _ => JsonObject::new(),
+        };
+        // With `flatten_event(true)` event fields live at the top level
+        let message = take_string(&mut fields, &["message"])
+            .or_else(|| take_string(&mut obj, &["message"]))
+            .unwrap_or_default();
+        let source = Source {
+            target: take_string(&mut obj, &["target"]),
+            function: None,
+            file: take_string(&mut obj, &["filename"]),
+            line: take_usize(&mut obj, &["line_number"]),
+            thread: take_string(&mut obj, &["threadName"]),
+        };
+        let span = match obj.remove("span") {
+            Some(Value::Object(span)) => Some(into_fields(span)),
+            _ => None,
+        };
+        let spans = match obj.remove("spans") {
+            Some(Value::Array(spans)) => spans
+                .into_iter()
+                .filter_map(|s| match s {
+                    Value::Object(s) => Some(into_fields(s)),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        fields.extend(obj);
+        Ok(LogEntry {
+            timestamp,
+            level,
+            message,
+            fields: into_fields(fields),
+            span,
+            spans,
+            source,
+            origin: None,
+            source_id: None,
+            raw: None,
+        })
+    }
+}
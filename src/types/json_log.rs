@@ -1,77 +1,244 @@
 use std::collections::HashMap;
 
-use egui::Color32;
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 
-#[derive(Serialize, Deserialize, Debug)]
-pub enum Level {
-    INFO,
-    ERROR,
-    TRACE,
-    DEBUG,
-    WARN,
-}
+pub use super::level::Level;
 
-impl Level {
-    pub fn color(&self) -> Color32 {
-        match self {
-            Level::INFO => Color32::GREEN,
-            Level::ERROR => Color32::RED,
-            Level::TRACE => Color32::BLUE,
-            Level::DEBUG => Color32::ORANGE,
-            Level::WARN => Color32::YELLOW,
-        }
-    }
-    pub fn to_string(&self) -> String {
-        match self {
-            Level::INFO => "INFO".to_string(),
-            Level::ERROR => "ERROR".to_string(),
-            Level::TRACE => "TRACE".to_string(),
-            Level::DEBUG => "DEBUG".to_string(),
-            Level::WARN => "WARN".to_string(),
-        }
-    }
-}
-
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Source {
     pub target: Option<String>,
     pub function: Option<String>,
-    pub file: String,
-    pub line: usize,
+    pub file: Option<String>,
+    pub line: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread: Option<String>,
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.file, self.line, &self.target) {
+            (Some(file), Some(line), _) => write!(f, "{file}:{line}"),
+            (Some(file), None, _) => f.write_str(file),
+            (None, _, Some(target)) => f.write_str(target),
+            (None, _, None) => Ok(()),
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LogEntry {
     #[serde(with = "log_time_format")]
     pub timestamp: OffsetDateTime,
     pub level: Level,
     pub message: String,
+    #[serde(default)]
     pub fields: HashMap<String, serde_json::Value>,
     pub span: Option<HashMap<String, serde_json::Value>>,
+    /// Whole span stack, outermost first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub spans: Vec<HashMap<String, serde_json::Value>>,
+    #[serde(default)]
     pub source: Source,
+    /// Where the message came from, for entries received from a broker
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<Origin>,
+    /// Id of the source the entry was received from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_id: Option<String>,
+    /// Set when the message could not be decoded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw: Option<RawPayload>,
+}
+
+/// Message we failed to decode, kept as received so it can be inspected
+/// and decoded again later
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RawPayload {
+    pub error: String,
+    /// Position in a batched message, `data` then holds just that line
+    /// as plain JSON
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch_index: Option<usize>,
+    #[serde(with = "base64_bytes")]
+    pub data: Vec<u8>,
+}
+
+/// AMQP delivery info and message properties. That's how services
+/// publishing to a shared exchange are told apart.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Origin {
+    pub exchange: String,
+    pub routing_key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    /// The `type` property
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_encoding: Option<String>,
+    /// The `timestamp` property, set by the publisher
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "time::serde::rfc3339::option"
+    )]
+    pub timestamp: Option<OffsetDateTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<String>,
+    /// Per-message TTL in milliseconds, as the publisher wrote it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expiration: Option<String>,
+    /// 1 is transient, 2 persistent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delivery_mode: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cluster_id: Option<String>,
+    /// Delivered before without an ack, e.g. to a consumer that went away
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub redelivered: bool,
+    #[serde(default, skip_serializing_if = "serde_json::Map::is_empty")]
+    pub headers: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Origin {
+    pub const KEYS: [&'static str; 16] = [
+        "exchange",
+        "routing_key",
+        "app_id",
+        "message_id",
+        "correlation_id",
+        "user_id",
+        "type",
+        "content_type",
+        "content_encoding",
+        "timestamp",
+        "reply_to",
+        "expiration",
+        "delivery_mode",
+        "priority",
+        "cluster_id",
+        "redelivered",
+    ];
+
+    /// Value by key as used in filters, headers are `headers.<name>`
+    pub fn get(&self, key: &str) -> Option<String> {
+        if let Some(header) = key.strip_prefix("headers.") {
+            return self.headers.get(header).map(|v| match v {
+                serde_json::Value::String(s) => s.clone(),
+                other => other.to_string(),
+            });
+        }
+        match key {
+            "exchange" => Some(self.exchange.clone()),
+            "routing_key" => Some(self.routing_key.clone()),
+            "app_id" => self.app_id.clone(),
+            "message_id" => self.message_id.clone(),
+            "correlation_id" => self.correlation_id.clone(),
+            "user_id" => self.user_id.clone(),
+            "type" => self.kind.clone(),
+            "content_type" => self.content_type.clone(),
+            "content_encoding" => self.content_encoding.clone(),
+            "timestamp" => self.timestamp.and_then(|ts| {
+                ts.format(&time::format_description::well_known::Rfc3339)
+                    .ok()
+            }),
+            "reply_to" => self.reply_to.clone(),
+            "expiration" => self.expiration.clone(),
+            "delivery_mode" => self.delivery_mode.map(|m| m.to_string()),
+            "priority" => self.priority.map(|p| p.to_string()),
+            "cluster_id" => self.cluster_id.clone(),
+            "redelivered" => self.redelivered.then(|| "true".to_string()),
+            _ => None,
+        }
+    }
 }
 
+impl LogEntry {
+    /// Entry standing for a message that could not be decoded
+    pub fn raw(payload: RawPayload) -> Self {
+        let mut message = String::from_utf8_lossy(&payload.data).into_owned();
+        if let Some((idx, _)) = message.char_indices().nth(RAW_PREVIEW_CHARS) {
+            message.truncate(idx);
+            message.push('…');
+        }
+        LogEntry {
+            timestamp: OffsetDateTime::now_utc(),
+            level: Level::ERROR,
+            message,
+            fields: HashMap::new(),
+            span: None,
+            spans: Vec::new(),
+            source: Source::default(),
+            origin: None,
+            source_id: None,
+            raw: Some(payload),
+        }
+    }
+
+    pub fn is_raw(&self) -> bool {
+        self.raw.is_some()
+    }
+}
+
+const RAW_PREVIEW_CHARS: usize = 200;
+
+/// Reads anything [`TimestampParser`] understands, writes RFC3339 keeping
+/// sub-second precision.
+///
+/// [`TimestampParser`]: super::timestamp::TimestampParser
 mod log_time_format {
-    use serde::{Deserialize, Deserializer};
-    use time::OffsetDateTime;
+    use serde::{de::Error, Deserialize, Deserializer};
+    use time::{format_description::well_known::Rfc3339, OffsetDateTime};
 
-    use crate::default_time_format;
+    use crate::types::timestamp::TimestampParser;
 
     pub fn deserialize<'de, D>(d: D) -> Result<OffsetDateTime, D::Error>
     where
         D: Deserializer<'de>,
     {
-        let s = String::deserialize(d)?;
-        Ok(time::OffsetDateTime::parse(&s, default_time_format()).unwrap())
+        let v = serde_json::Value::deserialize(d)?;
+        TimestampParser::default()
+            .parse(&v)
+            .map_err(D::Error::custom)
     }
 
     pub fn serialize<S>(dt: &OffsetDateTime, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
     {
-        let s = dt.format(default_time_format()).unwrap();
+        let s = dt.format(&Rfc3339).map_err(serde::ser::Error::custom)?;
         serializer.serialize_str(&s)
     }
 }
+
+/// Payloads are arbitrary bytes, JSON has no place for them but strings
+mod base64_bytes {
+    use base64::{prelude::BASE64_STANDARD, Engine};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn deserialize<'de, D>(d: D) -> Result<Vec<u8>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(d)?;
+        BASE64_STANDARD.decode(s).map_err(D::Error::custom)
+    }
+
+    pub fn serialize<S>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&BASE64_STANDARD.encode(data))
+    }
+}
@@ -0,0 +1,509 @@
+//! Log levels are open-ended: anything a producer writes is accepted and
+//! placed on a severity scale, so levels can be ordered and compared.
+
+use std::{borrow::Cow, collections::HashMap, sync::OnceLock};
+
+use egui::Color32;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::decode::DecodeError;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Level {
+    pub name: Cow<'static, str>,
+    /// Higher is more severe, built-in levels go from 10 to 60
+    pub severity: u8,
+}
+
+impl Level {
+    pub const TRACE: Level = Level::builtin("TRACE", 10);
+    pub const DEBUG: Level = Level::builtin("DEBUG", 20);
+    pub const INFO: Level = Level::builtin("INFO", 30);
+    pub const NOTICE: Level = Level::builtin("NOTICE", 35);
+    pub const WARN: Level = Level::builtin("WARN", 40);
+    pub const ERROR: Level = Level::builtin("ERROR", 50);
+    // Syslog severities above error
+    pub const CRITICAL: Level = Level::builtin("CRITICAL", 52);
+    pub const ALERT: Level = Level::builtin("ALERT", 54);
+    pub const EMERGENCY: Level = Level::builtin("EMERGENCY", 56);
+    pub const FATAL: Level = Level::builtin("FATAL", 60);
+
+    pub const BUILTIN: [Level; 10] = [
+        Level::TRACE,
+        Level::DEBUG,
+        Level::INFO,
+        Level::NOTICE,
+        Level::WARN,
+        Level::ERROR,
+        Level::CRITICAL,
+        Level::ALERT,
+        Level::EMERGENCY,
+        Level::FATAL,
+    ];
+
+    const fn builtin(name: &'static str, severity: u8) -> Level {
+        Level {
+            name: Cow::Borrowed(name),
+            severity,
+        }
+    }
+
+    fn is_builtin(&self) -> bool {
+        Level::BUILTIN.contains(self)
+    }
+}
+
+impl PartialOrd for Level {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Level {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.severity
+            .cmp(&other.severity)
+            .then_with(|| self.name.cmp(&other.name))
+    }
+}
+
+impl std::fmt::Display for Level {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+/// Built-in levels are stored by name as they always were, others
+/// together with their severity, so they survive without the settings
+/// that defined them.
+impl Serialize for Level {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.is_builtin() {
+            serializer.serialize_str(&self.name)
+        } else {
+            StoredLevel {
+                name: self.name.to_string(),
+                severity: self.severity,
+            }
+            .serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Level {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        match Value::deserialize(d)? {
+            Value::Object(obj) => {
+                let stored: StoredLevel =
+                    serde_json::from_value(Value::Object(obj)).map_err(serde::de::Error::custom)?;
+                Ok(Level {
+                    name: stored.name.into(),
+                    severity: stored.severity,
+                })
+            }
+            other => LevelTable::builtin()
+                .parse(&other)
+                .map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredLevel {
+    name: String,
+    severity: u8,
+}
+
+/// User defined level, or additions to a built-in one when `name` matches
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct LevelDefinition {
+    pub name: String,
+    /// Required for new levels, keeps the built-in one if omitted
+    #[serde(default)]
+    pub severity: Option<u8>,
+    /// Case-insensitive names and numbers producers use for this level
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    #[serde(default)]
+    pub color: Option<LevelColor>,
+}
+
+/// `#rrggbb` colors for light and dark theme
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LevelColor {
+    pub light: String,
+    pub dark: String,
+}
+
+struct BuiltinLevel {
+    level: Level,
+    aliases: &'static [&'static str],
+    light: Color32,
+    dark: Color32,
+}
+
+const BUILTIN: [BuiltinLevel; 10] = [
+    BuiltinLevel {
+        level: Level::TRACE,
+        aliases: &["verbose", "finest", "finer"],
+        light: Color32::from_rgb(90, 90, 200),
+        dark: Color32::from_rgb(150, 150, 255),
+    },
+    BuiltinLevel {
+        level: Level::DEBUG,
+        aliases: &["dbg", "fine"],
+        light: Color32::from_rgb(0, 120, 160),
+        dark: Color32::from_rgb(90, 200, 230),
+    },
+    BuiltinLevel {
+        level: Level::INFO,
+        aliases: &["information", "informational"],
+        light: Color32::from_rgb(0, 140, 0),
+        dark: Color32::from_rgb(100, 220, 100),
+    },
+    BuiltinLevel {
+        level: Level::NOTICE,
+        aliases: &[],
+        light: Color32::from_rgb(0, 130, 110),
+        dark: Color32::from_rgb(90, 220, 190),
+    },
+    BuiltinLevel {
+        level: Level::WARN,
+        aliases: &["warning"],
+        light: Color32::from_rgb(180, 120, 0),
+        dark: Color32::from_rgb(255, 200, 60),
+    },
+    BuiltinLevel {
+        level: Level::ERROR,
+        aliases: &["err", "severe"],
+        light: Color32::from_rgb(200, 0, 0),
+        dark: Color32::from_rgb(255, 90, 90),
+    },
+    BuiltinLevel {
+        level: Level::CRITICAL,
+        aliases: &["crit"],
+        light: Color32::from_rgb(170, 0, 40),
+        dark: Color32::from_rgb(255, 70, 120),
+    },
+    BuiltinLevel {
+        level: Level::ALERT,
+        aliases: &[],
+        light: Color32::from_rgb(160, 0, 80),
+        dark: Color32::from_rgb(255, 70, 170),
+    },
+    BuiltinLevel {
+        level: Level::EMERGENCY,
+        aliases: &["emerg"],
+        light: Color32::from_rgb(150, 0, 100),
+        dark: Color32::from_rgb(255, 75, 200),
+    },
+    BuiltinLevel {
+        level: Level::FATAL,
+        aliases: &["panic"],
+        light: Color32::from_rgb(150, 0, 120),
+        dark: Color32::from_rgb(255, 80, 220),
+    },
+];
+
+/// Maps whatever producers write into [`Level`] and levels into colors
+#[derive(Clone, Debug)]
+pub struct LevelTable {
+    /// Lowercase alias to level
+    aliases: HashMap<String, Level>,
+    /// Level name to (light, dark) colors
+    colors: HashMap<String, (Color32, Color32)>,
+    /// All known levels, from least to most severe
+    levels: Vec<Level>,
+}
+
+impl Default for LevelTable {
+    fn default() -> Self {
+        LevelTable::builtin().clone()
+    }
+}
+
+impl LevelTable {
+    pub fn builtin() -> &'static LevelTable {
+        static MEM: OnceLock<LevelTable> = OnceLock::new();
+        MEM.get_or_init(|| {
+            let mut table = LevelTable {
+                aliases: HashMap::new(),
+                colors: HashMap::new(),
+                levels: Level::BUILTIN.to_vec(),
+            };
+            for builtin in BUILTIN {
+                table.add_alias(&builtin.level.name, &builtin.level);
+                for alias in builtin.aliases {
+                    table.add_alias(alias, &builtin.level);
+                }
+                table.colors.insert(
+                    builtin.level.name.to_string(),
+                    (builtin.light, builtin.dark),
+                );
+            }
+            table
+        })
+    }
+
+    pub fn new(definitions: &[LevelDefinition]) -> Result<Self, String> {
+        let mut table = LevelTable::builtin().clone();
+        for def in definitions {
+            let name = def.name.trim().to_uppercase();
+            let level = match (table.find(&name), def.severity) {
+                (Some(level), None) => level,
+                (_, Some(severity)) => Level {
+                    name: name.clone().into(),
+                    severity,
+                },
+                (None, None) => return Err(format!("Level {name} needs a severity")),
+            };
+            table.levels.retain(|l| l.name != level.name);
+            for aliased in table.aliases.values_mut() {
+                if aliased.name == level.name {
+                    *aliased = level.clone();
+                }
+            }
+            table.levels.push(level.clone());
+            table.add_alias(&name, &level);
+            for alias in &def.aliases {
+                table.add_alias(alias, &level);
+            }
+            if let Some(color) = &def.color {
+                let parse = |hex: &str| {
+                    Color32::from_hex(hex.trim())
+                        .map_err(|_| format!("Invalid color \"{hex}\" for level {name}"))
+                };
+                table.colors.insert(
+                    level.name.to_string(),
+                    (parse(&color.light)?, parse(&color.dark)?),
+                );
+            }
+        }
+        table.levels.sort();
+        Ok(table)
+    }
+
+    /// All known levels, from least to most severe
+    pub fn levels(&self) -> &[Level] {
+        &self.levels
+    }
+
+    pub fn parse(&self, value: &Value) -> Result<Level, DecodeError> {
+        match value {
+            Value::String(s) => Ok(self.parse_str(s)),
+            Value::Number(n) => n
+                .as_i64()
+                .or_else(|| n.as_f64().map(|f| f as i64))
+                .map(|n| self.from_number(n))
+                .ok_or_else(|| DecodeError(format!("Invalid level {n}"))),
+            other => Err(DecodeError(format!("Expected level, got {other}"))),
+        }
+    }
+
+    /// Unknown names are kept as is with severity of INFO
+    pub fn parse_str(&self, s: &str) -> Level {
+        let s = s.trim();
+        if let Some(level) = self.find(s) {
+            return level;
+        }
+        if let Ok(n) = s.parse() {
+            return self.from_number(n);
+        }
+        Level {
+            name: s.to_uppercase().into(),
+            severity: Level::INFO.severity,
+        }
+    }
+
+    /// Numbers are looked up in aliases first, then guessed by scale:
+    /// 0-7 is syslog severity, up to 999 bunyan/pino levels and
+    /// log4j/logback `level_value` above that.
+    pub fn from_number(&self, n: i64) -> Level {
+        if let Some(level) = self.aliases.get(&n.to_string()) {
+            return level.clone();
+        }
+        match n {
+            i64::MIN..=0 => Level::EMERGENCY,
+            1 => Level::ALERT,
+            2 => Level::CRITICAL,
+            3 => Level::ERROR,
+            4 => Level::WARN,
+            5 => Level::NOTICE,
+            6 => Level::INFO,
+            7 => Level::DEBUG,
+            8..=999 => match n {
+                ..=10 => Level::TRACE,
+                11..=20 => Level::DEBUG,
+                21..=30 => Level::INFO,
+                31..=40 => Level::WARN,
+                41..=50 => Level::ERROR,
+                _ => Level::FATAL,
+            },
+            _ => match n {
+                ..=5000 => Level::TRACE,
+                5001..=10000 => Level::DEBUG,
+                10001..=20000 => Level::INFO,
+                20001..=30000 => Level::WARN,
+                30001..=40000 => Level::ERROR,
+                _ => Level::FATAL,
+            },
+        }
+    }
+
+    /// Levels without own color get the color of closest less severe level
+    pub fn color(&self, level: &Level, dark_mode: bool) -> Color32 {
+        let colors = self.colors.get(level.name.as_ref()).or_else(|| {
+            self.levels
+                .iter()
+                .rev()
+                .filter(|l| l.severity <= level.severity)
+                .find_map(|l| self.colors.get(l.name.as_ref()))
+        });
+        match colors {
+            Some((light, dark)) => {
+                if dark_mode {
+                    *dark
+                } else {
+                    *light
+                }
+            }
+            None => Color32::GRAY,
+        }
+    }
+
+    fn find(&self, name: &str) -> Option<Level> {
+        self.aliases.get(&name.to_lowercase()).cloned()
+    }
+
+    fn add_alias(&mut self, alias: &str, level: &Level) {
+        self.aliases
+            .insert(alias.trim().to_lowercase(), level.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn custom() -> LevelTable {
+        LevelTable::new(&[
+            LevelDefinition {
+                name: "audit".to_string(),
+                severity: Some(45),
+                aliases: vec!["SECURITY".to_string(), "99".to_string()],
+                color: Some(LevelColor {
+                    light: "#112233".to_string(),
+                    dark: "#445566".to_string(),
+                }),
+            },
+            LevelDefinition {
+                name: "Info".to_string(),
+                aliases: vec!["chatter".to_string()],
+                ..Default::default()
+            },
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn parses_names_and_aliases() {
+        let table = LevelTable::builtin();
+        let cases = [
+            ("info", Level::INFO),
+            (" Warning ", Level::WARN),
+            ("FATAL", Level::FATAL),
+            ("crit", Level::CRITICAL),
+            ("severe", Level::ERROR),
+            ("3", Level::ERROR),
+        ];
+        for (name, level) in cases {
+            assert_eq!(table.parse(&json!(name)).unwrap(), level, "{name}");
+        }
+        let unknown = table.parse_str("chatty");
+        assert_eq!(unknown.name, "CHATTY");
+        assert_eq!(unknown.severity, Level::INFO.severity);
+        assert!(table.parse(&json!(null)).is_err());
+    }
+
+    #[test]
+    fn guesses_numbers_by_scale() {
+        let table = LevelTable::builtin();
+        let cases = [
+            (-1, Level::EMERGENCY),
+            (4, Level::WARN),
+            (7, Level::DEBUG),
+            // bunyan and pino
+            (10, Level::TRACE),
+            (30, Level::INFO),
+            (50, Level::ERROR),
+            (60, Level::FATAL),
+            // log4j
+            (5000, Level::TRACE),
+            (20000, Level::INFO),
+            (40000, Level::ERROR),
+            (50000, Level::FATAL),
+        ];
+        for (n, level) in cases {
+            assert_eq!(table.from_number(n), level, "{n}");
+        }
+        assert_eq!(table.parse(&json!(30.0)).unwrap(), Level::INFO);
+    }
+
+    #[test]
+    fn adds_defined_levels() {
+        let table = custom();
+        let audit = table.parse_str("Security");
+        assert_eq!(audit.name, "AUDIT");
+        assert_eq!(audit.severity, 45);
+        assert_eq!(table.parse(&json!(99)).unwrap(), audit);
+        assert_eq!(table.parse_str("chatter"), Level::INFO);
+        let names: Vec<_> = table.levels().iter().map(|l| l.name.as_ref()).collect();
+        assert_eq!(names[4..6], ["WARN", "AUDIT"]);
+
+        assert_eq!(
+            table.color(&audit, true),
+            Color32::from_rgb(0x44, 0x55, 0x66)
+        );
+        // Closest less severe level with a color
+        let between = Level {
+            name: "LOUD".into(),
+            severity: 47,
+        };
+        assert_eq!(
+            table.color(&between, false),
+            Color32::from_rgb(0x11, 0x22, 0x33)
+        );
+
+        let missing_severity = LevelDefinition {
+            name: "new".to_string(),
+            ..Default::default()
+        };
+        assert!(LevelTable::new(&[missing_severity]).is_err());
+        let bad_color = LevelDefinition {
+            name: "info".to_string(),
+            color: Some(LevelColor {
+                light: "green".to_string(),
+                dark: "#00ff00".to_string(),
+            }),
+            ..Default::default()
+        };
+        assert!(LevelTable::new(&[bad_color]).is_err());
+    }
+
+    #[test]
+    fn stores_custom_levels_with_severity() {
+        assert_eq!(serde_json::to_value(Level::WARN).unwrap(), json!("WARN"));
+        let audit = custom().parse_str("audit");
+        let stored = serde_json::to_value(&audit).unwrap();
+        assert_eq!(stored, json!({"name": "AUDIT", "severity": 45}));
+        // Read back without the settings defining it
+        assert_eq!(serde_json::from_value::<Level>(stored).unwrap(), audit);
+        assert_eq!(
+            serde_json::from_value::<Level>(json!("warning")).unwrap(),
+            Level::WARN
+        );
+    }
+}
@@ -1 +1,4 @@
+pub mod decode;
 pub mod json_log;
+pub mod level;
+pub mod timestamp;
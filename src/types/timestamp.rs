@@ -0,0 +1,225 @@
+//! Lenient timestamp parsing. Producers write time in all sorts of ways,
+//! and a weird timestamp must never take the worker down.
+
+use serde_json::Value;
+use time::{
+    format_description::{
+        well_known::{Rfc2822, Rfc3339},
+        OwnedFormatItem,
+    },
+    OffsetDateTime, PrimitiveDateTime,
+};
+
+use super::decode::DecodeError;
+use crate::default_time_format;
+
+/// Parses RFC3339, RFC2822, our legacy format, user provided format
+/// descriptions and unix epoch (seconds, milliseconds, microseconds or
+/// nanoseconds, as number or string), in that order.
+#[derive(Default)]
+pub struct TimestampParser {
+    custom: Vec<OwnedFormatItem>,
+}
+
+impl TimestampParser {
+    /// `formats` are `time` crate format descriptions, e.g.
+    /// `[year]-[month]-[day] [hour]:[minute]:[second]`. Formats without an
+    /// offset are taken as UTC.
+    pub fn new(formats: &[String]) -> Result<Self, DecodeError> {
+        let custom = formats
+            .iter()
+            .map(|f| {
+                time::format_description::parse_owned::<2>(f)
+                    .map_err(|e| DecodeError(format!("Invalid time format \"{f}\": {e}")))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(TimestampParser { custom })
+    }
+
+    pub fn parse(&self, value: &Value) -> Result<OffsetDateTime, DecodeError> {
+        match value {
+            Value::String(s) => self.parse_str(s),
+            Value::Number(n) => match (n.as_i64(), n.as_f64()) {
+                (Some(i), _) => from_epoch(i as i128),
+                (None, Some(f)) => from_epoch_float(f),
+                _ => Err(DecodeError(format!("Invalid timestamp {n}"))),
+            },
+            _ => Err(DecodeError(format!("Expected timestamp, got {value}"))),
+        }
+    }
+
+    pub fn parse_str(&self, s: &str) -> Result<OffsetDateTime, DecodeError> {
+        let s = s.trim();
+        if let Ok(ts) = OffsetDateTime::parse(s, &Rfc3339) {
+            return Ok(ts);
+        }
+        if let Ok(ts) = OffsetDateTime::parse(s, &Rfc2822) {
+            return Ok(ts);
+        }
+        if let Ok(ts) = OffsetDateTime::parse(s, default_time_format()) {
+            return Ok(ts);
+        }
+        // Before epochs, as a format may be all digits too
+        for format in &self.custom {
+            if let Ok(ts) = OffsetDateTime::parse(s, format) {
+                return Ok(ts);
+            }
+            if let Ok(ts) = PrimitiveDateTime::parse(s, format) {
+                return Ok(ts.assume_utc());
+            }
+        }
+        if let Ok(epoch) = s.parse::<i128>() {
+            return from_epoch(epoch);
+        }
+        if let Ok(epoch) = s.parse::<f64>() {
+            return from_epoch_float(epoch);
+        }
+        Err(DecodeError(format!("Unrecognized timestamp \"{s}\"")))
+    }
+}
+
+/// Guesses the unit by magnitude: anything below 10^11 is seconds (that's
+/// up to year 5138), then milliseconds, microseconds and nanoseconds.
+fn epoch_multiplier(abs: f64) -> i128 {
+    if abs < 1e11 {
+        1_000_000_000
+    } else if abs < 1e14 {
+        1_000_000
+    } else if abs < 1e17 {
+        1_000
+    } else {
+        1
+    }
+}
+
+fn from_epoch(epoch: i128) -> Result<OffsetDateTime, DecodeError> {
+    let nanos = epoch.saturating_mul(epoch_multiplier(epoch.unsigned_abs() as f64));
+    from_nanos(nanos).ok_or_else(|| DecodeError(format!("Invalid epoch timestamp {epoch}")))
+}
+
+/// Fractional epoch, e.g. python's `time.time()`
+fn from_epoch_float(epoch: f64) -> Result<OffsetDateTime, DecodeError> {
+    let invalid = || DecodeError(format!("Invalid epoch timestamp {epoch}"));
+    if !epoch.is_finite() {
+        return Err(invalid());
+    }
+    // Nanoseconds since 1970 are beyond what f64 holds exactly, so the
+    // fraction is scaled on its own
+    let multiplier = epoch_multiplier(epoch.abs());
+    let whole = epoch.trunc();
+    let fraction = ((epoch - whole) * multiplier as f64).round() as i128;
+    let nanos = (whole as i128).saturating_mul(multiplier) + fraction;
+    from_nanos(nanos).ok_or_else(invalid)
+}
+
+/// `time` wraps seconds past `i64` around instead of failing
+fn from_nanos(nanos: i128) -> Option<OffsetDateTime> {
+    i64::try_from(nanos.div_euclid(1_000_000_000)).ok()?;
+    OffsetDateTime::from_unix_timestamp_nanos(nanos).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use time::macros::datetime;
+
+    use super::*;
+
+    fn parse(value: Value) -> Result<OffsetDateTime, DecodeError> {
+        TimestampParser::default().parse(&value)
+    }
+
+    #[test]
+    fn parses_text_formats() {
+        let cases = [
+            ("2024-03-01T10:20:30Z", datetime!(2024-03-01 10:20:30 UTC)),
+            (
+                "2024-03-01T10:20:30.123456789+05:30",
+                datetime!(2024-03-01 10:20:30.123456789 +5:30),
+            ),
+            (
+                " Fri, 01 Mar 2024 10:20:30 +0100 ",
+                datetime!(2024-03-01 10:20:30 +1),
+            ),
+            // Written by earlier versions
+            ("2024-03-01T10:20:30 +02", datetime!(2024-03-01 10:20:30 +2)),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(parse(json!(text)).unwrap(), expected, "{text}");
+        }
+    }
+
+    #[test]
+    fn guesses_epoch_units() {
+        let expected = datetime!(2024-03-01 10:20:30 UTC);
+        for epoch in [
+            1_709_288_430_i64,
+            1_709_288_430_000,
+            1_709_288_430_000_000,
+            1_709_288_430_000_000_000,
+        ] {
+            assert_eq!(parse(json!(epoch)).unwrap(), expected, "{epoch}");
+            assert_eq!(
+                parse(json!(epoch.to_string())).unwrap(),
+                expected,
+                "{epoch}"
+            );
+        }
+        assert_eq!(
+            parse(json!(1_709_288_430.25)).unwrap(),
+            datetime!(2024-03-01 10:20:30.25 UTC)
+        );
+        assert_eq!(
+            parse(json!("1709288430.5")).unwrap(),
+            datetime!(2024-03-01 10:20:30.5 UTC)
+        );
+        assert_eq!(
+            parse(json!(1_709_288_430_123.5)).unwrap(),
+            datetime!(2024-03-01 10:20:30.1235 UTC)
+        );
+        assert_eq!(
+            parse(json!(-1.5)).unwrap(),
+            datetime!(1969-12-31 23:59:58.5 UTC)
+        );
+    }
+
+    #[test]
+    fn tries_custom_formats() {
+        let parser = TimestampParser::new(&[
+            "[day].[month].[year] [hour]:[minute]:[second]".to_string(),
+            "[year][month][day][hour][minute][second]".to_string(),
+            "[day]/[month]/[year] [hour]:[minute] [offset_hour]".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(
+            parser.parse_str("01.03.2024 10:20:30").unwrap(),
+            datetime!(2024-03-01 10:20:30 UTC)
+        );
+        // Would be an epoch otherwise
+        assert_eq!(
+            parser.parse_str("20240301102030").unwrap(),
+            datetime!(2024-03-01 10:20:30 UTC)
+        );
+        assert_eq!(
+            parser.parse_str("01/03/2024 10:20 -03").unwrap(),
+            datetime!(2024-03-01 10:20 -3)
+        );
+        assert!(parser.parse_str("01/03/2024").is_err());
+        assert!(TimestampParser::new(&["[nonsense]".to_string()]).is_err());
+    }
+
+    #[test]
+    fn reports_errors() {
+        for value in [
+            json!("yesterday"),
+            json!(""),
+            json!(null),
+            json!(true),
+            json!({"ts": 1}),
+            json!(f64::MAX),
+            json!(i64::MAX.to_string() + "0000000000"),
+        ] {
+            assert!(parse(value.clone()).is_err(), "{value}");
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::app::config::ReconnectSettings;
+
+/// Counts failed connection attempts and tells how long to wait before the next one
+#[derive(Default)]
+pub struct Backoff {
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+
+    /// Registers a failed attempt. Returns `None` when we should give up.
+    pub fn next_delay(&mut self, settings: &ReconnectSettings) -> Option<Duration> {
+        if settings.max_attempts.is_some_and(|max| self.attempt >= max) {
+            return None;
+        }
+        let base = settings.initial_delay_ms as f64 * settings.multiplier.powi(self.attempt as i32);
+        let base = base.min(settings.max_delay_ms as f64);
+        let jitter = settings.jitter.clamp(0.0, 1.0);
+        let factor = if jitter > 0.0 {
+            rand::thread_rng().gen_range(1.0 - jitter..=1.0 + jitter)
+        } else {
+            1.0
+        };
+        self.attempt = self.attempt.saturating_add(1);
+        Some(Duration::from_millis((base * factor) as u64))
+    }
+}
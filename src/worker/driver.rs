@@ -0,0 +1,392 @@
+//! Task driving a single [`LogSource`]: it connects the source, reconnects
+//! with a backoff when the connection is lost, decodes received messages and
+//! acks them once the app stored all of their entries.
+
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use futures::future::OptionFuture;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::{
+    app::config::{DecodeErrorAction, Settings, StreamOffset},
+    trace_err,
+    types::{
+        decode::Decoder,
+        json_log::{LogEntry, RawPayload},
+    },
+};
+
+use super::{
+    backoff::Backoff,
+    source::{Delivery, LogSource, SourceEvent, SourceId},
+    ConnectionState, DeliveryId, Notification, Notifier,
+};
+
+/// What the worker asks of a source task
+pub enum Control {
+    Reconnect,
+    /// Sequence numbers of deliveries, one per stored entry
+    Ack(Vec<u64>),
+    /// Sequence number of a delivery with an entry that failed to store
+    Nack(u64),
+    Update(Box<Settings>),
+    Replay(StreamOffset),
+    Stop,
+}
+
+/// Delivery waiting for the app to store its entries
+struct PendingAck {
+    tag: u64,
+    entries: usize,
+}
+
+pub struct Driver {
+    source: Box<dyn LogSource>,
+    id: SourceId,
+    /// Key of the task, part of delivery ids
+    task: u64,
+    control: UnboundedReceiver<Control>,
+    notifier: Notifier,
+    decoder: Arc<RwLock<Decoder>>,
+    backoff: Backoff,
+    /// Deliveries handed to the app and waiting for its ack
+    unacked: HashMap<u64, PendingAck>,
+    next_seq: u64,
+}
+
+impl Driver {
+    pub fn new(
+        source: Box<dyn LogSource>,
+        task: u64,
+        control: UnboundedReceiver<Control>,
+        notifier: Notifier,
+        decoder: Arc<RwLock<Decoder>>,
+    ) -> Self {
+        Driver {
+            id: source.id().to_string(),
+            source,
+            task,
+            control,
+            notifier,
+            decoder,
+            backoff: Backoff::default(),
+            unacked: HashMap::new(),
+            next_seq: 0,
+        }
+    }
+
+    pub async fn run(mut self) {
+        let mut connected = false;
+        // Connect right away
+        let mut retry = Some(Box::pin(tokio::time::sleep(Duration::ZERO)));
+        loop {
+            tokio::select! {
+                control = self.control.recv() => match control {
+                    Some(Control::Reconnect) => {
+                        self.backoff.reset();
+                        retry = Some(Box::pin(tokio::time::sleep(Duration::ZERO)));
+                    }
+                    Some(Control::Ack(seqs)) => self.ack(seqs).await,
+                    Some(Control::Nack(seq)) => self.nack(seq).await,
+                    Some(Control::Update(settings)) => self.source.update(&settings),
+                    Some(Control::Replay(from)) => {
+                        if self.source.replay(&from) {
+                            self.backoff.reset();
+                            retry = Some(Box::pin(tokio::time::sleep(Duration::ZERO)));
+                        }
+                    }
+                    None | Some(Control::Stop) => {
+                        self.source.close().await;
+                        self.notifier.notify(Notification::SourceStopped {
+                            source: self.id.clone(),
+                        });
+                        return;
+                    }
+                },
+                Some(()) = OptionFuture::from(retry.as_mut()) => {
+                    retry = None;
+                    connected = false;
+                    self.unacked.clear();
+                    self.set_state(ConnectionState::Connecting);
+                    match self.source.connect().await {
+                        Ok(()) => {
+                            connected = true;
+                            self.backoff.reset();
+                            self.set_state(ConnectionState::Connected);
+                        }
+                        Err(error) => retry = self.schedule_retry(error),
+                    }
+                }
+                event = self.source.next(), if connected => match event {
+                    Ok(SourceEvent::Delivery(delivery)) => self.receive(*delivery).await,
+                    Ok(SourceEvent::State(state)) => self.set_state(state),
+                    Err(error) => {
+                        connected = false;
+                        retry = self.schedule_retry(error);
+                    }
+                }
+            }
+        }
+    }
+
+    async fn receive(&mut self, delivery: Delivery) {
+        let decoded = self
+            .decoder
+            .read()
+            .expect("Failed to lock decoder")
+            .decode_entries(
+                &delivery.data,
+                delivery.content_type.as_deref(),
+                delivery.content_encoding.as_deref(),
+            );
+        let entries: Vec<LogEntry> = match decoded {
+            // The rest of the batch gets acked, so bad lines are kept
+            // whatever the settings say
+            Ok(entries) => entries,
+            Err(err) => match self.source.on_decode_error() {
+                // Keep it, so the producer can be debugged
+                DecodeErrorAction::Keep => vec![LogEntry::raw(RawPayload {
+                    error: err.to_string(),
+                    data: delivery.data,
+                    batch_index: None,
+                })],
+                DecodeErrorAction::DeadLetter => {
+                    trace_err!(self.source.reject(delivery.tag).await, ());
+                    self.notifier.notify(Notification::Error(format!(
+                        "Dead-lettered undecodable message: {err}"
+                    )));
+                    return;
+                }
+            },
+        };
+        if entries.is_empty() {
+            trace_err!(self.source.ack(delivery.tag).await, ());
+            return;
+        }
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.unacked.insert(
+            seq,
+            PendingAck {
+                tag: delivery.tag,
+                entries: entries.len(),
+            },
+        );
+        for mut entry in entries {
+            if let Some(origin) = &delivery.origin {
+                entry.origin = Some(origin.clone());
+            }
+            entry.source_id = Some(self.id.clone());
+            self.notifier.notify(Notification::LogEntry {
+                entry: Box::new(entry),
+                delivery: DeliveryId {
+                    task: self.task,
+                    seq,
+                },
+            });
+        }
+    }
+
+    async fn ack(&mut self, seqs: Vec<u64>) {
+        for seq in seqs {
+            // Gone if the source reconnected meanwhile, it's up to the
+            // source whether those come again
+            let Some(pending) = self.unacked.get_mut(&seq) else {
+                continue;
+            };
+            pending.entries -= 1;
+            if pending.entries == 0 {
+                let pending = self.unacked.remove(&seq).expect("Just got it");
+                trace_err!(self.source.ack(pending.tag).await, ());
+            }
+        }
+    }
+
+    /// Entries of the delivery still to be acked are ignored from now on
+    async fn nack(&mut self, seq: u64) {
+        if let Some(pending) = self.unacked.remove(&seq) {
+            trace_err!(self.source.nack(pending.tag, true).await, ());
+        }
+    }
+
+    fn set_state(&self, status: ConnectionState) {
+        self.notifier.notify(Notification::ConnectionStatusChanged {
+            source: self.id.clone(),
+            status,
+        });
+    }
+
+    /// Reports the failure and returns the timer for the next attempt,
+    /// or `None` if we gave up.
+    fn schedule_retry(&mut self, error: String) -> Option<Pin<Box<tokio::time::Sleep>>> {
+        match self.backoff.next_delay(&self.source.reconnect_settings()) {
+            Some(retry_in) => {
+                self.set_state(ConnectionState::Backoff { retry_in, error });
+                Some(Box::pin(tokio::time::sleep(retry_in)))
+            }
+            None => {
+                self.set_state(ConnectionState::Failed(error));
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::Receiver;
+
+    use tokio::sync::mpsc::UnboundedSender;
+
+    use super::*;
+    use crate::worker::source::memory::{Call, MemoryHandle, MemorySource};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    const LINE: &str = r#"{"level":30,"time":1700000000000,"msg":"hi"}"#;
+
+    /// Driver running a [`MemorySource`]
+    struct Harness {
+        control: UnboundedSender<Control>,
+        notifications: Receiver<Notification>,
+        source: MemoryHandle,
+    }
+
+    impl Harness {
+        fn start(on_decode_error: DecodeErrorAction) -> Self {
+            let (mut source, handle) = MemorySource::new();
+            source.on_decode_error = on_decode_error;
+            let (control, control_rx) = tokio::sync::mpsc::unbounded_channel();
+            let (tx, notifications) = std::sync::mpsc::channel();
+            let notifier = Notifier {
+                ctx: egui::Context::default(),
+                tx,
+            };
+            let decoder = Arc::new(RwLock::new(Decoder::default()));
+            let driver = Driver::new(Box::new(source), 0, control_rx, notifier, decoder);
+            tokio::spawn(driver.run());
+            Harness {
+                control,
+                notifications,
+                source: handle,
+            }
+        }
+
+        fn send(&self, control: Control) {
+            self.control.send(control).expect("Driver is gone");
+        }
+
+        /// Delivery of the next entry, state changes are skipped
+        fn entry(&self) -> DeliveryId {
+            loop {
+                match self.notifications.recv_timeout(TIMEOUT) {
+                    Ok(Notification::LogEntry { delivery, .. }) => return delivery,
+                    Ok(_) => {}
+                    Err(e) => panic!("No entry: {e}"),
+                }
+            }
+        }
+
+        async fn call(&mut self) -> Call {
+            tokio::time::timeout(TIMEOUT, self.source.calls.recv())
+                .await
+                .expect("No call made")
+                .expect("Source is gone")
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn acks_stored_delivery() {
+        let mut driver = Harness::start(DecodeErrorAction::Keep);
+        assert_eq!(driver.call().await, Call::Connect);
+        driver.source.deliver(7, LINE);
+        let id = driver.entry();
+        driver.send(Control::Ack(vec![id.seq]));
+        assert_eq!(driver.call().await, Call::Ack(7));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn requeues_delivery_failed_to_store() {
+        let mut driver = Harness::start(DecodeErrorAction::Keep);
+        assert_eq!(driver.call().await, Call::Connect);
+        driver.source.deliver(3, &format!("{LINE}\n{LINE}"));
+        let first = driver.entry();
+        let second = driver.entry();
+        driver.send(Control::Nack(first.seq));
+        assert_eq!(
+            driver.call().await,
+            Call::Nack {
+                tag: 3,
+                requeue: true
+            }
+        );
+        // Stored after all, but the delivery comes again anyway
+        driver.send(Control::Ack(vec![second.seq]));
+        driver.source.deliver(4, LINE);
+        let next = driver.entry();
+        driver.send(Control::Ack(vec![next.seq]));
+        assert_eq!(driver.call().await, Call::Ack(4));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn acks_batch_once_all_entries_stored() {
+        let mut driver = Harness::start(DecodeErrorAction::Keep);
+        assert_eq!(driver.call().await, Call::Connect);
+        driver.source.deliver(1, &[LINE; 3].join("\n"));
+        let ids: Vec<DeliveryId> = (0..3).map(|_| driver.entry()).collect();
+        assert!(ids.iter().all(|id| *id == ids[0]));
+        driver.send(Control::Ack(vec![ids[0].seq, ids[1].seq]));
+        // Acked before the batch, which still misses an entry
+        driver.source.deliver(2, LINE);
+        let single = driver.entry();
+        driver.send(Control::Ack(vec![single.seq]));
+        assert_eq!(driver.call().await, Call::Ack(2));
+        driver.send(Control::Ack(vec![ids[2].seq]));
+        assert_eq!(driver.call().await, Call::Ack(1));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn ignores_acks_from_before_reconnect() {
+        let mut driver = Harness::start(DecodeErrorAction::Keep);
+        assert_eq!(driver.call().await, Call::Connect);
+        driver.source.deliver(1, LINE);
+        let stale = driver.entry();
+        driver.send(Control::Reconnect);
+        assert_eq!(driver.call().await, Call::Connect);
+        driver.send(Control::Ack(vec![stale.seq]));
+        driver.source.deliver(1, LINE);
+        let fresh = driver.entry();
+        assert_ne!(fresh, stale);
+        driver.send(Control::Ack(vec![stale.seq, fresh.seq]));
+        // Only once, for the delivery after the reconnect
+        assert_eq!(driver.call().await, Call::Ack(1));
+        driver.send(Control::Stop);
+        assert_eq!(driver.call().await, Call::Close);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn rejects_undecodable_when_dead_lettering() {
+        let mut driver = Harness::start(DecodeErrorAction::DeadLetter);
+        assert_eq!(driver.call().await, Call::Connect);
+        driver.source.deliver(5, "not a log");
+        assert_eq!(driver.call().await, Call::Reject(5));
+        let error = std::iter::from_fn(|| driver.notifications.recv_timeout(TIMEOUT).ok())
+            .find(|n| matches!(n, Notification::Error(_)));
+        assert!(error.is_some());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn keeps_undecodable_as_raw_entry() {
+        let mut driver = Harness::start(DecodeErrorAction::Keep);
+        assert_eq!(driver.call().await, Call::Connect);
+        driver.source.deliver(5, "not a log");
+        let id = driver.entry();
+        driver.send(Control::Ack(vec![id.seq]));
+        assert_eq!(driver.call().await, Call::Ack(5));
+    }
+}
@@ -1,47 +1,84 @@
 use std::{
-    ops::Deref,
-    sync::{Arc, Mutex, RwLock},
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::Duration,
 };
 
-use futures::{future::OptionFuture, task::ArcWake, StreamExt};
-use lapin::{options::BasicConsumeOptions, types::FieldTable, ConnectionProperties};
-use tokio::runtime::Runtime;
+use tokio::{runtime::Runtime, sync::mpsc::UnboundedSender};
 
-use crate::{app::config::RabbitMQSettings, trace_err, types::json_log::LogEntry};
-
-mod cmd_rcv;
+use crate::{
+    app::config::{Settings, StreamOffset},
+    types::{decode::Decoder, json_log::LogEntry},
+};
 
-#[derive(Default, Clone)]
-struct ConnWaker(Arc<Mutex<Option<std::task::Waker>>>);
+use driver::{Control, Driver};
+use source::SourceId;
 
-impl ConnWaker {
-    fn put(&self, w: std::task::Waker) {
-        let mut guard = self.0.lock().expect("Failed to lock ConnWaker guard");
-        guard.replace(w);
-    }
+mod backoff;
+mod cmd_rcv;
+mod driver;
+mod properties;
+pub mod source;
+mod tls;
+mod topology;
 
-    /// Wake will deinit waker (take it off), and then we can reuse it again
-    fn wake(&self) {
-        if let Ok(mut guard) = self.0.lock() {
-            if let Some(waker) = guard.take() {
-                waker.wake();
-            }
-        }
-    }
+/// Identifies a delivery to acknowledge, unique for the worker lifetime
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DeliveryId {
+    /// Task of the source it came from
+    task: u64,
+    seq: u64,
 }
 
 pub enum Command {
     Reconnect,
-    UpdateConfig(RabbitMQSettings),
+    /// One id per stored entry. Delivery is acked once all of its entries
+    /// are stored.
+    Ack(Vec<DeliveryId>),
+    /// An entry of the delivery couldn't be stored, the source should
+    /// deliver it again
+    Nack(DeliveryId),
+    UpdateConfig(Box<Settings>),
+    /// Re-read the stream starting from given offset
+    Replay {
+        from: StreamOffset,
+    },
 }
 
 #[derive(Debug)]
 pub enum Notification {
-    LogEntry(LogEntry),
-    ConnectionStatusChanged { status: Result<(), String> },
+    /// Delivery is acknowledged only once the app sends [`Command::Ack`]
+    /// for it
+    LogEntry {
+        entry: Box<LogEntry>,
+        delivery: DeliveryId,
+    },
+    ConnectionStatusChanged {
+        source: SourceId,
+        status: ConnectionState,
+    },
+    /// The source was removed from the settings
+    SourceStopped {
+        source: SourceId,
+    },
     Error(String),
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    /// Last attempt failed with `error`, next one happens in `retry_in`
+    Backoff {
+        retry_in: Duration,
+        error: String,
+    },
+    /// Gave up reconnecting, waiting for the user to ask again
+    Failed(String),
+    /// Nothing more will come, for the given reason
+    Finished(String),
+}
+
 pub struct WorkerHandle {
     tx: crossbeam::channel::Sender<Command>,
     rx: std::sync::mpsc::Receiver<Notification>,
@@ -51,13 +88,16 @@ pub struct WorkerHandle {
 impl WorkerHandle {
     pub fn command(&self, cmd: Command) {
         self.tx.send(cmd).expect("Failed to send command!");
-        let lock = self
+        // No waker means the worker is busy right now and will pick the
+        // command up next time it polls for them
+        let waker = self
             .waker
             .write()
             .expect("Failed to acquire lock waker for write")
-            .take()
-            .expect("Waker was None");
-        lock.wake();
+            .take();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
     }
     pub fn get_notifications(&self) -> Vec<Notification> {
         let mut notifications = Vec::new();
@@ -76,33 +116,67 @@ impl WorkerHandle {
 }
 
 pub struct Worker {
-    ctx: egui::Context,
-    rmq_conf: RabbitMQSettings,
-    ntf_tx: std::sync::mpsc::Sender<Notification>,
+    notifier: Notifier,
+    settings: Settings,
+    /// Shared with the source tasks, replaced when settings change
+    decoder: Arc<RwLock<Decoder>>,
     cmd_rx: crossbeam::channel::Receiver<Command>,
     worker_handle: Option<WorkerHandle>,
     worker_handle_waker: Arc<RwLock<Option<std::task::Waker>>>,
-    conn_waker: ConnWaker,
+    sources: Vec<SourceTask>,
+    next_task: u64,
+}
+
+/// Task driving one of the sources
+struct SourceTask {
+    id: SourceId,
+    key: u64,
+    tx: UnboundedSender<Control>,
+}
+
+impl SourceTask {
+    fn send(&self, control: Control) {
+        // The task is gone only if the runtime is shutting down
+        let _ = self.tx.send(control);
+    }
+}
+
+/// Passes notifications to the app and wakes it up. Entries are stored and
+/// acked from the app's update, so every notification asks for one, even
+/// while the window is minimized.
+#[derive(Clone)]
+struct Notifier {
+    ctx: egui::Context,
+    tx: std::sync::mpsc::Sender<Notification>,
+}
+
+impl Notifier {
+    fn notify(&self, n: Notification) {
+        self.tx.send(n).expect("Failed to send notification");
+        self.ctx.request_repaint();
+    }
 }
 
 impl Worker {
-    pub fn new(config: RabbitMQSettings, ctx: egui::Context) -> Self {
+    pub fn new(settings: &Settings, ctx: egui::Context) -> Self {
         let (cmd_tx, cmd_rx) = crossbeam::channel::unbounded();
         let (ntf_tx, ntf_rx) = std::sync::mpsc::channel();
 
         let waker = Arc::new(RwLock::new(None));
         Worker {
-            ctx,
-            rmq_conf: config,
-            ntf_tx,
+            notifier: Notifier { ctx, tx: ntf_tx },
+            settings: settings.clone(),
+            // Invalid settings are reported on connect
+            decoder: Arc::new(RwLock::new(settings.decoder().unwrap_or_default())),
             cmd_rx,
             worker_handle: Some(WorkerHandle {
                 tx: cmd_tx,
                 rx: ntf_rx,
                 waker: waker.clone(),
             }),
-            conn_waker: Default::default(),
             worker_handle_waker: waker,
+            sources: Vec::new(),
+            next_task: 0,
         }
     }
 
@@ -113,96 +187,86 @@ impl Worker {
         // Execute the runtime in its own thread.
         std::thread::spawn(move || {
             rt.block_on(async {
-                let mut cons = self.connect().await;
-                loop {
-                    tokio::select! {
-                        // biased;
-                        Some(cmd) = self.get_cmd() => {
-                            match cmd {
-                                Command::Reconnect => {
-                                    cons = self.connect().await;
-                                },
-                                Command::UpdateConfig(c) => {
-                                    self.rmq_conf = c;
-                                },
+                self.update_sources();
+                while let Some(cmd) = self.get_cmd().await {
+                    match cmd {
+                        Command::Reconnect => {
+                            for task in &self.sources {
+                                task.send(Control::Reconnect);
                             }
                         }
-                        Some((Some(Ok(delivery)), c)) = OptionFuture::from(cons.take().map(|c| c.into_future())) => {
-                            cons = Some(c);
-                            // FIXME: Sometimes message not acknowledged.
-                            // Why?
-                            trace_err!(delivery.ack(lapin::options::BasicAckOptions::default())
-                            .await, ());
-                            let entry = match serde_json::from_slice(&delivery.data) {
-                                Ok(entry) => entry,
-                                Err(err) => {
-                                    self.ntf_tx
-                                        .send(Notification::Error(err.to_string()))
-                                        .expect("Failed to send msg!");
-                                    continue;
+                        Command::Ack(ids) => {
+                            let mut by_task: HashMap<u64, Vec<u64>> = HashMap::new();
+                            for id in ids {
+                                by_task.entry(id.task).or_default().push(id.seq);
+                            }
+                            for task in &self.sources {
+                                if let Some(seqs) = by_task.remove(&task.key) {
+                                    task.send(Control::Ack(seqs));
                                 }
-                            };
-                            self.notify(Notification::LogEntry(entry));
+                            }
                         }
-                        else => {
-                            println!("Exiting from worker!");
-                            break;
+                        Command::Nack(id) => {
+                            if let Some(task) = self.sources.iter().find(|t| t.key == id.task) {
+                                task.send(Control::Nack(id.seq));
+                            }
+                        }
+                        Command::UpdateConfig(c) => {
+                            match c.decoder() {
+                                Ok(decoder) => {
+                                    *self.decoder.write().expect("Failed to lock decoder") = decoder
+                                }
+                                Err(e) => self.notifier.notify(Notification::Error(e)),
+                            }
+                            self.settings = *c;
+                            self.update_sources();
+                        }
+                        Command::Replay { from } => {
+                            if self.settings.rabbit_mq.stream.enabled {
+                                for task in &self.sources {
+                                    task.send(Control::Replay(from.clone()));
+                                }
+                            } else {
+                                self.notifier.notify(Notification::Error(
+                                    "Replay is only possible from a stream".into(),
+                                ));
+                            }
                         }
                     }
                 }
+                println!("Exiting from worker!");
             })
         });
         wh
     }
 
-    fn notify(&self, n: Notification) {
-        self.ntf_tx.send(n).expect("Failed to send notification");
-        self.ctx.request_repaint();
-    }
-
-    async fn connect(&self) -> Option<lapin::Consumer> {
-        let notify_err = |e: Box<dyn std::error::Error>| {
-            self.notify(Notification::ConnectionStatusChanged {
-                status: Err(e.to_string()),
-            });
-        };
-        let conn = lapin::Connection::connect(
-            &self.rmq_conf.connection_string(),
-            ConnectionProperties::default(),
-        )
-        .await;
-        let conn = match conn {
-            Ok(c) => c,
-            Err(e) => {
-                notify_err(Box::new(e));
-                return None;
-            }
-        };
-        let ch = match conn.create_channel().await {
-            Ok(ch) => ch,
-            Err(e) => {
-                notify_err(Box::new(e));
-                return None;
+    /// Starts sources added to the settings, stops removed ones and passes
+    /// the settings on to the rest
+    fn update_sources(&mut self) {
+        let mut configured = source::configured(&self.settings);
+        self.sources.retain(|task| {
+            let keep = configured.iter().any(|s| s.id() == task.id);
+            if !keep {
+                task.send(Control::Stop);
             }
-        };
-        let cons = match ch
-            .basic_consume(
-                "log",
-                "",
-                BasicConsumeOptions::default(),
-                FieldTable::default(),
-            )
-            .await
-        {
-            Ok(cons) => cons,
-            Err(e) => {
-                notify_err(Box::new(e));
-                return None;
-            }
-        };
-        self.conn_waker.wake();
-        self.notify(Notification::ConnectionStatusChanged { status: Ok(()) });
-        Some(cons)
+            keep
+        });
+        for task in &self.sources {
+            task.send(Control::Update(Box::new(self.settings.clone())));
+        }
+        configured.retain(|s| !self.sources.iter().any(|task| task.id == s.id()));
+        for source in configured {
+            let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+            let key = self.next_task;
+            self.next_task += 1;
+            self.sources.push(SourceTask {
+                id: source.id().to_string(),
+                key,
+                tx,
+            });
+            let driver = Driver::new(source, key, rx, self.notifier.clone(), self.decoder.clone());
+            tokio::spawn(driver.run());
+        }
     }
 
     fn get_cmd(&self) -> cmd_rcv::CmdRcv {
@@ -212,3 +276,30 @@ impl Worker {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::json_log::RawPayload;
+
+    #[test]
+    fn entries_wake_the_app() {
+        let ctx = egui::Context::default();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let notifier = Notifier {
+            ctx: ctx.clone(),
+            tx,
+        };
+        assert!(!ctx.has_requested_repaint());
+        notifier.notify(Notification::LogEntry {
+            entry: Box::new(LogEntry::raw(RawPayload {
+                error: String::new(),
+                data: Vec::new(),
+                batch_index: None,
+            })),
+            delivery: DeliveryId { task: 0, seq: 0 },
+        });
+        assert!(ctx.has_requested_repaint());
+        assert!(rx.try_recv().is_ok());
+    }
+}
@@ -0,0 +1,70 @@
+use lapin::{
+    message::Delivery,
+    types::{AMQPValue, FieldTable, ShortString},
+};
+use serde_json::{json, Value};
+use time::OffsetDateTime;
+
+use crate::types::{decode::JsonObject, json_log::Origin};
+
+/// Delivery info and the AMQP properties
+pub fn origin(delivery: &Delivery) -> Origin {
+    let props = &delivery.properties;
+    let string = |s: &Option<ShortString>| s.as_ref().map(|s| s.to_string());
+    Origin {
+        exchange: delivery.exchange.to_string(),
+        routing_key: delivery.routing_key.to_string(),
+        app_id: string(props.app_id()),
+        message_id: string(props.message_id()),
+        correlation_id: string(props.correlation_id()),
+        user_id: string(props.user_id()),
+        kind: string(props.kind()),
+        content_type: string(props.content_type()),
+        content_encoding: string(props.content_encoding()),
+        // Seconds since epoch by the spec
+        timestamp: props
+            .timestamp()
+            .and_then(|ts| OffsetDateTime::from_unix_timestamp(ts as i64).ok()),
+        reply_to: string(props.reply_to()),
+        expiration: string(props.expiration()),
+        delivery_mode: *props.delivery_mode(),
+        priority: *props.priority(),
+        cluster_id: string(props.cluster_id()),
+        redelivered: delivery.redelivered,
+        headers: props
+            .headers()
+            .as_ref()
+            .map(table_to_json)
+            .unwrap_or_default(),
+    }
+}
+
+fn table_to_json(table: &FieldTable) -> JsonObject {
+    table
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), value_to_json(v)))
+        .collect()
+}
+
+fn value_to_json(value: &AMQPValue) -> Value {
+    match value {
+        AMQPValue::Boolean(b) => (*b).into(),
+        AMQPValue::ShortShortInt(n) => (*n).into(),
+        AMQPValue::ShortShortUInt(n) => (*n).into(),
+        AMQPValue::ShortInt(n) => (*n).into(),
+        AMQPValue::ShortUInt(n) => (*n).into(),
+        AMQPValue::LongInt(n) => (*n).into(),
+        AMQPValue::LongUInt(n) => (*n).into(),
+        AMQPValue::LongLongInt(n) => (*n).into(),
+        AMQPValue::Float(f) => (*f).into(),
+        AMQPValue::Double(f) => (*f).into(),
+        AMQPValue::DecimalValue(d) => json!(d.value as f64 / 10f64.powi(d.scale as i32)),
+        AMQPValue::ShortString(s) => s.as_str().into(),
+        AMQPValue::LongString(s) => s.to_string().into(),
+        AMQPValue::FieldArray(a) => a.as_slice().iter().map(value_to_json).collect(),
+        AMQPValue::Timestamp(t) => (*t).into(),
+        AMQPValue::FieldTable(t) => Value::Object(table_to_json(t)),
+        AMQPValue::ByteArray(b) => String::from_utf8_lossy(b.as_slice()).into(),
+        AMQPValue::Void => Value::Null,
+    }
+}
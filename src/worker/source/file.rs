@@ -0,0 +1,363 @@
+//! Follows local files like `tail -F`. The glob pattern is expanded on every
+//! poll, so files created later are picked up too. A rotated file is read to
+//! its end before switching to the new one, a truncated one is read again
+//! from the start.
+//!
+//! Offsets of stored lines are remembered in the data directory, so after a
+//! restart files continue where they were left.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{File, Metadata},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app::{
+        config::{ReconnectSettings, Settings},
+        logs::logs_dir,
+    },
+    trace_err,
+};
+
+use super::{Delivery, LogSource, SourceEvent};
+
+/// How often files are checked for new lines
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Read from a file per poll at most
+const MAX_READ: u64 = 1024 * 1024;
+
+/// Offsets are saved at most that often
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct FileSource {
+    id: String,
+    pattern: String,
+    from_start: bool,
+    /// Set once files found on connect were opened, later ones are new
+    started: bool,
+    files: HashMap<PathBuf, Tailed>,
+    /// Where the stored lines end, by path
+    offsets: Option<HashMap<PathBuf, Offset>>,
+    offsets_dirty: bool,
+    last_save: Instant,
+    /// Lines read but not handed out yet
+    queue: VecDeque<Delivery>,
+    /// Where the lines handed out end, by tag
+    unacked: HashMap<u64, (PathBuf, Offset)>,
+    next_tag: u64,
+}
+
+/// Position in a particular file, a rotated one has another id
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct Offset {
+    file_id: Option<u64>,
+    offset: u64,
+}
+
+struct Tailed {
+    file: File,
+    file_id: Option<u64>,
+    /// Read up to
+    offset: u64,
+    /// Last line, until it's complete
+    partial: Vec<u8>,
+}
+
+impl FileSource {
+    pub fn new(pattern: &str, from_start: bool) -> Self {
+        FileSource {
+            id: format!("file:{pattern}"),
+            pattern: pattern.to_string(),
+            from_start,
+            started: false,
+            files: HashMap::new(),
+            offsets: None,
+            offsets_dirty: false,
+            last_save: Instant::now(),
+            queue: VecDeque::new(),
+            unacked: HashMap::new(),
+            next_tag: 0,
+        }
+    }
+
+    /// Opens files matching the pattern for the first time and reads what
+    /// was appended to all of them
+    fn poll(&mut self) -> Result<(), String> {
+        let paths = glob::glob(&self.pattern).map_err(|e| format!("Invalid pattern: {e}"))?;
+        for path in paths.filter_map(Result::ok) {
+            if path.is_file() && !self.files.contains_key(&path) {
+                if let Ok(tailed) = trace_err!(self.open(&path)) {
+                    // Where reading starts counts as stored, so reconnects
+                    // don't read skipped lines after all
+                    let offsets = self.offsets.get_or_insert_with(HashMap::new);
+                    if !offsets.contains_key(&path) {
+                        let start = Offset {
+                            file_id: tailed.file_id,
+                            offset: tailed.offset,
+                        };
+                        offsets.insert(path.clone(), start);
+                        self.offsets_dirty = true;
+                    }
+                    self.files.insert(path, tailed);
+                }
+            }
+        }
+        self.started = true;
+        let paths: Vec<PathBuf> = self.files.keys().cloned().collect();
+        for path in paths {
+            if let Err(e) = self.read(&path) {
+                // Opened again on the next poll if it's still there
+                println!("Failed to read {}: {e}", path.display());
+                self.files.remove(&path);
+            }
+        }
+        Ok(())
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Tailed> {
+        let file = File::open(path)?;
+        let meta = file.metadata()?;
+        let file_id = file_id(&meta);
+        let remembered = self.offsets.as_ref().and_then(|o| o.get(path));
+        let offset = match remembered {
+            Some(o) if o.file_id == file_id && o.offset <= meta.len() => o.offset,
+            // Rotated or truncated meanwhile
+            Some(_) => 0,
+            None if !self.started && !self.from_start => meta.len(),
+            None => 0,
+        };
+        Ok(Tailed {
+            file,
+            file_id,
+            offset,
+            partial: Vec::new(),
+        })
+    }
+
+    fn read(&mut self, path: &Path) -> io::Result<()> {
+        let tailed = self.files.get_mut(path).expect("Only known files are read");
+        if tailed.file.metadata()?.len() < tailed.offset {
+            tailed.offset = 0;
+            tailed.partial.clear();
+        }
+        tailed.file.seek(SeekFrom::Start(tailed.offset))?;
+        let mut data = Vec::new();
+        (&tailed.file).take(MAX_READ).read_to_end(&mut data)?;
+        let read_all = (data.len() as u64) < MAX_READ;
+        let start = tailed.offset - tailed.partial.len() as u64;
+        tailed.offset += data.len() as u64;
+        let mut pending = std::mem::take(&mut tailed.partial);
+        pending.extend_from_slice(&data);
+        let id = tailed.file_id;
+
+        let mut end = start;
+        let mut rest = pending.as_slice();
+        while let Some(idx) = rest.iter().position(|&b| b == b'\n') {
+            let (line, tail) = rest.split_at(idx + 1);
+            rest = tail;
+            end += line.len() as u64;
+            self.push(
+                path,
+                line,
+                Offset {
+                    file_id: id,
+                    offset: end,
+                },
+            );
+        }
+        let rest = rest.to_vec();
+
+        // Rotated, what's left of the old file won't be completed
+        let rotated = read_all && std::fs::metadata(path).is_ok_and(|meta| file_id(&meta) != id);
+        if rotated {
+            end += rest.len() as u64;
+            self.push(
+                path,
+                &rest,
+                Offset {
+                    file_id: id,
+                    offset: end,
+                },
+            );
+            let mut tailed = self.open_rotated(path)?;
+            std::mem::swap(self.files.get_mut(path).expect("Just read it"), &mut tailed);
+        } else if let Some(tailed) = self.files.get_mut(path) {
+            tailed.partial = rest;
+        }
+        Ok(())
+    }
+
+    /// New file under the path of a rotated one
+    fn open_rotated(&self, path: &Path) -> io::Result<Tailed> {
+        let file = File::open(path)?;
+        let file_id = file_id(&file.metadata()?);
+        Ok(Tailed {
+            file,
+            file_id,
+            offset: 0,
+            partial: Vec::new(),
+        })
+    }
+
+    /// Queues the line, blank ones are skipped
+    fn push(&mut self, path: &Path, line: &[u8], end: Offset) {
+        let line = line.trim_ascii_end();
+        if line.trim_ascii().is_empty() {
+            return;
+        }
+        let tag = self.next_tag;
+        self.next_tag += 1;
+        self.unacked.insert(tag, (path.to_path_buf(), end));
+        self.queue.push_back(Delivery {
+            tag,
+            data: line.to_vec(),
+            content_type: None,
+            content_encoding: None,
+            origin: None,
+        });
+    }
+
+    fn commit(&mut self, tag: u64) {
+        let Some((path, end)) = self.unacked.remove(&tag) else {
+            return;
+        };
+        let offsets = self.offsets.get_or_insert_with(HashMap::new);
+        let offset = offsets.entry(path).or_insert(end);
+        if offset.file_id != end.file_id || offset.offset < end.offset {
+            *offset = end;
+        }
+        self.offsets_dirty = true;
+        if self.last_save.elapsed() >= SAVE_INTERVAL {
+            self.save();
+        }
+    }
+
+    fn save(&mut self) {
+        self.last_save = Instant::now();
+        if !std::mem::take(&mut self.offsets_dirty) {
+            return;
+        }
+        let Some(offsets) = &self.offsets else {
+            return;
+        };
+        trace_err!(save_offsets(&offsets_file(&self.pattern), offsets), ());
+    }
+}
+
+#[async_trait]
+impl LogSource for FileSource {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    async fn connect(&mut self) -> Result<(), String> {
+        glob::Pattern::new(&self.pattern).map_err(|e| format!("Invalid pattern: {e}"))?;
+        if self.offsets.is_none() {
+            self.offsets = Some(load_offsets(&offsets_file(&self.pattern))?);
+        }
+        // Lines not stored yet are read again
+        self.files.clear();
+        self.queue.clear();
+        self.unacked.clear();
+        self.poll()
+    }
+
+    async fn next(&mut self) -> Result<SourceEvent, String> {
+        loop {
+            if let Some(delivery) = self.queue.pop_front() {
+                return Ok(SourceEvent::Delivery(Box::new(delivery)));
+            }
+            self.poll()?;
+            if self.queue.is_empty() {
+                if self.offsets_dirty {
+                    self.save();
+                }
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        }
+    }
+
+    async fn ack(&mut self, tag: u64) -> Result<(), String> {
+        self.commit(tag);
+        Ok(())
+    }
+
+    async fn nack(&mut self, tag: u64, requeue: bool) -> Result<(), String> {
+        if requeue {
+            // Offset stays before the line, so it's read again on the next
+            // connect unless a later line got stored meanwhile
+            self.unacked.remove(&tag);
+        } else {
+            self.commit(tag);
+        }
+        Ok(())
+    }
+
+    async fn reject(&mut self, tag: u64) -> Result<(), String> {
+        // Skipped, it won't decode any better next time
+        self.commit(tag);
+        Ok(())
+    }
+
+    async fn close(&mut self) {
+        self.save();
+        self.files.clear();
+    }
+
+    fn update(&mut self, settings: &Settings) {
+        self.from_start = settings.tail.from_start;
+    }
+
+    fn reconnect_settings(&self) -> ReconnectSettings {
+        ReconnectSettings::default()
+    }
+}
+
+#[cfg(unix)]
+fn file_id(meta: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(meta.ino())
+}
+
+/// Rotation is only noticed by the file getting shorter
+#[cfg(not(unix))]
+fn file_id(_meta: &Metadata) -> Option<u64> {
+    None
+}
+
+/// One file per pattern, named after it
+fn offsets_file(pattern: &str) -> PathBuf {
+    let name: String = pattern
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    logs_dir().join("tail").join(format!("{name}.json"))
+}
+
+fn load_offsets(path: &Path) -> Result<HashMap<PathBuf, Offset>, String> {
+    match std::fs::read(path) {
+        Ok(data) => serde_json::from_slice(&data).or_else(|e| {
+            // Starting over beats not following the files at all
+            println!("Ignoring damaged {}: {e}", path.display());
+            Ok(HashMap::new())
+        }),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(e) => Err(format!("Failed to read offsets: {e}")),
+    }
+}
+
+fn save_offsets(path: &Path, offsets: &HashMap<PathBuf, Offset>) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_vec(offsets).map_err(io::Error::other)?)?;
+    std::fs::rename(tmp, path)
+}
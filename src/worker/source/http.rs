@@ -0,0 +1,619 @@
+//! Local HTTP endpoint for services that can't publish to RabbitMQ. Every
+//! `POST /logs` is one message: a single JSON entry, an array or NDJSON,
+//! told apart the same way as AMQP message bodies. `Content-Type` and
+//! `Content-Encoding` are honored too.
+//!
+//! Only as much of HTTP/1.1 as log shippers use is spoken: fixed length and
+//! chunked bodies, `Expect: 100-continue` and keep-alive.
+
+use async_trait::async_trait;
+use std::time::Duration;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        watch,
+    },
+    task::{JoinHandle, JoinSet},
+};
+
+use crate::app::config::{HttpSettings, ReconnectSettings, Settings};
+
+use super::{Delivery, LogSource, SourceEvent};
+
+/// Requests accepted ahead of what's decoded
+const QUEUED_REQUESTS: usize = 16;
+
+/// Request line and headers together are limited to that
+const MAX_HEAD: u64 = 16 * 1024;
+
+/// Idle connections are closed after that
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Whole body has to arrive within that
+const BODY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Further connections wait in the listen backlog
+const MAX_CONNECTIONS: usize = 64;
+
+pub struct HttpSource {
+    id: String,
+    /// Token and size limit apply to the next request already
+    conf: watch::Sender<HttpSettings>,
+    requests: Option<Receiver<Body>>,
+    /// Accepts connections while connected
+    server: Option<JoinHandle<()>>,
+    next_tag: u64,
+}
+
+struct Body {
+    data: Vec<u8>,
+    content_type: Option<String>,
+    content_encoding: Option<String>,
+}
+
+impl HttpSource {
+    pub fn new(conf: &HttpSettings) -> Self {
+        HttpSource {
+            id: format!("http:{}", conf.bind),
+            conf: watch::Sender::new(conf.clone()),
+            requests: None,
+            server: None,
+            next_tag: 0,
+        }
+    }
+}
+
+#[async_trait]
+impl LogSource for HttpSource {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    async fn connect(&mut self) -> Result<(), String> {
+        self.close().await;
+        let bind = self.conf.borrow().bind.clone();
+        let listener = TcpListener::bind(&bind)
+            .await
+            .map_err(|e| format!("Can't listen on {bind}: {e}"))?;
+        let (tx, rx) = mpsc::channel(QUEUED_REQUESTS);
+        let conf = self.conf.subscribe();
+        self.server = Some(tokio::spawn(accept(listener, conf, tx)));
+        self.requests = Some(rx);
+        Ok(())
+    }
+
+    async fn next(&mut self) -> Result<SourceEvent, String> {
+        let Some(requests) = &mut self.requests else {
+            return Err("Not connected".to_string());
+        };
+        let body = requests
+            .recv()
+            .await
+            .ok_or_else(|| "Listener stopped".to_string())?;
+        let tag = self.next_tag;
+        self.next_tag += 1;
+        Ok(SourceEvent::Delivery(Box::new(Delivery {
+            tag,
+            data: body.data,
+            content_type: body.content_type,
+            content_encoding: body.content_encoding,
+            origin: None,
+        })))
+    }
+
+    // Requests are answered once queued, there's nobody to tell later
+    async fn ack(&mut self, _tag: u64) -> Result<(), String> {
+        Ok(())
+    }
+
+    async fn nack(&mut self, _tag: u64, _requeue: bool) -> Result<(), String> {
+        Ok(())
+    }
+
+    async fn reject(&mut self, _tag: u64) -> Result<(), String> {
+        Ok(())
+    }
+
+    async fn close(&mut self) {
+        self.requests = None;
+        if let Some(server) = self.server.take() {
+            server.abort();
+        }
+    }
+
+    fn update(&mut self, settings: &Settings) {
+        self.conf.send_replace(settings.http.clone());
+    }
+
+    fn reconnect_settings(&self) -> ReconnectSettings {
+        ReconnectSettings::default()
+    }
+}
+
+/// Connections are dropped along with the task
+async fn accept(listener: TcpListener, conf: watch::Receiver<HttpSettings>, tx: Sender<Body>) {
+    let mut connections = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept(), if connections.len() < MAX_CONNECTIONS => match accepted {
+                Ok((stream, _)) => {
+                    connections.spawn(serve(stream, conf.clone(), tx.clone()));
+                }
+                Err(e) => {
+                    // Likely out of file descriptors, give them time to close
+                    println!("Failed to accept HTTP connection: {e}");
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+            },
+            Some(_) = connections.join_next() => {}
+        }
+    }
+}
+
+struct Response {
+    status: &'static str,
+    message: &'static str,
+    /// The rest of the request wasn't read
+    close: bool,
+}
+
+impl Response {
+    const ACCEPTED: Response = Response::new("202 Accepted", "");
+
+    const fn new(status: &'static str, message: &'static str) -> Self {
+        Response {
+            status,
+            message,
+            close: false,
+        }
+    }
+
+    const fn closing(status: &'static str, message: &'static str) -> Self {
+        Response {
+            status,
+            message,
+            close: true,
+        }
+    }
+}
+
+async fn serve(stream: TcpStream, conf: watch::Receiver<HttpSettings>, tx: Sender<Body>) {
+    let (read, mut write) = stream.into_split();
+    let mut reader = BufReader::new(read);
+    loop {
+        let head = match tokio::time::timeout(IDLE_TIMEOUT, read_head(&mut reader)).await {
+            Ok(Ok(Some(head))) => head,
+            Ok(Ok(None)) | Err(_) => return,
+            Ok(Err(response)) => {
+                let _ = respond(&mut write, &response, true).await;
+                return;
+            }
+        };
+        let conf = conf.borrow().clone();
+        let handled =
+            tokio::time::timeout(BODY_TIMEOUT, handle(&head, &conf, &mut reader, &mut write));
+        let response = match handled.await {
+            Ok(Ok(body)) => match tx.send(body).await {
+                Ok(()) => Response::ACCEPTED,
+                Err(_) => Response::new("503 Service Unavailable", "Source is stopping"),
+            },
+            Ok(Err(response)) => response,
+            Err(_) => Response::closing("408 Request Timeout", "Body took too long"),
+        };
+        let close = response.close || !head.keep_alive;
+        if respond(&mut write, &response, close).await.is_err() || close {
+            return;
+        }
+    }
+}
+
+struct Head {
+    method: String,
+    path: String,
+    keep_alive: bool,
+    content_length: Option<u64>,
+    chunked: bool,
+    content_type: Option<String>,
+    content_encoding: Option<String>,
+    authorization: Option<String>,
+    expect_continue: bool,
+}
+
+/// `None` if the client closed the connection
+async fn read_head<R: AsyncRead + Unpin>(
+    reader: &mut BufReader<R>,
+) -> Result<Option<Head>, Response> {
+    let too_big = || Response::closing("431 Request Header Fields Too Large", "Headers too big");
+    let bad = |message| Response::closing("400 Bad Request", message);
+    let mut limited = reader.take(MAX_HEAD);
+    let mut line = String::new();
+    // Blank lines before a request are to be ignored
+    while line.trim().is_empty() {
+        line.clear();
+        match limited.read_line(&mut line).await {
+            Ok(0) => return Ok(None),
+            Ok(_) if !line.ends_with('\n') => return Err(too_big()),
+            Ok(_) => {}
+            Err(_) => return Err(bad("Invalid request line")),
+        }
+    }
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target), Some(version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(bad("Invalid request line"));
+    };
+    let mut head = Head {
+        method: method.to_string(),
+        path: target.split('?').next().unwrap_or_default().to_string(),
+        keep_alive: version != "HTTP/1.0",
+        content_length: None,
+        chunked: false,
+        content_type: None,
+        content_encoding: None,
+        authorization: None,
+        expect_continue: false,
+    };
+    loop {
+        line.clear();
+        match limited.read_line(&mut line).await {
+            Ok(_) if !line.ends_with('\n') => return Err(too_big()),
+            Ok(_) => {}
+            Err(_) => return Err(bad("Invalid header")),
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            return Ok(Some(head));
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            return Err(bad("Invalid header"));
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-length" => {
+                let length = value.parse().map_err(|_| bad("Invalid Content-Length"))?;
+                head.content_length = Some(length);
+            }
+            "transfer-encoding" => head.chunked = value.eq_ignore_ascii_case("chunked"),
+            "content-type" => head.content_type = Some(value.to_string()),
+            "content-encoding" => head.content_encoding = Some(value.to_string()),
+            "authorization" => head.authorization = Some(value.to_string()),
+            "expect" => head.expect_continue = value.eq_ignore_ascii_case("100-continue"),
+            "connection" => {
+                if value.eq_ignore_ascii_case("close") {
+                    head.keep_alive = false;
+                } else if value.eq_ignore_ascii_case("keep-alive") {
+                    head.keep_alive = true;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Checks the request and reads its body
+async fn handle<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+    head: &Head,
+    conf: &HttpSettings,
+    reader: &mut BufReader<R>,
+    write: &mut W,
+) -> Result<Body, Response> {
+    if head.path != "/logs" {
+        return Err(Response::closing(
+            "404 Not Found",
+            "Logs are posted to /logs",
+        ));
+    }
+    if head.method != "POST" {
+        return Err(Response::closing(
+            "405 Method Not Allowed",
+            "Logs are sent with POST",
+        ));
+    }
+    if let Some(token) = conf.token.as_deref().filter(|t| !t.is_empty()) {
+        let given = head
+            .authorization
+            .as_deref()
+            .and_then(|a| a.strip_prefix("Bearer "));
+        if given.map(str::trim) != Some(token) {
+            return Err(Response::closing("401 Unauthorized", "Invalid token"));
+        }
+    }
+    let limit = conf.max_body_mb.saturating_mul(1024 * 1024);
+    let too_big = || Response::closing("413 Content Too Large", "Body too big");
+    if head.content_length.is_some_and(|length| length > limit) {
+        return Err(too_big());
+    }
+    if !head.chunked && head.content_length.is_none() {
+        return Err(Response::closing(
+            "411 Length Required",
+            "Content-Length is required",
+        ));
+    }
+    if head.expect_continue {
+        write
+            .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")
+            .await
+            .map_err(|_| Response::closing("400 Bad Request", "Connection lost"))?;
+    }
+    let data = match head.content_length {
+        _ if head.chunked => read_chunked(reader, limit).await?,
+        Some(length) => {
+            let mut data = Vec::with_capacity(length as usize);
+            let read = reader.take(length).read_to_end(&mut data).await;
+            if read.is_err() || data.len() as u64 != length {
+                return Err(Response::closing("400 Bad Request", "Body ended early"));
+            }
+            data
+        }
+        None => unreachable!("Checked above"),
+    };
+    Ok(Body {
+        data,
+        content_type: head.content_type.clone(),
+        content_encoding: head.content_encoding.clone(),
+    })
+}
+
+async fn read_chunked<R: AsyncRead + Unpin>(
+    reader: &mut BufReader<R>,
+    limit: u64,
+) -> Result<Vec<u8>, Response> {
+    let bad = || Response::closing("400 Bad Request", "Invalid chunked body");
+    let mut data = Vec::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        (&mut *reader)
+            .take(MAX_HEAD)
+            .read_line(&mut line)
+            .await
+            .map_err(|_| bad())?;
+        // Extensions after `;` aren't used by anyone
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = u64::from_str_radix(size, 16).map_err(|_| bad())?;
+        if size == 0 {
+            break;
+        }
+        // Sizes are up to the client, adding one could overflow
+        if size > limit.saturating_sub(data.len() as u64) {
+            return Err(Response::closing("413 Content Too Large", "Body too big"));
+        }
+        let start = data.len();
+        (&mut *reader)
+            .take(size)
+            .read_to_end(&mut data)
+            .await
+            .map_err(|_| bad())?;
+        if (data.len() - start) as u64 != size {
+            return Err(bad());
+        }
+        let mut crlf = [0; 2];
+        reader.read_exact(&mut crlf).await.map_err(|_| bad())?;
+        if &crlf != b"\r\n" {
+            return Err(bad());
+        }
+    }
+    // Trailers, up to the blank line
+    loop {
+        line.clear();
+        (&mut *reader)
+            .take(MAX_HEAD)
+            .read_line(&mut line)
+            .await
+            .map_err(|_| bad())?;
+        if line.trim_end().is_empty() {
+            return Ok(data);
+        }
+    }
+}
+
+async fn respond<W: AsyncWrite + Unpin>(
+    write: &mut W,
+    response: &Response,
+    close: bool,
+) -> std::io::Result<()> {
+    let mut out = format!(
+        "HTTP/1.1 {}\r\nContent-Length: {}\r\n",
+        response.status,
+        response.message.len()
+    );
+    if !response.message.is_empty() {
+        out.push_str("Content-Type: text/plain\r\n");
+    }
+    if response.status.starts_with("401") {
+        out.push_str("WWW-Authenticate: Bearer\r\n");
+    } else if response.status.starts_with("405") {
+        out.push_str("Allow: POST\r\n");
+    }
+    if close {
+        out.push_str("Connection: close\r\n");
+    }
+    out.push_str("\r\n");
+    out.push_str(response.message);
+    write.write_all(out.as_bytes()).await?;
+    write.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: usize = 1024 * 1024;
+
+    fn conf() -> HttpSettings {
+        HttpSettings {
+            token: Some("secret".to_string()),
+            max_body_mb: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Reads the head of `request` and handles it, returning the body or
+    /// the status of the error response along with what was written back
+    async fn post(request: &[u8]) -> (Result<Vec<u8>, &'static str>, String) {
+        let mut reader = BufReader::new(request);
+        let head = read_head(&mut reader)
+            .await
+            .map_err(|r| r.status)
+            .expect("Valid head")
+            .expect("Request not empty");
+        let mut written = Vec::new();
+        let body = handle(&head, &conf(), &mut reader, &mut written).await;
+        (
+            body.map(|body| body.data).map_err(|r| r.status),
+            String::from_utf8(written).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn reads_head() {
+        let request = b"\r\nPOST /logs?x=1 HTTP/1.0\r\nContent-Length: 2\r\n\
+            content-type: application/x-ndjson\r\nConnection: keep-alive\r\n\
+            Authorization: Bearer secret\r\nExpect: 100-continue\r\n\r\n{}";
+        let mut reader = BufReader::new(&request[..]);
+        let head = read_head(&mut reader).await.ok().unwrap().unwrap();
+        assert_eq!(head.method, "POST");
+        assert_eq!(head.path, "/logs");
+        assert!(head.keep_alive);
+        assert_eq!(head.content_length, Some(2));
+        assert!(!head.chunked);
+        assert_eq!(head.content_type.as_deref(), Some("application/x-ndjson"));
+        assert_eq!(head.authorization.as_deref(), Some("Bearer secret"));
+        assert!(head.expect_continue);
+
+        let mut reader = BufReader::new(&b""[..]);
+        assert!(read_head(&mut reader).await.ok().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn refuses_bad_heads() {
+        let cases: [(&[u8], &str); 4] = [
+            (b"POST /logs\r\n\r\n", "400 Bad Request"),
+            (
+                b"POST /logs HTTP/1.1\r\nNo colon\r\n\r\n",
+                "400 Bad Request",
+            ),
+            (
+                b"POST /logs HTTP/1.1\r\nContent-Length: -1\r\n\r\n",
+                "400 Bad Request",
+            ),
+            (
+                b"POST /logs HTTP/1.1\r\nHost: x",
+                "431 Request Header Fields Too Large",
+            ),
+        ];
+        for (request, status) in cases {
+            let mut reader = BufReader::new(request);
+            let response = read_head(&mut reader).await.err().expect("Refused");
+            assert_eq!(response.status, status);
+        }
+        let huge = format!(
+            "POST /logs HTTP/1.1\r\nX: {}\r\n\r\n",
+            "a".repeat(MAX_HEAD as usize)
+        );
+        let mut reader = BufReader::new(huge.as_bytes());
+        let response = read_head(&mut reader).await.err().expect("Refused");
+        assert_eq!(response.status, "431 Request Header Fields Too Large");
+    }
+
+    #[tokio::test]
+    async fn reads_sized_body() {
+        let (body, written) = post(
+            b"POST /logs HTTP/1.1\r\nAuthorization: Bearer secret\r\n\
+              Content-Length: 5\r\nExpect: 100-continue\r\n\r\nhello, next",
+        )
+        .await;
+        assert_eq!(body.unwrap(), b"hello");
+        assert_eq!(written, "HTTP/1.1 100 Continue\r\n\r\n");
+
+        let (body, _) = post(
+            b"POST /logs HTTP/1.1\r\nAuthorization: Bearer secret\r\n\
+              Content-Length: 5\r\n\r\nhel",
+        )
+        .await;
+        assert_eq!(body.unwrap_err(), "400 Bad Request");
+    }
+
+    #[tokio::test]
+    async fn reads_chunked_body() {
+        let (body, _) = post(
+            b"POST /logs HTTP/1.1\r\nAuthorization: Bearer secret\r\n\
+              Transfer-Encoding: chunked\r\n\r\n\
+              5;ext=1\r\nhello\r\n1\r\n \r\nA\r\n0123456789\r\n0\r\nTrailer: x\r\n\r\n",
+        )
+        .await;
+        assert_eq!(body.unwrap(), b"hello 0123456789");
+
+        for chunks in ["5\r\nhel", "5\r\nhelloXX0\r\n\r\n", "z\r\n"] {
+            let request = format!(
+                "POST /logs HTTP/1.1\r\nAuthorization: Bearer secret\r\n\
+                 Transfer-Encoding: chunked\r\n\r\n{chunks}"
+            );
+            let (body, _) = post(request.as_bytes()).await;
+            assert_eq!(body.unwrap_err(), "400 Bad Request", "{chunks:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_big_bodies() {
+        let length = format!(
+            "POST /logs HTTP/1.1\r\nAuthorization: Bearer secret\r\n\
+             Content-Length: {}\r\nExpect: 100-continue\r\n\r\n",
+            MB + 1
+        );
+        let (body, written) = post(length.as_bytes()).await;
+        assert_eq!(body.unwrap_err(), "413 Content Too Large");
+        // Refused before the client sends the body
+        assert!(written.is_empty());
+
+        let half = MB / 2 + 1;
+        let chunked = format!(
+            "POST /logs HTTP/1.1\r\nAuthorization: Bearer secret\r\n\
+             Transfer-Encoding: chunked\r\n\r\n{half:x}\r\n{}\r\n{half:x}\r\n",
+            "a".repeat(half)
+        );
+        let (body, _) = post(chunked.as_bytes()).await;
+        assert_eq!(body.unwrap_err(), "413 Content Too Large");
+
+        // Would overflow when added to what's read
+        let chunked = format!(
+            "POST /logs HTTP/1.1\r\nAuthorization: Bearer secret\r\n\
+             Transfer-Encoding: chunked\r\n\r\n1\r\na\r\n{:x}\r\n",
+            u64::MAX
+        );
+        let (body, _) = post(chunked.as_bytes()).await;
+        assert_eq!(body.unwrap_err(), "413 Content Too Large");
+    }
+
+    #[tokio::test]
+    async fn checks_request() {
+        let cases: [(&[u8], &str); 5] = [
+            (
+                b"POST /logs HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n",
+                "411 Length Required",
+            ),
+            (
+                b"POST /logs HTTP/1.1\r\nContent-Length: 0\r\n\r\n",
+                "401 Unauthorized",
+            ),
+            (
+                b"POST /logs HTTP/1.1\r\nAuthorization: Bearer wrong\r\nContent-Length: 0\r\n\r\n",
+                "401 Unauthorized",
+            ),
+            (
+                b"GET /logs HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n",
+                "405 Method Not Allowed",
+            ),
+            (
+                b"POST / HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n",
+                "404 Not Found",
+            ),
+        ];
+        for (request, status) in cases {
+            let (body, _) = post(request).await;
+            assert_eq!(body.unwrap_err(), status);
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! Source fed from a channel, so what drives sources can be tested without
+//! a broker. Calls made on the source are reported back.
+
+use async_trait::async_trait;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::app::config::{DecodeErrorAction, ReconnectSettings, Settings};
+
+use super::{Delivery, LogSource, SourceEvent};
+
+/// What was asked of the source
+#[derive(Debug, Clone, PartialEq)]
+pub enum Call {
+    Connect,
+    Ack(u64),
+    Nack { tag: u64, requeue: bool },
+    Reject(u64),
+    Close,
+}
+
+pub struct MemorySource {
+    events: UnboundedReceiver<SourceEvent>,
+    calls: UnboundedSender<Call>,
+    pub on_decode_error: DecodeErrorAction,
+}
+
+/// Test side of a [`MemorySource`]
+pub struct MemoryHandle {
+    events: UnboundedSender<SourceEvent>,
+    pub calls: UnboundedReceiver<Call>,
+}
+
+impl MemorySource {
+    pub fn new() -> (Self, MemoryHandle) {
+        let (events_tx, events) = mpsc::unbounded_channel();
+        let (calls, calls_rx) = mpsc::unbounded_channel();
+        let source = MemorySource {
+            events,
+            calls,
+            on_decode_error: DecodeErrorAction::Keep,
+        };
+        let handle = MemoryHandle {
+            events: events_tx,
+            calls: calls_rx,
+        };
+        (source, handle)
+    }
+
+    fn call(&self, call: Call) {
+        // The test may have stopped listening
+        let _ = self.calls.send(call);
+    }
+}
+
+impl MemoryHandle {
+    pub fn deliver(&self, tag: u64, data: &str) {
+        self.send(SourceEvent::Delivery(Box::new(Delivery {
+            tag,
+            data: data.as_bytes().to_vec(),
+            content_type: None,
+            content_encoding: None,
+            origin: None,
+        })));
+    }
+
+    pub fn send(&self, event: SourceEvent) {
+        self.events.send(event).expect("Source is gone");
+    }
+}
+
+#[async_trait]
+impl LogSource for MemorySource {
+    fn id(&self) -> &str {
+        "memory"
+    }
+
+    async fn connect(&mut self) -> Result<(), String> {
+        self.call(Call::Connect);
+        Ok(())
+    }
+
+    async fn next(&mut self) -> Result<SourceEvent, String> {
+        self.events
+            .recv()
+            .await
+            .ok_or_else(|| "Handle dropped".to_string())
+    }
+
+    async fn ack(&mut self, tag: u64) -> Result<(), String> {
+        self.call(Call::Ack(tag));
+        Ok(())
+    }
+
+    async fn nack(&mut self, tag: u64, requeue: bool) -> Result<(), String> {
+        self.call(Call::Nack { tag, requeue });
+        Ok(())
+    }
+
+    async fn reject(&mut self, tag: u64) -> Result<(), String> {
+        self.call(Call::Reject(tag));
+        Ok(())
+    }
+
+    async fn close(&mut self) {
+        self.call(Call::Close);
+    }
+
+    fn update(&mut self, _settings: &Settings) {}
+
+    fn reconnect_settings(&self) -> ReconnectSettings {
+        ReconnectSettings::default()
+    }
+
+    fn on_decode_error(&self) -> DecodeErrorAction {
+        self.on_decode_error
+    }
+}
@@ -0,0 +1,112 @@
+//! Where log messages come from. Every [`LogSource`] is driven by a task of
+//! its own, which (re)connects it, decodes what it receives into
+//! [`LogEntry`] and acknowledges messages once the app stored their entries.
+//!
+//! [`LogEntry`]: crate::types::json_log::LogEntry
+
+use async_trait::async_trait;
+
+use crate::{
+    app::config::{DecodeErrorAction, ReconnectSettings, Settings, StreamOffset},
+    types::json_log::Origin,
+};
+
+use super::ConnectionState;
+
+use file::FileSource;
+use http::HttpSource;
+use rabbitmq::RabbitMqSource;
+use stdin::StdinSource;
+use syslog::SyslogSource;
+
+mod file;
+mod http;
+#[cfg(test)]
+pub mod memory;
+mod rabbitmq;
+mod stdin;
+mod syslog;
+
+/// Tells sources apart, stored with their entries
+pub type SourceId = String;
+
+/// What [`LogSource::next`] waits for
+pub enum SourceEvent {
+    Delivery(Box<Delivery>),
+    /// Connection state the source noticed itself, like the end of input
+    State(ConnectionState),
+}
+
+/// Message as received, before decoding
+pub struct Delivery {
+    /// Passed back to [`LogSource::ack`], [`LogSource::nack`] or
+    /// [`LogSource::reject`]
+    pub tag: u64,
+    pub data: Vec<u8>,
+    pub content_type: Option<String>,
+    pub content_encoding: Option<String>,
+    /// Replaces whatever the entries say about their origin
+    pub origin: Option<Origin>,
+}
+
+#[async_trait]
+pub trait LogSource: Send {
+    fn id(&self) -> &str;
+
+    /// Drops the previous connection if any. Messages not acked by then are
+    /// delivered again if the source is able to.
+    async fn connect(&mut self) -> Result<(), String>;
+
+    /// Waits for the next message or state change, `Err` means the
+    /// connection is lost
+    async fn next(&mut self) -> Result<SourceEvent, String>;
+
+    /// All entries of the message are stored
+    async fn ack(&mut self, tag: u64) -> Result<(), String>;
+
+    /// Some entries of the message couldn't be stored. With `requeue` it
+    /// should be delivered again if the source is able to.
+    async fn nack(&mut self, tag: u64, requeue: bool) -> Result<(), String>;
+
+    /// The message couldn't be decoded and shouldn't be delivered again
+    async fn reject(&mut self, tag: u64) -> Result<(), String>;
+
+    /// The source was removed from the settings
+    async fn close(&mut self);
+
+    /// Settings changed, they apply from the next connect
+    fn update(&mut self, settings: &Settings);
+
+    fn reconnect_settings(&self) -> ReconnectSettings;
+
+    fn on_decode_error(&self) -> DecodeErrorAction {
+        DecodeErrorAction::Keep
+    }
+
+    /// Reads from `from` once reconnected. Returns false if the source
+    /// can't go back.
+    fn replay(&mut self, _from: &StreamOffset) -> bool {
+        false
+    }
+}
+
+/// Sources enabled in the settings
+pub fn configured(settings: &Settings) -> Vec<Box<dyn LogSource>> {
+    let mut sources: Vec<Box<dyn LogSource>> = Vec::new();
+    if settings.rabbit_mq.enabled {
+        sources.push(Box::new(RabbitMqSource::new(settings)));
+    }
+    for path in &settings.tail.paths {
+        sources.push(Box::new(FileSource::new(path, settings.tail.from_start)));
+    }
+    if settings.http.enabled {
+        sources.push(Box::new(HttpSource::new(&settings.http)));
+    }
+    if settings.syslog.enabled {
+        sources.push(Box::new(SyslogSource::new(&settings.syslog)));
+    }
+    if settings.stdin {
+        sources.push(Box::new(StdinSource::new()));
+    }
+    sources
+}
@@ -0,0 +1,191 @@
+//! Consumes a queue or a stream of a RabbitMQ broker
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use futures::StreamExt;
+use lapin::{
+    acker::Acker,
+    options::{
+        BasicAckOptions, BasicConsumeOptions, BasicNackOptions, BasicQosOptions, BasicRejectOptions,
+    },
+    types::FieldTable,
+    ConnectionProperties,
+};
+
+use crate::{
+    app::config::{DecodeErrorAction, RabbitMQSettings, ReconnectSettings, Settings, StreamOffset},
+    trace_err,
+    worker::{properties, tls, topology},
+};
+
+use super::{Delivery, LogSource, SourceEvent};
+
+pub struct RabbitMqSource {
+    conf: RabbitMQSettings,
+    on_decode_error: DecodeErrorAction,
+    conn: Option<lapin::Connection>,
+    consumer: Option<lapin::Consumer>,
+    /// Deliveries of the current channel by their tag
+    ackers: HashMap<u64, Acker>,
+    /// Offset to read the stream from once we reconnect
+    replay_from: Option<StreamOffset>,
+}
+
+impl RabbitMqSource {
+    pub fn new(settings: &Settings) -> Self {
+        RabbitMqSource {
+            conf: settings.rabbit_mq.clone(),
+            on_decode_error: settings.decode.on_decode_error,
+            conn: None,
+            consumer: None,
+            ackers: HashMap::new(),
+            replay_from: None,
+        }
+    }
+
+    /// Connects and starts consuming. Streams are read from `replay_from`
+    /// if given, otherwise from the configured offset.
+    async fn open(
+        &self,
+        replay_from: Option<&StreamOffset>,
+    ) -> Result<(lapin::Connection, lapin::Consumer), String> {
+        self.conf.validate()?;
+        let conn = if self.conf.tls.enabled {
+            let connector = tls::connector(&self.conf.tls).map_err(|e| e.to_string())?;
+            let uri = self.conf.connection_string().parse()?;
+            // The error is lapin's own, there's no making it smaller
+            #[allow(clippy::result_large_err)]
+            let connect = Box::new(move |uri: &_| tls::connect(uri, &connector));
+            lapin::Connection::connector(uri, connect, ConnectionProperties::default()).await
+        } else {
+            lapin::Connection::connect(
+                &self.conf.connection_string(),
+                ConnectionProperties::default(),
+            )
+            .await
+        }
+        .map_err(|e| e.to_string())?;
+        let ch = conn.create_channel().await.map_err(|e| e.to_string())?;
+        let stream = &self.conf.stream;
+        let queue = topology::declare(&ch, &self.conf.topology, stream.enabled)
+            .await
+            .map_err(|e| e.to_string())?;
+        if self.conf.prefetch > 0 {
+            ch.basic_qos(self.conf.prefetch, BasicQosOptions::default())
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        let arguments = if stream.enabled {
+            topology::stream_arguments(replay_from.unwrap_or(&stream.offset))
+        } else {
+            FieldTable::default()
+        };
+        let cons = ch
+            .basic_consume(&queue, "", BasicConsumeOptions::default(), arguments)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok((conn, cons))
+    }
+}
+
+#[async_trait]
+impl LogSource for RabbitMqSource {
+    fn id(&self) -> &str {
+        "rabbitmq"
+    }
+
+    async fn connect(&mut self) -> Result<(), String> {
+        self.close().await;
+        let (conn, consumer) = self.open(self.replay_from.as_ref()).await?;
+        self.conn = Some(conn);
+        self.consumer = Some(consumer);
+        self.replay_from = None;
+        Ok(())
+    }
+
+    async fn next(&mut self) -> Result<SourceEvent, String> {
+        let Some(consumer) = &mut self.consumer else {
+            return Err("Not connected".to_string());
+        };
+        let delivery = match consumer.next().await {
+            Some(Ok(delivery)) => delivery,
+            // Channel or connection is gone
+            Some(Err(e)) => return Err(e.to_string()),
+            None => return Err("Consumer was cancelled".to_string()),
+        };
+        let origin = properties::origin(&delivery);
+        self.ackers.insert(delivery.delivery_tag, delivery.acker);
+        Ok(SourceEvent::Delivery(Box::new(Delivery {
+            tag: delivery.delivery_tag,
+            data: delivery.data,
+            content_type: origin.content_type.clone(),
+            content_encoding: origin.content_encoding.clone(),
+            origin: Some(origin),
+        })))
+    }
+
+    async fn ack(&mut self, tag: u64) -> Result<(), String> {
+        // Gone if the channel was closed meanwhile, the broker redelivers
+        // those anyway
+        let Some(acker) = self.ackers.remove(&tag) else {
+            return Ok(());
+        };
+        acker
+            .ack(BasicAckOptions::default())
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn nack(&mut self, tag: u64, requeue: bool) -> Result<(), String> {
+        let Some(acker) = self.ackers.remove(&tag) else {
+            return Ok(());
+        };
+        acker
+            .nack(BasicNackOptions {
+                multiple: false,
+                requeue,
+            })
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn reject(&mut self, tag: u64) -> Result<(), String> {
+        let Some(acker) = self.ackers.remove(&tag) else {
+            return Ok(());
+        };
+        acker
+            .reject(BasicRejectOptions { requeue: false })
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn close(&mut self) {
+        self.consumer = None;
+        self.ackers.clear();
+        if let Some(old) = self.conn.take() {
+            trace_err!(old.close(200, "Reconnecting").await, ());
+        }
+    }
+
+    fn update(&mut self, settings: &Settings) {
+        self.conf = settings.rabbit_mq.clone();
+        self.on_decode_error = settings.decode.on_decode_error;
+    }
+
+    fn reconnect_settings(&self) -> ReconnectSettings {
+        self.conf.reconnect.clone()
+    }
+
+    fn on_decode_error(&self) -> DecodeErrorAction {
+        self.on_decode_error
+    }
+
+    fn replay(&mut self, from: &StreamOffset) -> bool {
+        if !self.conf.stream.enabled {
+            return false;
+        }
+        self.replay_from = Some(from.clone());
+        true
+    }
+}
@@ -0,0 +1,112 @@
+//! Lines piped into the process, as in `my-service | bucklog`. Stdin is read
+//! on a thread of its own since reads block. After the end of input the
+//! source stays idle, entries received so far remain to be looked at.
+
+use std::io::BufRead;
+
+use async_trait::async_trait;
+use tokio::sync::mpsc::{self, Receiver};
+
+use crate::app::config::{ReconnectSettings, Settings};
+
+use super::{ConnectionState, Delivery, LogSource, SourceEvent};
+
+/// Lines read ahead of what's decoded
+const QUEUED_LINES: usize = 1024;
+
+#[derive(Default)]
+pub struct StdinSource {
+    /// Started on the first connect, stdin can't be read twice
+    lines: Option<Receiver<Vec<u8>>>,
+    /// End of input was reported
+    finished: bool,
+    next_tag: u64,
+}
+
+impl StdinSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl LogSource for StdinSource {
+    fn id(&self) -> &str {
+        "stdin"
+    }
+
+    async fn connect(&mut self) -> Result<(), String> {
+        if self.lines.is_some() {
+            return Ok(());
+        }
+        let (tx, rx) = mpsc::channel(QUEUED_LINES);
+        std::thread::spawn(move || {
+            let mut stdin = std::io::stdin().lock();
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                match stdin.read_until(b'\n', &mut line) {
+                    Ok(0) => break,
+                    Ok(_) => {}
+                    Err(e) => {
+                        println!("Failed to read stdin: {e}");
+                        break;
+                    }
+                }
+                let data = line.trim_ascii();
+                if !data.is_empty() && tx.blocking_send(data.to_vec()).is_err() {
+                    return;
+                }
+            }
+        });
+        self.lines = Some(rx);
+        Ok(())
+    }
+
+    async fn next(&mut self) -> Result<SourceEvent, String> {
+        let line = match &mut self.lines {
+            Some(lines) => lines.recv().await,
+            None => return Err("Not connected".to_string()),
+        };
+        let Some(data) = line else {
+            // End of input, nothing more will come
+            if !std::mem::replace(&mut self.finished, true) {
+                return Ok(SourceEvent::State(ConnectionState::Finished(
+                    "End of input".to_string(),
+                )));
+            }
+            return std::future::pending().await;
+        };
+        let tag = self.next_tag;
+        self.next_tag += 1;
+        Ok(SourceEvent::Delivery(Box::new(Delivery {
+            tag,
+            data,
+            content_type: None,
+            content_encoding: None,
+            origin: None,
+        })))
+    }
+
+    async fn ack(&mut self, _tag: u64) -> Result<(), String> {
+        Ok(())
+    }
+
+    async fn nack(&mut self, _tag: u64, _requeue: bool) -> Result<(), String> {
+        Ok(())
+    }
+
+    async fn reject(&mut self, _tag: u64) -> Result<(), String> {
+        Ok(())
+    }
+
+    async fn close(&mut self) {
+        self.lines = None;
+    }
+
+    fn update(&mut self, _settings: &Settings) {}
+
+    fn reconnect_settings(&self) -> ReconnectSettings {
+        ReconnectSettings::default()
+    }
+}
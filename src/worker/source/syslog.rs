@@ -0,0 +1,258 @@
+//! Syslog receiver for network appliances and daemons that speak nothing
+//! else. A datagram is one message. Over TCP messages are either octet
+//! counted or end with a newline (RFC 6587), told apart by their first byte.
+
+use async_trait::async_trait;
+use std::time::Duration;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader},
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::mpsc::{self, Receiver, Sender},
+    task::{JoinHandle, JoinSet},
+};
+
+use crate::{
+    app::config::{ReconnectSettings, Settings, SyslogSettings},
+    types::decode::syslog::CONTENT_TYPE,
+};
+
+use super::{Delivery, LogSource, SourceEvent};
+
+/// Messages received ahead of what's decoded
+const QUEUED_MESSAGES: usize = 1024;
+
+/// Longer messages are cut, longer TCP frames close the connection
+const MAX_MESSAGE: usize = 64 * 1024;
+
+pub struct SyslogSource {
+    id: String,
+    conf: SyslogSettings,
+    messages: Option<Receiver<Vec<u8>>>,
+    /// Receives while connected
+    server: Option<JoinHandle<()>>,
+    next_tag: u64,
+}
+
+impl SyslogSource {
+    pub fn new(conf: &SyslogSettings) -> Self {
+        SyslogSource {
+            id: format!("syslog:{}", conf.bind),
+            conf: conf.clone(),
+            messages: None,
+            server: None,
+            next_tag: 0,
+        }
+    }
+}
+
+#[async_trait]
+impl LogSource for SyslogSource {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    async fn connect(&mut self) -> Result<(), String> {
+        self.close().await;
+        let bind = &self.conf.bind;
+        if !self.conf.udp && !self.conf.tcp {
+            return Err("Neither UDP nor TCP is enabled".to_string());
+        }
+        let udp = match self.conf.udp {
+            true => Some(
+                UdpSocket::bind(bind)
+                    .await
+                    .map_err(|e| format!("Can't listen on UDP {bind}: {e}"))?,
+            ),
+            false => None,
+        };
+        let tcp = match self.conf.tcp {
+            true => Some(
+                TcpListener::bind(bind)
+                    .await
+                    .map_err(|e| format!("Can't listen on TCP {bind}: {e}"))?,
+            ),
+            false => None,
+        };
+        let (tx, rx) = mpsc::channel(QUEUED_MESSAGES);
+        self.server = Some(tokio::spawn(receive(udp, tcp, tx)));
+        self.messages = Some(rx);
+        Ok(())
+    }
+
+    async fn next(&mut self) -> Result<SourceEvent, String> {
+        let Some(messages) = &mut self.messages else {
+            return Err("Not connected".to_string());
+        };
+        let data = messages
+            .recv()
+            .await
+            .ok_or_else(|| "Listener stopped".to_string())?;
+        let tag = self.next_tag;
+        self.next_tag += 1;
+        Ok(SourceEvent::Delivery(Box::new(Delivery {
+            tag,
+            data,
+            content_type: Some(CONTENT_TYPE.to_string()),
+            content_encoding: None,
+            origin: None,
+        })))
+    }
+
+    // Syslog has no acknowledgements
+    async fn ack(&mut self, _tag: u64) -> Result<(), String> {
+        Ok(())
+    }
+
+    async fn nack(&mut self, _tag: u64, _requeue: bool) -> Result<(), String> {
+        Ok(())
+    }
+
+    async fn reject(&mut self, _tag: u64) -> Result<(), String> {
+        Ok(())
+    }
+
+    async fn close(&mut self) {
+        self.messages = None;
+        if let Some(server) = self.server.take() {
+            server.abort();
+        }
+    }
+
+    fn update(&mut self, settings: &Settings) {
+        self.conf = settings.syslog.clone();
+    }
+
+    fn reconnect_settings(&self) -> ReconnectSettings {
+        ReconnectSettings::default()
+    }
+}
+
+/// Connections are dropped along with the task
+async fn receive(udp: Option<UdpSocket>, tcp: Option<TcpListener>, tx: Sender<Vec<u8>>) {
+    let mut connections = JoinSet::new();
+    let mut buf = vec![0; MAX_MESSAGE];
+    loop {
+        tokio::select! {
+            received = async { udp.as_ref()?.recv(&mut buf).await.ok() }, if udp.is_some() => {
+                if let Some(len) = received {
+                    if tx.send(buf[..len].to_vec()).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            accepted = async { tcp.as_ref()?.accept().await.ok() }, if tcp.is_some() => match accepted {
+                Some((stream, _)) => {
+                    connections.spawn(serve(stream, tx.clone()));
+                }
+                // Likely out of file descriptors, give them time to close
+                None => tokio::time::sleep(Duration::from_millis(100)).await,
+            },
+            Some(_) = connections.join_next() => {}
+        }
+    }
+}
+
+async fn serve(stream: TcpStream, tx: Sender<Vec<u8>>) {
+    let mut reader = BufReader::new(stream);
+    while let Ok(Some(frame)) = read_frame(&mut reader).await {
+        let frame = frame.trim_ascii();
+        if !frame.is_empty() && tx.send(frame.to_vec()).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// `None` at the end of the stream
+async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut BufReader<R>,
+) -> std::io::Result<Option<Vec<u8>>> {
+    let invalid = |e: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string());
+    let Some(&first) = reader.fill_buf().await?.first() else {
+        return Ok(None);
+    };
+    let mut frame = Vec::new();
+    if first.is_ascii_digit() {
+        // Octet counting: `LEN SP MSG`
+        (&mut *reader).take(8).read_until(b' ', &mut frame).await?;
+        let len: usize = std::str::from_utf8(frame.trim_ascii())
+            .ok()
+            .and_then(|len| len.parse().ok())
+            .filter(|&len| len <= MAX_MESSAGE)
+            .ok_or_else(|| invalid("Invalid frame length"))?;
+        frame = vec![0; len];
+        reader.read_exact(&mut frame).await?;
+    } else {
+        (&mut *reader)
+            .take(MAX_MESSAGE as u64)
+            .read_until(b'\n', &mut frame)
+            .await?;
+        if frame.len() >= MAX_MESSAGE && frame.last() != Some(&b'\n') {
+            return Err(invalid("Message too long"));
+        }
+    }
+    Ok(Some(frame))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn frames(data: &[u8]) -> (Vec<Vec<u8>>, Option<std::io::Error>) {
+        let mut reader = BufReader::new(data);
+        let mut frames = Vec::new();
+        loop {
+            match read_frame(&mut reader).await {
+                Ok(Some(frame)) => frames.push(frame),
+                Ok(None) => return (frames, None),
+                Err(e) => return (frames, Some(e)),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_octet_counted_frames() {
+        let (read, error) = frames(b"5 <13>a10 <13>b\nc d\n0 ").await;
+        assert_eq!(read, [&b"<13>a"[..], b"<13>b\nc d\n", b""]);
+        assert!(error.is_none());
+    }
+
+    #[tokio::test]
+    async fn reads_newline_framed() {
+        let (read, error) = frames(b"<13>one\n<13>two\r\n<13>last").await;
+        assert_eq!(read, [&b"<13>one\n"[..], b"<13>two\r\n", b"<13>last"]);
+        assert!(error.is_none());
+    }
+
+    #[tokio::test]
+    async fn mixes_framings() {
+        let (read, _) = frames(b"<13>plain\n4 <13>").await;
+        assert_eq!(read, [&b"<13>plain\n"[..], b"<13>"]);
+    }
+
+    #[tokio::test]
+    async fn refuses_invalid_frames() {
+        let too_long = format!("{} ", MAX_MESSAGE + 1);
+        for data in [&b"12x <13>a"[..], b"123456789 ", too_long.as_bytes()] {
+            let (read, error) = frames(data).await;
+            assert!(read.is_empty());
+            assert_eq!(
+                error.map(|e| e.kind()),
+                Some(std::io::ErrorKind::InvalidData)
+            );
+        }
+
+        let (_, error) = frames(b"10 <13>short").await;
+        assert_eq!(
+            error.map(|e| e.kind()),
+            Some(std::io::ErrorKind::UnexpectedEof)
+        );
+
+        let long = vec![b'a'; MAX_MESSAGE + 1];
+        let (read, error) = frames(&long).await;
+        assert!(read.is_empty());
+        assert_eq!(
+            error.map(|e| e.kind()),
+            Some(std::io::ErrorKind::InvalidData)
+        );
+    }
+}
@@ -0,0 +1,315 @@
+use std::{io, path::Path, sync::Arc, time::Duration};
+
+use lapin::{
+    tcp::{HandshakeResult, RustlsConnector},
+    uri::AMQPUri,
+    TcpStream,
+};
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::WebPkiSupportedAlgorithms,
+    pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+    ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+
+use crate::app::config::TlsSettings;
+
+/// Builds rustls connector trusting system roots plus configured CA bundle,
+/// optionally presenting a client certificate.
+pub fn connector(settings: &TlsSettings) -> io::Result<RustlsConnector> {
+    let builder = if settings.skip_verify {
+        let provider = rustls::crypto::ring::default_provider();
+        ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoVerification(
+                provider.signature_verification_algorithms,
+            )))
+    } else {
+        ClientConfig::builder().with_root_certificates(root_store(settings)?)
+    };
+
+    let config = match (&settings.client_cert, &settings.client_key) {
+        (Some(cert), Some(key)) => builder
+            .with_client_auth_cert(read_certs(cert)?, read_key(key)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        (None, None) => builder.with_no_client_auth(),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Both client certificate and key must be set",
+            ))
+        }
+    };
+    Ok(config.into())
+}
+
+/// Same as lapin does for amqps, but with our own connector
+#[allow(clippy::result_large_err)] // HandshakeResult comes from lapin
+pub fn connect(uri: &AMQPUri, connector: &RustlsConnector) -> HandshakeResult {
+    let addr = format!("{}:{}", uri.authority.host, uri.authority.port);
+    let stream = match uri.query.connection_timeout {
+        Some(timeout) => TcpStream::connect_timeout(addr, Duration::from_millis(timeout)),
+        None => TcpStream::connect(addr),
+    }?;
+    let stream = stream.into_rustls(connector, &uri.authority.host)?;
+    stream.set_nonblocking(true)?;
+    Ok(stream)
+}
+
+fn root_store(settings: &TlsSettings) -> io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    match rustls_native_certs::load_native_certs() {
+        // Some system certificates may be unparsable, that's not our problem
+        Ok(certs) => {
+            roots.add_parsable_certificates(certs);
+        }
+        // The given bundle may be all the broker needs
+        Err(e) if settings.ca_file.is_some() => {
+            println!("Failed to load system certificates: {e}");
+        }
+        Err(e) => return Err(e),
+    }
+    if let Some(ca) = &settings.ca_file {
+        for cert in read_certs(ca)? {
+            roots
+                .add(cert)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        }
+    }
+    Ok(roots)
+}
+
+fn read_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let pem = std::fs::read(path)?;
+    rustls_pemfile::certs(&mut pem.as_slice()).collect()
+}
+
+fn read_key(path: &Path) -> io::Result<PrivateKeyDer<'static>> {
+    let pem = std::fs::read(path)?;
+    rustls_pemfile::private_key(&mut pem.as_slice())?.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("No private key found in {}", path.display()),
+        )
+    })
+}
+
+/// Accepts any broker certificate. Signatures are still checked, so the
+/// handshake itself stays sane.
+#[derive(Debug)]
+struct NoVerification(WebPkiSupportedAlgorithms);
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.0)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.0)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_schemes()
+    }
+}
+
+/// Certificates in `testdata/tls` are made with openssl by a throwaway CA
+/// whose key isn't kept, valid for a hundred years:
+///
+/// ```sh
+/// openssl ecparam -name prime256v1 -genkey -noout -out ca.key
+/// openssl req -x509 -new -key ca.key -sha256 -days 36500 \
+///     -subj "/CN=bucklog test CA" -out ca.pem
+/// # server with subjectAltName=DNS:localhost, client with
+/// # extendedKeyUsage=clientAuth in $name.ext
+/// openssl ecparam -name prime256v1 -genkey -noout \
+///     | openssl pkcs8 -topk8 -nocrypt -out $name.key
+/// openssl req -new -key $name.key -subj "/CN=$name" -out $name.csr
+/// openssl x509 -req -in $name.csr -CA ca.pem -CAkey ca.key -CAcreateserial \
+///     -days 36500 -sha256 -extfile $name.ext -out $name.pem
+/// ```
+///
+/// The same files serve a local broker for the ignored test:
+///
+/// ```sh
+/// docker run --rm -p 5671:5671 -v $PWD/testdata/tls:/certs:ro \
+///     -v $PWD/testdata/tls/rabbitmq.conf:/etc/rabbitmq/conf.d/90-tls.conf:ro \
+///     rabbitmq:3
+/// cargo test connects_to_broker -- --ignored
+/// ```
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{Ipv4Addr, TcpListener},
+        path::PathBuf,
+        thread::JoinHandle,
+    };
+
+    use rustls::{server::WebPkiClientVerifier, ServerConfig, ServerConnection};
+
+    use super::*;
+    use crate::{
+        app::config::{RabbitMQSettings, Settings},
+        worker::source,
+    };
+
+    fn testdata(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata/tls")
+            .join(name)
+    }
+
+    fn trusting_ca() -> TlsSettings {
+        TlsSettings {
+            enabled: true,
+            ca_file: Some(testdata("ca.pem")),
+            ..Default::default()
+        }
+    }
+
+    fn with_client_cert() -> TlsSettings {
+        TlsSettings {
+            client_cert: Some(testdata("client.pem")),
+            client_key: Some(testdata("client.key")),
+            ..trusting_ca()
+        }
+    }
+
+    /// Local TLS server for a single handshake, requiring a client
+    /// certificate if asked to. Returns its port and how the handshake went.
+    fn serve(client_auth: bool) -> (u16, JoinHandle<io::Result<()>>) {
+        let builder = if client_auth {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(&testdata("ca.pem")).unwrap() {
+                roots.add(cert).unwrap();
+            }
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+                .build()
+                .unwrap();
+            ServerConfig::builder().with_client_cert_verifier(verifier)
+        } else {
+            ServerConfig::builder().with_no_client_auth()
+        };
+        let config = builder
+            .with_single_cert(
+                read_certs(&testdata("server.pem")).unwrap(),
+                read_key(&testdata("server.key")).unwrap(),
+            )
+            .unwrap();
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = std::thread::spawn(move || {
+            let (mut tcp, _) = listener.accept()?;
+            let mut conn = ServerConnection::new(Arc::new(config)).map_err(io::Error::other)?;
+            while conn.is_handshaking() {
+                conn.complete_io(&mut tcp)?;
+            }
+            Ok(())
+        });
+        (port, handle)
+    }
+
+    fn handshake(settings: &TlsSettings, port: u16) -> Result<(), String> {
+        let connector = connector(settings).map_err(|e| e.to_string())?;
+        let uri: AMQPUri = format!("amqps://localhost:{port}").parse()?;
+        connect(&uri, &connector)
+            .map(|_| ())
+            .map_err(|e| format!("{e:?}"))
+    }
+
+    #[test]
+    fn trusts_configured_ca() {
+        let (port, server) = serve(false);
+        handshake(&trusting_ca(), port).unwrap();
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn rejects_broker_signed_by_unknown_ca() {
+        let (port, _server) = serve(false);
+        let settings = TlsSettings {
+            enabled: true,
+            ..Default::default()
+        };
+        assert!(handshake(&settings, port).is_err());
+    }
+
+    #[test]
+    fn skips_verification_when_asked() {
+        let (port, server) = serve(false);
+        let settings = TlsSettings {
+            enabled: true,
+            skip_verify: true,
+            ..Default::default()
+        };
+        handshake(&settings, port).unwrap();
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn presents_client_certificate() {
+        let (port, server) = serve(true);
+        handshake(&with_client_cert(), port).unwrap();
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn requires_both_client_cert_and_key() {
+        let settings = TlsSettings {
+            client_key: None,
+            ..with_client_cert()
+        };
+        assert!(connector(&settings).is_err());
+    }
+
+    /// Broker at `BUCKLOG_TLS_BROKER` (`localhost:5671` by default) set up
+    /// as above, with the default guest user
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore]
+    async fn connects_to_broker() {
+        let broker =
+            std::env::var("BUCKLOG_TLS_BROKER").unwrap_or_else(|_| "localhost:5671".to_string());
+        let (host, port) = broker.rsplit_once(':').expect("Expected host:port");
+        let mut conf = RabbitMQSettings {
+            enabled: true,
+            host: host.to_string(),
+            port: port.parse().expect("Invalid port"),
+            vhost: "%2f".to_string(),
+            username: "guest".to_string(),
+            password: "guest".to_string(),
+            tls: with_client_cert(),
+            ..Default::default()
+        };
+        conf.topology.queue = "bucklog-tls-test".to_string();
+        conf.topology.declare_queue = true;
+        let settings = Settings {
+            rabbit_mq: conf,
+            ..Default::default()
+        };
+        let mut sources = source::configured(&settings);
+        assert_eq!(sources.len(), 1);
+        sources[0].connect().await.unwrap();
+        sources[0].close().await;
+    }
+}
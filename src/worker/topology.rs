@@ -0,0 +1,160 @@
+use lapin::{
+    options::{ExchangeDeclareOptions, QueueBindOptions, QueueDeclareOptions},
+    types::{AMQPValue, FieldTable},
+    Channel,
+};
+
+use crate::app::config::{ConsumeMode, ExchangeKind, StreamOffset, TopologySettings};
+
+/// Declares (if asked to) and binds everything described by the settings.
+/// Returns the name of the queue which should be consumed.
+pub async fn declare(
+    ch: &Channel,
+    topology: &TopologySettings,
+    stream: bool,
+) -> lapin::Result<String> {
+    if let Some(exchange) = &topology.exchange {
+        if exchange.declare {
+            ch.exchange_declare(
+                &exchange.name,
+                lapin_kind(exchange.kind),
+                ExchangeDeclareOptions {
+                    durable: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await?;
+        }
+    }
+
+    let queue = match topology.mode {
+        ConsumeMode::Shared => {
+            if topology.declare_queue {
+                let mut args = FieldTable::default();
+                if stream {
+                    args.insert(
+                        "x-queue-type".into(),
+                        AMQPValue::LongString("stream".into()),
+                    );
+                }
+                ch.queue_declare(
+                    &topology.queue,
+                    QueueDeclareOptions {
+                        durable: true,
+                        ..Default::default()
+                    },
+                    args,
+                )
+                .await?;
+            }
+            topology.queue.clone()
+        }
+        // Server-named queue which lives as long as our connection
+        ConsumeMode::Tap => ch
+            .queue_declare(
+                "",
+                QueueDeclareOptions {
+                    exclusive: true,
+                    auto_delete: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await?
+            .name()
+            .to_string(),
+    };
+
+    if let Some(exchange) = &topology.exchange {
+        bind(
+            ch,
+            &queue,
+            &exchange.name,
+            exchange.kind,
+            &topology.binding_keys,
+        )
+        .await?;
+    }
+    Ok(queue)
+}
+
+async fn bind(
+    ch: &Channel,
+    queue: &str,
+    exchange: &str,
+    kind: ExchangeKind,
+    binding_keys: &[String],
+) -> lapin::Result<()> {
+    // Without keys bind to everything the exchange routes.
+    // Fanout ignores routing keys, but the queue must be bound anyway
+    if binding_keys.is_empty() {
+        let everything = match kind {
+            ExchangeKind::Topic => "#",
+            _ => "",
+        };
+        return ch
+            .queue_bind(
+                queue,
+                exchange,
+                everything,
+                QueueBindOptions::default(),
+                FieldTable::default(),
+            )
+            .await;
+    }
+    for key in binding_keys {
+        let (routing_key, arguments) = match kind {
+            ExchangeKind::Headers => ("", headers_arguments(key)),
+            _ => (key.as_str(), FieldTable::default()),
+        };
+        ch.queue_bind(
+            queue,
+            exchange,
+            routing_key,
+            QueueBindOptions::default(),
+            arguments,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Turns `app=billing,env=prod` into binding arguments matching all headers
+fn headers_arguments(key: &str) -> FieldTable {
+    let mut args = FieldTable::default();
+    args.insert("x-match".into(), AMQPValue::LongString("all".into()));
+    for pair in key.split(',') {
+        if let Some((k, v)) = pair.split_once('=') {
+            args.insert(
+                k.trim().into(),
+                AMQPValue::LongString(v.trim().to_string().into()),
+            );
+        }
+    }
+    args
+}
+
+/// Consumer arguments telling the broker where to start reading a stream
+pub fn stream_arguments(offset: &StreamOffset) -> FieldTable {
+    let value = match offset.resolve() {
+        StreamOffset::First => AMQPValue::LongString("first".into()),
+        StreamOffset::Last => AMQPValue::LongString("last".into()),
+        StreamOffset::Next => AMQPValue::LongString("next".into()),
+        StreamOffset::Offset(o) => AMQPValue::LongLongInt(o as i64),
+        StreamOffset::Timestamp(ts) => AMQPValue::Timestamp(ts.unix_timestamp().max(0) as u64),
+        StreamOffset::Ago(_) => unreachable!("resolved above"),
+    };
+    let mut args = FieldTable::default();
+    args.insert("x-stream-offset".into(), value);
+    args
+}
+
+fn lapin_kind(kind: ExchangeKind) -> lapin::ExchangeKind {
+    match kind {
+        ExchangeKind::Topic => lapin::ExchangeKind::Topic,
+        ExchangeKind::Fanout => lapin::ExchangeKind::Fanout,
+        ExchangeKind::Headers => lapin::ExchangeKind::Headers,
+        ExchangeKind::Direct => lapin::ExchangeKind::Direct,
+    }
+}